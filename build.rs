@@ -0,0 +1,43 @@
+use std::{path::Path, process::Command};
+
+/// Sets `VERGEN_GIT_SHA` and `VERGEN_BUILD_TIMESTAMP` for `/version`. The sha
+/// can be passed in from the environment, since the Docker build has no `.git`.
+fn main() {
+    println!("cargo:rerun-if-env-changed=VERGEN_GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    // HEAD only names the branch, so a commit moves the ref it points at,
+    // which lives in its own file or, once packed, in `packed-refs`. Watching
+    // a missing file would rerun this on every build, so a packed ref is
+    // watched through the directory its loose file would show up in.
+    if let Some(head_ref) = std::fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+    {
+        let loose = Path::new(".git").join(head_ref);
+        match loose.parent() {
+            Some(dir) if !loose.exists() => println!("cargo:rerun-if-changed={}", dir.display()),
+            _ => println!("cargo:rerun-if-changed={}", loose.display()),
+        }
+    }
+    if Path::new(".git/packed-refs").exists() {
+        println!("cargo:rerun-if-changed=.git/packed-refs");
+    }
+
+    let sha = std::env::var("VERGEN_GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|sha| sha.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=VERGEN_GIT_SHA={sha}");
+
+    let built = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    println!("cargo:rustc-env=VERGEN_BUILD_TIMESTAMP={built}");
+}
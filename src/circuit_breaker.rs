@@ -0,0 +1,240 @@
+use crate::{
+    notifier::{MessageIds, Notifier},
+    rss::Post,
+};
+use async_trait::async_trait;
+use std::{io::Error, sync::Mutex, time::Duration};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Where a [`CircuitBreaker`] stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls go through, and this many have failed in a row.
+    Closed { failures: u32 },
+    /// Calls are turned away until `until`.
+    Open { until: Instant },
+    /// The cooldown is over and one call is out to see whether the API is
+    /// back; others are turned away until it answers.
+    HalfOpen,
+}
+
+/// Stops calling an API that keeps failing: after `threshold` failures in a
+/// row it opens for `cooldown`, then lets a single call through, closing
+/// again if that succeeds and opening for another cooldown if not.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    /// A breaker opening after `threshold` failures in a row, or never with
+    /// a threshold of zero.
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn state(&self) -> BreakerState {
+        *self.state.lock().expect("Breaker lock poisoned")
+    }
+
+    /// Whether calls are going out as normal, rather than being turned away
+    /// or waiting on the probe.
+    pub fn is_closed(&self) -> bool {
+        matches!(
+            *self.state.lock().expect("Breaker lock poisoned"),
+            BreakerState::Closed { .. }
+        )
+    }
+
+    /// Whether a call may go out now. The first call after the cooldown is
+    /// let through as the probe.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().expect("Breaker lock poisoned");
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if Instant::now() >= until => {
+                info!("Cooldown over, letting one call through to Slack");
+                *state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen => false,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().expect("Breaker lock poisoned");
+        if *state == BreakerState::HalfOpen {
+            info!("Slack answered again, closing the circuit");
+        }
+        *state = BreakerState::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().expect("Breaker lock poisoned");
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures.saturating_add(1),
+            BreakerState::HalfOpen => self.threshold,
+            BreakerState::Open { .. } => return,
+        };
+        if self.threshold == 0 || failures < self.threshold {
+            *state = BreakerState::Closed { failures };
+            return;
+        }
+        warn!(
+            failures,
+            cooldown_secs = self.cooldown.as_secs(),
+            "Slack keeps failing, not calling it until the cooldown is over"
+        );
+        *state = BreakerState::Open {
+            until: Instant::now() + self.cooldown,
+        };
+    }
+}
+
+/// A call turned away by an open [`CircuitBreaker`], without anything sent.
+#[derive(Debug, thiserror::Error)]
+#[error("Slack circuit is open; not calling it until the cooldown is over")]
+pub struct CircuitOpen;
+
+impl CircuitOpen {
+    /// Whether `err` is a call the breaker turned away.
+    pub fn caused(err: &Error) -> bool {
+        err.get_ref().is_some_and(|inner| inner.is::<CircuitOpen>())
+    }
+}
+
+/// Sends through `inner` while `breaker` allows it, telling the breaker how
+/// each call went.
+pub struct BreakerNotifier<'a> {
+    inner: &'a dyn Notifier,
+    breaker: &'a CircuitBreaker,
+}
+
+impl<'a> BreakerNotifier<'a> {
+    pub fn new(inner: &'a dyn Notifier, breaker: &'a CircuitBreaker) -> Self {
+        Self { inner, breaker }
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        if self.breaker.allow() {
+            Ok(())
+        } else {
+            Err(Error::other(CircuitOpen))
+        }
+    }
+
+    fn track<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        match &result {
+            Ok(_) => self.breaker.record_success(),
+            Err(_) => self.breaker.record_failure(),
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl Notifier for BreakerNotifier<'_> {
+    async fn post(&self, post: &Post) -> Result<MessageIds, Error> {
+        self.check()?;
+        self.track(self.inner.post(post).await)
+    }
+
+    async fn update(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        self.check()?;
+        self.track(self.inner.update(post, ids).await)
+    }
+
+    async fn reply(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        self.check()?;
+        self.track(self.inner.reply(post, ids).await)
+    }
+
+    async fn post_digest(&self, posts: &[&Post]) -> Result<MessageIds, Error> {
+        self.check()?;
+        self.track(self.inner.post_digest(posts).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BreakerState, CircuitBreaker};
+    use std::time::Duration;
+    use tokio::time::{Instant, advance};
+
+    const COOLDOWN: Duration = Duration::from_secs(60);
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_after_failures_in_a_row() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), BreakerState::Closed { failures: 2 });
+        assert!(breaker.allow());
+
+        breaker.record_failure();
+        assert_eq!(
+            breaker.state(),
+            BreakerState::Open {
+                until: Instant::now() + COOLDOWN
+            }
+        );
+        assert!(!breaker.allow());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lets_one_probe_through_after_the_cooldown() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+
+        advance(COOLDOWN - Duration::from_secs(1)).await;
+        assert!(!breaker.allow());
+        advance(Duration::from_secs(1)).await;
+        assert!(breaker.allow());
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(!breaker.allow());
+
+        breaker.record_success();
+        assert_eq!(breaker.state(), BreakerState::Closed { failures: 0 });
+        assert!(breaker.allow());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_probe_opens_for_another_cooldown() {
+        let breaker = CircuitBreaker::new(2, COOLDOWN);
+        breaker.record_failure();
+        breaker.record_failure();
+        advance(COOLDOWN).await;
+        assert!(breaker.allow());
+
+        breaker.record_failure();
+
+        assert_eq!(
+            breaker.state(),
+            BreakerState::Open {
+                until: Instant::now() + COOLDOWN
+            }
+        );
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(0, COOLDOWN);
+        for _ in 0..10 {
+            breaker.record_failure();
+        }
+        assert!(breaker.allow());
+    }
+}
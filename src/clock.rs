@@ -0,0 +1,30 @@
+use chrono::{DateTime, Utc};
+use std::fmt::Debug;
+
+/// Where the current time comes from, so what depends on it can be tested
+/// at a time of our choosing. Durations are still measured with `Instant`.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The time as the system has it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Always the same time, for tests.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
@@ -0,0 +1,240 @@
+use crate::{
+    config::DiscordConfig,
+    markdown::{map_lines_outside_fences, map_outside_inline_code, regex},
+    notifier::{DEFAULT_CHANNEL, MessageIds, Notifier, single_message},
+    rss::Post,
+};
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{io::Error, sync::OnceLock};
+
+/// Discord's limit on the content of a single message.
+const MESSAGE_CONTENT_LIMIT: usize = 2000;
+
+#[derive(Debug, Serialize)]
+struct WebhookMessage {
+    content: String,
+    allowed_mentions: AllowedMentions,
+}
+
+/// Which mentions in the content Discord may turn into pings.
+#[derive(Debug, Serialize)]
+struct AllowedMentions {
+    parse: Vec<&'static str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookResponse {
+    id: String,
+}
+
+static RE_DEEP_HEADING: OnceLock<Regex> = OnceLock::new();
+static RE_UNDERSCORE_BOLD: OnceLock<Regex> = OnceLock::new();
+
+/// Adapts the CommonMark used in nais.io posts to Discord's markdown, which
+/// only renders three heading levels and reads `__x__` as underline.
+pub(crate) fn format_discord_post(org: &str) -> String {
+    map_lines_outside_fences(org, |line| {
+        if let Some(caps) = regex(&RE_DEEP_HEADING, r"^#{4,6}\s+(.*?)\s*#*$").captures(line) {
+            return format!("**{}**", format_inline(&caps[1]));
+        }
+        format_inline(line)
+    })
+}
+
+fn format_inline(text: &str) -> String {
+    map_outside_inline_code(text, |segment| {
+        regex(&RE_UNDERSCORE_BOLD, r"__(.+?)__")
+            .replace_all(segment, "**$1**")
+            .to_string()
+    })
+}
+
+fn message(post: &Post) -> WebhookMessage {
+    let text = format!(
+        "**[{}](<{}>)**\n{}",
+        post.title,
+        post.link,
+        format_discord_post(&post.content)
+    );
+    let content = if text.chars().count() <= MESSAGE_CONTENT_LIMIT {
+        text
+    } else {
+        let suffix = format!("… [read more](<{}>)", post.link);
+        let keep = MESSAGE_CONTENT_LIMIT.saturating_sub(suffix.chars().count());
+        text.chars().take(keep).chain(suffix.chars()).collect()
+    };
+
+    WebhookMessage {
+        content,
+        allowed_mentions: AllowedMentions { parse: Vec::new() },
+    }
+}
+
+/// Posts to a Discord channel through an incoming webhook.
+#[derive(Debug, Clone)]
+pub struct DiscordNotifier {
+    config: DiscordConfig,
+    client: reqwest::Client,
+}
+
+impl DiscordNotifier {
+    pub fn new(config: DiscordConfig, client: reqwest::Client) -> Self {
+        Self { config, client }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder, post: &Post) -> Result<String, Error> {
+        let response = request
+            .json(&message(post))
+            .send()
+            .await
+            .map_err(|e| Error::other(e.to_string()))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Error::other(format!("Discord answered with {status}")));
+        }
+
+        response
+            .json::<WebhookResponse>()
+            .await
+            .map(|message| message.id)
+            .map_err(|e| Error::other(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    async fn post(&self, post: &Post) -> Result<MessageIds, Error> {
+        // Without `wait` Discord answers 204 and we never learn the message id.
+        let request = self
+            .client
+            .post(&self.config.webhook_url)
+            .query(&[("wait", "true")]);
+        self.send(request, post).await.map(single_message)
+    }
+
+    async fn update(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        let id = ids
+            .get(DEFAULT_CHANNEL)
+            .ok_or_else(|| Error::other("No Discord message id to update"))?;
+        let request = self
+            .client
+            .patch(format!("{}/messages/{id}", self.config.webhook_url));
+        self.send(request, post).await.map(single_message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiscordNotifier, MESSAGE_CONTENT_LIMIT, format_discord_post, message};
+    use crate::{
+        config::DiscordConfig,
+        notifier::{Notifier, single_message},
+        rss::Post,
+    };
+    use axum::{
+        Json, Router,
+        extract::Path,
+        routing::{patch, post},
+    };
+    use chrono::{TimeZone, Utc};
+    use std::sync::{Arc, Mutex};
+
+    fn sample_post() -> Post {
+        Post {
+            title: "Test Post".to_string(),
+            link: "https://nais.io/log#test-post".to_string(),
+            pub_date: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            content: "This is **content** with a [link](https://example.com).".to_string(),
+            categories: Vec::new(),
+            guid: None,
+        }
+    }
+
+    #[test]
+    fn converts_markdown_for_discord() {
+        let cases = [
+            ("# Heading", "# Heading"),
+            ("### Sub heading", "### Sub heading"),
+            ("#### Deep heading", "**Deep heading**"),
+            ("__bold__ text", "**bold** text"),
+            ("**bold** and _italic_", "**bold** and _italic_"),
+            ("- [item](https://nais.io)", "- [item](https://nais.io)"),
+            ("Use `__init__` here", "Use `__init__` here"),
+            (
+                "```\n#### not a heading\n```",
+                "```\n#### not a heading\n```",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(format_discord_post(input), expected, "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn renders_webhook_payload() {
+        let payload = serde_json::to_value(message(&sample_post())).unwrap();
+
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "content": "**[Test Post](<https://nais.io/log#test-post>)**\nThis is **content** with a [link](https://example.com).",
+                "allowed_mentions": { "parse": [] },
+            })
+        );
+    }
+
+    #[test]
+    fn long_post_is_truncated_with_link() {
+        let post = Post {
+            content: "z".repeat(5000),
+            ..sample_post()
+        };
+
+        let content = message(&post).content;
+
+        assert_eq!(content.chars().count(), MESSAGE_CONTENT_LIMIT);
+        assert!(content.ends_with("… [read more](<https://nais.io/log#test-post>)"));
+    }
+
+    #[tokio::test]
+    async fn posts_and_updates_through_webhook() {
+        let patched = Arc::new(Mutex::new(Vec::new()));
+        let recorder = patched.clone();
+        let app = Router::new()
+            .route(
+                "/webhook",
+                post(|| async { Json(serde_json::json!({ "id": "1234" })) }),
+            )
+            .route(
+                "/webhook/messages/{id}",
+                patch(move |Path(id): Path<String>| {
+                    let recorder = recorder.clone();
+                    async move {
+                        recorder.lock().unwrap().push(id.clone());
+                        Json(serde_json::json!({ "id": id }))
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let notifier: Arc<dyn Notifier> = Arc::new(DiscordNotifier::new(
+            DiscordConfig {
+                webhook_url: format!("http://{addr}/webhook"),
+            },
+            reqwest::Client::new(),
+        ));
+
+        let ids = notifier.post(&sample_post()).await.unwrap();
+        assert_eq!(ids, single_message("1234".to_string()));
+
+        let updated = notifier.update(&sample_post(), &ids).await.unwrap();
+        assert_eq!(updated, ids);
+        assert_eq!(*patched.lock().unwrap(), vec!["1234"]);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::slack::SlackError;
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use redis::RedisError;
+use serde::Serialize;
+
+/// An error answered with a JSON body like
+/// `{"error": "Valkey not available", "code": "valkey_unavailable"}`, so
+/// tools can tell failures apart by `code` without parsing the message.
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    #[serde(skip)]
+    status: StatusCode,
+    error: String,
+    code: &'static str,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, error: impl Into<String>) -> Self {
+        Self {
+            status,
+            error: error.into(),
+            code,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self)).into_response()
+    }
+}
+
+/// What can stop a reconcile or an endpoint built on it, each answered
+/// with its own status. The body stays generic, as the details are for the
+/// logs rather than whoever called us.
+#[derive(Debug, thiserror::Error)]
+pub enum ReconcileError {
+    #[error("Failed getting the feed: {0}")]
+    FeedFetch(#[from] reqwest::Error),
+    #[error("Failed to parse RSS feed: {0}")]
+    FeedParse(#[from] quick_xml::DeError),
+    #[error("Valkey failed: {0}")]
+    Redis(#[from] RedisError),
+    #[error("Slack failed: {0}")]
+    Slack(#[from] SlackError),
+}
+
+impl ReconcileError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ReconcileError::FeedFetch(err) if err.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+            ReconcileError::FeedFetch(_) | ReconcileError::FeedParse(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ReconcileError::Redis(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ReconcileError::Slack(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl IntoResponse for ReconcileError {
+    fn into_response(self) -> Response {
+        let (code, message) = match &self {
+            ReconcileError::FeedFetch(err) if err.is_timeout() => {
+                ("feed_timeout", "HTTP client error")
+            }
+            ReconcileError::FeedFetch(_) => ("feed_fetch_failed", "HTTP client error"),
+            ReconcileError::FeedParse(_) => ("feed_parse_failed", "Failed to parse RSS feed"),
+            ReconcileError::Redis(_) => ("valkey_unavailable", "Valkey not available"),
+            ReconcileError::Slack(_) => ("slack_unavailable", "Slack not available"),
+        };
+        ApiError::new(self.status(), code, message).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiError, ReconcileError};
+    use crate::slack::SlackError;
+    use axum::{
+        body::to_bytes,
+        http::{StatusCode, header},
+        response::IntoResponse,
+    };
+    use redis::{ErrorKind, RedisError};
+
+    fn status(err: impl Into<ReconcileError>) -> StatusCode {
+        err.into().into_response().status()
+    }
+
+    #[test]
+    fn failed_fetch_is_an_internal_error() {
+        let err = reqwest::Client::new().get("not a url").build().unwrap_err();
+
+        assert_eq!(status(err), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn timed_out_fetch_is_a_gateway_timeout() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Accepts the connection but never answers.
+        tokio::spawn(async move {
+            let _held = listener.accept().await;
+            std::future::pending::<()>().await;
+        });
+
+        let err = reqwest::Client::new()
+            .get(format!("http://{addr}/rss.xml"))
+            .timeout(std::time::Duration::from_millis(50))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert_eq!(status(err), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[test]
+    fn unparseable_feed_is_an_internal_error() {
+        let err = quick_xml::de::from_str::<serde::de::IgnoredAny>("<item><title>").unwrap_err();
+
+        assert_eq!(status(err), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn valkey_failure_is_service_unavailable() {
+        let err = RedisError::from((ErrorKind::ClusterDown, "cluster is down"));
+
+        assert_eq!(status(err), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn errors_are_answered_as_json() {
+        let response =
+            ApiError::new(StatusCode::NOT_FOUND, "post_not_found", "No such post").into_response();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "error": "No such post", "code": "post_not_found" })
+        );
+    }
+
+    #[tokio::test]
+    async fn reconcile_errors_carry_their_code() {
+        let err = RedisError::from((ErrorKind::ClusterDown, "cluster is down"));
+
+        let response = ReconcileError::from(err).into_response();
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "error": "Valkey not available", "code": "valkey_unavailable" })
+        );
+    }
+
+    #[test]
+    fn slack_failure_is_a_bad_gateway() {
+        let err = SlackError::Api {
+            code: "channel_not_found".to_string(),
+        };
+
+        assert_eq!(status(err), StatusCode::BAD_GATEWAY);
+    }
+}
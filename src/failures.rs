@@ -0,0 +1,163 @@
+use crate::redis_client::ValkeyClient;
+use redis::RedisResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::{error, warn};
+
+/// Prefix of the keys counting how often a post has failed to go out.
+const FAILURES_PREFIX: &str = "failures:";
+
+/// Prefix of the keys of posts given up on, which reconciles leave alone.
+const DEAD_LETTER_PREFIX: &str = "deadletter:";
+
+/// A post that failed to go out too many times in a row to keep trying.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub key: String,
+    pub attempts: u32,
+    /// What went wrong the last time.
+    pub error: String,
+}
+
+/// Counts another failed attempt at sending the post under `key`, moving it
+/// to the dead letters once it reaches `max_attempts`. Returns whether it did.
+pub async fn record_failure(
+    store: &mut dyn ValkeyClient,
+    key: &str,
+    error: &str,
+    max_attempts: u32,
+) -> RedisResult<bool> {
+    let counter = format!("{FAILURES_PREFIX}{key}");
+    // Reconciles hold the lock, so nobody else counts at the same time.
+    let attempts = store
+        .get(&counter)
+        .await?
+        .and_then(|raw| raw.parse::<u32>().ok())
+        .unwrap_or(0)
+        + 1;
+    if attempts < max_attempts {
+        store.set(&counter, &attempts.to_string()).await?;
+        return Ok(false);
+    }
+
+    let letter = DeadLetter {
+        key: key.to_string(),
+        attempts,
+        error: error.to_string(),
+    };
+    let raw = serde_json::to_string(&letter).expect("Dead letter should serialize");
+    store
+        .set(&format!("{DEAD_LETTER_PREFIX}{key}"), &raw)
+        .await?;
+    store.del(&counter).await?;
+    warn!(
+        key,
+        attempts, "Post keeps failing, moving it to the dead letters"
+    );
+    Ok(true)
+}
+
+/// Forgets earlier failures of a post that has now gone out.
+pub async fn clear_failures(store: &mut dyn ValkeyClient, key: &str) {
+    if let Err(err) = store.del(&format!("{FAILURES_PREFIX}{key}")).await {
+        error!(key, error = %err, "Failed clearing failure count in Redis");
+    }
+}
+
+/// Keys of the posts in the dead letters.
+pub async fn dead_letter_keys(store: &mut dyn ValkeyClient) -> RedisResult<HashSet<String>> {
+    Ok(store
+        .scan_keys(&format!("{DEAD_LETTER_PREFIX}*"))
+        .await?
+        .into_iter()
+        .filter_map(|key| key.strip_prefix(DEAD_LETTER_PREFIX).map(str::to_string))
+        .collect())
+}
+
+/// Lists the dead letters, ordered by key.
+pub async fn dead_letters(store: &mut dyn ValkeyClient) -> RedisResult<Vec<DeadLetter>> {
+    let mut keys = store.scan_keys(&format!("{DEAD_LETTER_PREFIX}*")).await?;
+    keys.sort();
+    let values = store.get_many(&keys).await?;
+
+    Ok(values
+        .into_iter()
+        .flatten()
+        .filter_map(|raw| serde_json::from_str(&raw).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeadLetter, clear_failures, dead_letter_keys, dead_letters, record_failure};
+    use crate::redis_client::{InMemoryValkey, ValkeyClient};
+
+    #[tokio::test]
+    async fn counts_failures_per_post() {
+        let mut store = InMemoryValkey::new();
+
+        assert!(
+            !record_failure(&mut store, "some-post", "boom", 3)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !record_failure(&mut store, "some-post", "boom", 3)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !record_failure(&mut store, "other-post", "boom", 3)
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(
+            store.get("failures:some-post").await.unwrap().as_deref(),
+            Some("2")
+        );
+        assert_eq!(
+            store.get("failures:other-post").await.unwrap().as_deref(),
+            Some("1")
+        );
+
+        clear_failures(&mut store, "some-post").await;
+        assert_eq!(store.get("failures:some-post").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn dead_letters_post_at_the_threshold() {
+        let mut store = InMemoryValkey::new();
+
+        for _ in 0..2 {
+            assert!(
+                !record_failure(&mut store, "some-post", "boom", 3)
+                    .await
+                    .unwrap()
+            );
+        }
+        assert!(dead_letter_keys(&mut store).await.unwrap().is_empty());
+
+        assert!(
+            record_failure(&mut store, "some-post", "channel_not_found", 3)
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(store.get("failures:some-post").await.unwrap(), None);
+        assert!(
+            dead_letter_keys(&mut store)
+                .await
+                .unwrap()
+                .contains("some-post")
+        );
+        assert_eq!(
+            dead_letters(&mut store).await.unwrap(),
+            vec![DeadLetter {
+                key: "some-post".to_string(),
+                attempts: 3,
+                error: "channel_not_found".to_string(),
+            }]
+        );
+    }
+}
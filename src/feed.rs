@@ -0,0 +1,244 @@
+use crate::redis_client::ValkeyClient;
+use reqwest::{
+    StatusCode,
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Key the validators of the last handled feed are kept under, next to the
+/// archive entries.
+const VALIDATORS_KEY: &str = "feed:validators";
+
+/// Cache validators from a feed response, sent back on the next fetch so the
+/// server can answer `304 Not Modified`.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct FeedValidators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+pub enum FetchedFeed {
+    NotModified,
+    Modified {
+        body: String,
+        validators: FeedValidators,
+    },
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    Request(reqwest::Error),
+    Status(StatusCode),
+    Body(reqwest::Error),
+    /// The body was longer than `MAX_FEED_BYTES`, so we stopped reading it.
+    TooLarge {
+        limit: usize,
+    },
+    /// The feed didn't finish answering within `FEED_TIMEOUT_SECONDS`.
+    Timeout {
+        after: Duration,
+    },
+}
+
+async fn load_validators(store: &mut dyn ValkeyClient) -> FeedValidators {
+    match store.get(VALIDATORS_KEY).await {
+        Ok(Some(raw)) => serde_json::from_str(&raw).unwrap_or_else(|err| {
+            error!(error = %err, "Invalid feed validators in Redis, fetching unconditionally");
+            FeedValidators::default()
+        }),
+        Ok(None) => FeedValidators::default(),
+        Err(err) => {
+            error!(error = %err, "Failed getting feed validators from Redis");
+            FeedValidators::default()
+        }
+    }
+}
+
+/// Remembers the validators of a feed we're done with. Only call this once
+/// every post in it was handled, or a failed post won't be retried until the
+/// feed changes again.
+pub async fn save_validators(store: &mut dyn ValkeyClient, validators: &FeedValidators) {
+    if validators == &FeedValidators::default() {
+        return;
+    }
+    let raw = match serde_json::to_string(validators) {
+        Ok(raw) => raw,
+        Err(err) => {
+            error!(error = %err, "Failed serializing feed validators");
+            return;
+        }
+    };
+    if let Err(err) = store.set(VALIDATORS_KEY, &raw).await {
+        error!(error = %err, "Failed saving feed validators to Redis");
+    }
+}
+
+/// Fetches the feed, asking the server to skip the body when it hasn't
+/// changed since the validators we last saved. Reading the body stops once
+/// it passes `max_bytes`, and the whole fetch once it takes `timeout`.
+pub async fn fetch_feed(
+    client: &reqwest::Client,
+    url: &str,
+    store: &mut dyn ValkeyClient,
+    max_bytes: usize,
+    timeout: Duration,
+) -> Result<FetchedFeed, FetchError> {
+    let timed_out = |err: reqwest::Error, wrap: fn(reqwest::Error) -> FetchError| {
+        if err.is_timeout() {
+            FetchError::Timeout { after: timeout }
+        } else {
+            wrap(err)
+        }
+    };
+    let previous = load_validators(store).await;
+
+    let mut request = client.get(url).timeout(timeout);
+    if let Some(etag) = &previous.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &previous.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let mut response = request
+        .send()
+        .await
+        .map_err(|err| timed_out(err, FetchError::Request))?;
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        info!("Feed not modified since last reconcile");
+        return Ok(FetchedFeed::NotModified);
+    }
+    if !status.is_success() {
+        return Err(FetchError::Status(status));
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let validators = FeedValidators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    if response
+        .content_length()
+        .is_some_and(|len| len > max_bytes as u64)
+    {
+        return Err(FetchError::TooLarge { limit: max_bytes });
+    }
+    // Read in chunks rather than all at once, since a missing or lying
+    // Content-Length would otherwise let the body grow without bound.
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| timed_out(err, FetchError::Body))?
+    {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(FetchError::TooLarge { limit: max_bytes });
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = String::from_utf8_lossy(&body).into_owned();
+
+    Ok(FetchedFeed::Modified { body, validators })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FetchError, FetchedFeed, fetch_feed};
+    use crate::redis_client::InMemoryValkey;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Answers one request with a chunked body of `chunks` chunks of 1 KiB,
+    /// so the client can't tell the size up front.
+    async fn streaming_feed(chunks: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = socket.read(&mut request).await;
+            let _ = socket
+                .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n")
+                .await;
+            let chunk = format!("400\r\n{}\r\n", "x".repeat(1024));
+            for _ in 0..chunks {
+                if socket.write_all(chunk.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            let _ = socket.write_all(b"0\r\n\r\n").await;
+        });
+        format!("http://{addr}/rss.xml")
+    }
+
+    #[tokio::test]
+    async fn stops_reading_body_past_the_limit() {
+        let url = streaming_feed(10_000).await;
+
+        let result = fetch_feed(
+            &reqwest::Client::new(),
+            &url,
+            &mut InMemoryValkey::new(),
+            16 * 1024,
+            Duration::from_secs(10),
+        )
+        .await;
+
+        assert!(matches!(result, Err(FetchError::TooLarge { limit }) if limit == 16 * 1024));
+    }
+
+    #[tokio::test]
+    async fn reads_body_within_the_limit() {
+        let url = streaming_feed(4).await;
+
+        let result = fetch_feed(
+            &reqwest::Client::new(),
+            &url,
+            &mut InMemoryValkey::new(),
+            16 * 1024,
+            Duration::from_secs(10),
+        )
+        .await;
+
+        let Ok(FetchedFeed::Modified { body, .. }) = result else {
+            panic!("expected the whole body");
+        };
+        assert_eq!(body.len(), 4 * 1024);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_slow_feed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = socket.read(&mut request).await;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+        let timeout = Duration::from_millis(100);
+
+        let result = fetch_feed(
+            &reqwest::Client::new(),
+            &format!("http://{addr}/rss.xml"),
+            &mut InMemoryValkey::new(),
+            16 * 1024,
+            timeout,
+        )
+        .await;
+
+        assert!(matches!(result, Err(FetchError::Timeout { after }) if after == timeout));
+    }
+}
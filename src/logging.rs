@@ -0,0 +1,238 @@
+use crate::otlp::OtlpLayer;
+use color_eyre::eyre::{Result, eyre};
+use reqwest::Url;
+use std::str::FromStr;
+use tracing::{Subscriber, level_filters::LevelFilter, warn};
+use tracing_subscriber::{
+    EnvFilter, Layer, fmt, layer::SubscriberExt, registry, util::SubscriberInitExt,
+};
+
+/// How log lines are written, picked with `LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One JSON object per line, as NAIS collects them.
+    #[default]
+    Json,
+    /// Multi-line, human readable output for local development.
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "pretty" => Ok(Self::Pretty),
+            other => Err(eyre!(
+                "Invalid LOG_FORMAT {other:?}; expected \"json\" or \"pretty\""
+            )),
+        }
+    }
+}
+
+pub fn log_format_from_env() -> Result<LogFormat> {
+    match std::env::var("LOG_FORMAT") {
+        Ok(format) => format.parse(),
+        Err(_) => Ok(LogFormat::default()),
+    }
+}
+
+/// The level to log at from `LOG_LEVEL`, falling back to info. An invalid
+/// level shouldn't keep the app from starting, so it comes back as a warning
+/// to log once logging is up.
+fn parse_level(raw: Option<&str>) -> (LevelFilter, Option<String>) {
+    match raw.map(str::trim).filter(|raw| !raw.is_empty()) {
+        None => (LevelFilter::INFO, None),
+        Some(raw) => match raw.parse::<LevelFilter>() {
+            Ok(level) => (level, None),
+            Err(_) => (
+                LevelFilter::INFO,
+                Some(format!("Invalid LOG_LEVEL {raw:?}, logging at info")),
+            ),
+        },
+    }
+}
+
+/// Where to export traces from `OTEL_EXPORTER_OTLP_ENDPOINT`, if anywhere.
+/// Like an invalid level, an invalid endpoint comes back as a warning rather
+/// than keeping the app from starting.
+fn parse_otlp_endpoint(raw: Option<&str>) -> (Option<Url>, Option<String>) {
+    match raw.map(str::trim).filter(|raw| !raw.is_empty()) {
+        None => (None, None),
+        Some(raw) => match Url::parse(raw) {
+            Ok(endpoint) => (Some(endpoint), None),
+            Err(_) => (
+                None,
+                Some(format!(
+                    "Invalid OTEL_EXPORTER_OTLP_ENDPOINT {raw:?}, not exporting traces"
+                )),
+            ),
+        },
+    }
+}
+
+/// Logs in `format` at `level`, and also exports spans to `otlp_endpoint`
+/// when there is one.
+fn subscriber(
+    format: LogFormat,
+    level: LevelFilter,
+    otlp_endpoint: Option<&Url>,
+) -> impl Subscriber + Send + Sync {
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    let log = match format {
+        LogFormat::Json => fmt::layer().json().boxed(),
+        LogFormat::Pretty => fmt::layer().pretty().boxed(),
+    };
+    let service_name = std::env::var("OTEL_SERVICE_NAME")
+        .ok()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string());
+    registry()
+        .with(filter)
+        .with(log)
+        .with(otlp_endpoint.map(|endpoint| OtlpLayer::new(endpoint, &service_name)))
+}
+
+/// Sets up logging in `format`. `RUST_LOG` takes precedence over `LOG_LEVEL`
+/// when set, for per-module directives. With `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// set, spans are exported as traces too, which needs a Tokio runtime.
+pub fn init(format: LogFormat) {
+    let (level, level_warning) = parse_level(std::env::var("LOG_LEVEL").ok().as_deref());
+    let (otlp_endpoint, otlp_warning) =
+        parse_otlp_endpoint(std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().as_deref());
+
+    subscriber(format, level, otlp_endpoint.as_ref()).init();
+
+    for warning in [level_warning, otlp_warning].into_iter().flatten() {
+        warn!("{warning}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogFormat, parse_level, parse_otlp_endpoint, subscriber};
+    use axum::{Json, Router, routing::post};
+    use reqwest::Url;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tracing::{info_span, level_filters::LevelFilter};
+
+    #[test]
+    fn parses_log_levels() {
+        assert_eq!(parse_level(None), (LevelFilter::INFO, None));
+        assert_eq!(parse_level(Some("debug")), (LevelFilter::DEBUG, None));
+        assert_eq!(parse_level(Some("WARN")), (LevelFilter::WARN, None));
+    }
+
+    #[test]
+    fn invalid_level_falls_back_to_info_with_a_warning() {
+        let (level, warning) = parse_level(Some("chatty"));
+
+        assert_eq!(level, LevelFilter::INFO);
+        assert_eq!(
+            warning.as_deref(),
+            Some("Invalid LOG_LEVEL \"chatty\", logging at info")
+        );
+    }
+
+    #[test]
+    fn parses_log_formats() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("Pretty".parse::<LogFormat>().unwrap(), LogFormat::Pretty);
+        assert!("plain".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn parses_otlp_endpoints() {
+        assert_eq!(parse_otlp_endpoint(None), (None, None));
+        assert_eq!(parse_otlp_endpoint(Some(" ")), (None, None));
+        assert_eq!(
+            parse_otlp_endpoint(Some("http://collector:4318")),
+            (Some(Url::parse("http://collector:4318").unwrap()), None)
+        );
+        assert_eq!(
+            parse_otlp_endpoint(Some("collector")),
+            (
+                None,
+                Some(
+                    "Invalid OTEL_EXPORTER_OTLP_ENDPOINT \"collector\", not exporting traces"
+                        .to_string()
+                )
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn spans_are_exported_only_with_an_endpoint() {
+        let exported = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+        let received = exported.clone();
+        let app = Router::new().route(
+            "/v1/traces",
+            post(move |Json(body): Json<serde_json::Value>| async move {
+                received.lock().unwrap().push(body);
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let endpoint = Url::parse(&format!("http://{addr}")).unwrap();
+        let trace = || {
+            let _feed = info_span!("feed", feed_title = "NAIS Log").entered();
+            let post = info_span!("post", key = "test-post", action = tracing::field::Empty);
+            post.record("action", "new");
+        };
+
+        tracing::subscriber::with_default(
+            subscriber(LogFormat::Json, LevelFilter::INFO, None),
+            trace,
+        );
+        tracing::subscriber::with_default(
+            subscriber(LogFormat::Json, LevelFilter::INFO, Some(&endpoint)),
+            trace,
+        );
+
+        let spans = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let spans: Vec<serde_json::Value> = exported
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .flat_map(|body| {
+                        body["resourceSpans"][0]["scopeSpans"][0]["spans"]
+                            .as_array()
+                            .cloned()
+                            .unwrap_or_default()
+                    })
+                    .collect();
+                if spans.len() >= 2 {
+                    break spans;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(spans.len(), 2);
+        let (post, feed) = (&spans[0], &spans[1]);
+        assert_eq!(
+            (post["name"].as_str(), feed["name"].as_str()),
+            (Some("post"), Some("feed"))
+        );
+        assert_eq!(post["traceId"], feed["traceId"]);
+        assert_eq!(post["parentSpanId"], feed["spanId"]);
+        assert!(feed.get("parentSpanId").is_none());
+        assert_eq!(
+            post["attributes"],
+            serde_json::json!([
+                { "key": "key", "value": { "stringValue": "test-post" } },
+                { "key": "action", "value": { "stringValue": "new" } },
+            ])
+        );
+    }
+}
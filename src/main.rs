@@ -1,33 +1,100 @@
 extern crate redis;
 
+mod circuit_breaker;
+mod clock;
 mod config;
+mod discord;
+mod error;
+mod failures;
+mod feed;
+mod logging;
+mod markdown;
+mod metrics;
+mod notifier;
+mod otlp;
+mod rate_limit;
 mod redis_client;
+mod request_id;
 mod rss;
+mod scheduler;
 mod slack;
+mod slack_signature;
+mod slack_webhook;
+mod teams;
 
 use axum::{
-    Router,
-    extract::State,
+    Json, Router,
+    body::Bytes,
+    extract::{Path, State},
     http,
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
 };
 use color_eyre::eyre;
-use rss::FeedError;
-use tracing::{error, info, instrument};
-use tracing_subscriber::{EnvFilter, fmt, util::SubscriberInitExt};
+use config::FeedSource;
+use error::{ApiError, ReconcileError};
+use feed::{FetchError, FetchedFeed};
+use redis_client::{InMemoryValkey, PrefixedValkey, Store, ValkeyClient};
+use rss::{FeedError, ReconcilePreview, ReconcileSummary};
+use serde::Serialize;
+use slack::SlackAuth;
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{Instrument, error, info, info_span, instrument, warn};
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
     let app_config = config::AppConfig::from_env()?;
+    let http_client = config::http_config_from_env()?.client()?;
+    let reconcile_interval = config::reconcile_interval_from_env()?;
+    let valkey_connect_attempts = config::valkey_connect_attempts_from_env()?;
+    let feed_url = config::feed_url_from_env()?;
+    let feed_sources = config::feed_sources_from_env(&app_config)?;
+    let post_age = config::post_age_filter_from_env()?;
+    let categories = config::category_filter_from_env();
+    let link_hosts = config::link_host_filter_from_env(
+        std::iter::once(&feed_url).chain(feed_sources.iter().map(|source| &source.url)),
+    );
+    let max_new_posts = config::max_posts_per_reconcile_from_env()?;
+    let announce_order = config::announce_order_from_env()?;
+    let post_delay = config::post_delay_from_env(&app_config)?;
+    let max_feed_bytes = config::max_feed_bytes_from_env()?;
+    let feed_timeout = config::feed_timeout_from_env()?;
+    let quiet_hours = config::quiet_hours_from_env()?;
+    let allow_pushed_feed = std::env::var("ALLOW_PUSHED_FEED").is_ok();
+    let expected_feed_title = std::env::var("EXPECTED_FEED_TITLE")
+        .ok()
+        .filter(|title| !title.trim().is_empty());
+    let admin_token = config::secret_from_env("ADMIN_TOKEN")?.filter(|token| !token.is_empty());
+    let reset_allowed = config::reset_allowed_from_env();
+    let slack_healthcheck = std::env::var("SLACK_HEALTHCHECK").is_ok();
+    let max_consecutive_failures = config::max_consecutive_failures_from_env()?;
+    let key_prefix = config::redis_key_prefix_from_env()?;
+    let slack_signing_secret =
+        config::secret_from_env("SLACK_SIGNING_SECRET")?.filter(|secret| !secret.is_empty());
+    let log_format = logging::log_format_from_env()?;
 
-    fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .json()
-        .finish()
-        .init();
+    logging::init(log_format);
 
-    let state = config::AppState::new(app_config);
+    let state = config::AppState::with_http_client(app_config, http_client)
+        .with_key_prefix(key_prefix)
+        .with_feed_url(feed_url)
+        .with_feed_sources(feed_sources)
+        .with_post_age(post_age)
+        .with_categories(categories)
+        .with_link_hosts(link_hosts)
+        .with_max_new_posts(max_new_posts)
+        .with_announce_order(announce_order)
+        .with_post_delay(post_delay)
+        .with_max_feed_bytes(max_feed_bytes)
+        .with_feed_timeout(feed_timeout)
+        .with_quiet_hours(quiet_hours)
+        .with_allow_pushed_feed(allow_pushed_feed)
+        .with_expected_feed_title(expected_feed_title)
+        .with_admin_token(admin_token)
+        .with_reset_allowed(reset_allowed)
+        .with_slack_signing_secret(slack_signing_secret)
+        .with_slack_healthcheck(slack_healthcheck)
+        .with_max_consecutive_failures(max_consecutive_failures);
 
     info!("Good morning, Nais!");
 
@@ -35,116 +102,2192 @@ async fn main() -> eyre::Result<()> {
         info!("Running in DRY_RUN mode: Slack and Redis are disabled");
     }
 
-    let app = Router::new()
+    // Starting anyway keeps the pod around to recover; until Valkey answers
+    // it isn't ready and every reconcile is refused rather than run without
+    // knowing what was announced.
+    if let Some(mut store) = state.valkey.as_ref().map(Store::client)
+        && !redis_client::wait_until_reachable(store.as_mut(), valkey_connect_attempts).await
+    {
+        error!("Starting without Valkey, not ready until it can be reached");
+    }
+
+    if let Some(period) = reconcile_interval {
+        info!(
+            interval_seconds = period.as_secs(),
+            "Reconciling on a timer alongside /reconcile"
+        );
+        let state = state.clone();
+        tokio::spawn(scheduler::run_every(period, move || {
+            let state = state.clone();
+            async move {
+                let response = run_reconcile(&state).await;
+                if !response.status().is_success() {
+                    error!(status = %response.status(), "Scheduled reconcile failed");
+                }
+            }
+        }));
+    }
+
+    let app = build_app(state.clone());
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+    serve(listener, app, shutdown_signal())
+        .await
+        .map_err(eyre::Error::msg)?;
+
+    // A scheduled reconcile isn't a request, so the server didn't wait for it.
+    // Holding the lock until we return also keeps a new one from starting.
+    let _drained = state.reconcile_lock.lock().await;
+    info!("Shut down cleanly");
+    Ok(())
+}
+
+/// Every route the app serves, answering with `state`.
+fn build_app(state: config::AppState) -> Router {
+    Router::new()
         .route("/reconcile", post(reconcile))
-        .route("/internal/health", get(healthz))
+        .route("/reconcile/dry", post(reconcile_dry))
+        .route("/posts", get(posts))
+        .route("/posts/{key}", delete(delete_post))
+        .route("/posts/{key}/replay", post(replay))
+        .route("/deadletter", get(deadletter))
+        .route("/admin/reset", post(admin_reset))
+        .route(
+            "/slack/command",
+            post(slack_command).layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                slack_signature::verify,
+            )),
+        )
+        .route("/internal/health", get(health))
         .route("/internal/ready", get(ready))
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(metrics))
+        .route("/version", get(version))
         .route(
             "/",
             get(|| async { "Hello, check out https://nais.io/log/!" }),
         )
-        .with_state(state);
+        .layer(axum::middleware::from_fn(request_id::propagate))
+        .with_state(state)
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
-    axum::serve(listener, app).await.map_err(eyre::Error::msg)
+/// Serves `app` until `shutdown` completes, then stops accepting connections
+/// and waits for in-flight requests to finish.
+async fn serve(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
 }
 
-async fn healthz() -> &'static str {
+/// Completes on SIGTERM, which NAIS sends during rollouts, or on Ctrl-C.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("Failed listening for Ctrl-C: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(err) => {
+                error!("Failed listening for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+    info!("Shutdown signal received, finishing in-flight requests");
+}
+
+async fn health() -> &'static str {
     "ok"
 }
 
-async fn ready(State(state): State<config::AppState>) -> impl IntoResponse {
+/// Which build is running, so it can be matched against a rollout.
+#[derive(Debug, Serialize, PartialEq)]
+struct VersionInfo {
+    version: &'static str,
+    git_sha: &'static str,
+    build_timestamp: &'static str,
+}
+
+async fn version() -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("VERGEN_GIT_SHA"),
+        build_timestamp: env!("VERGEN_BUILD_TIMESTAMP"),
+    })
+}
+
+async fn metrics(State(state): State<config::AppState>) -> impl IntoResponse {
+    (
+        [(http::header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        state.metrics.render(),
+    )
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct RedisHealth {
+    redis: &'static str,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct Health {
+    #[serde(flatten)]
+    redis: RedisHealth,
+    /// Left out unless `SLACK_HEALTHCHECK` is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slack: Option<SlackAuth>,
+    /// Reconciles failed in a row; left out unless `MAX_CONSECUTIVE_FAILURES`
+    /// is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    consecutive_failures: Option<u32>,
+}
+
+async fn healthz(State(state): State<config::AppState>) -> (http::StatusCode, Json<Health>) {
+    let (mut status, Json(redis)) = if state.config.is_dry_run() {
+        (http::StatusCode::OK, Json(RedisHealth { redis: "ok" }))
+    } else {
+        let mut store = state.valkey.as_ref().map(Store::client);
+        redis_health(store.as_mut().map(|s| s.as_mut() as &mut dyn ValkeyClient)).await
+    };
+
+    let slack = match &state.slack_auth {
+        Some(check) => Some(check.status().await),
+        None => None,
+    };
+    if slack.is_some_and(|auth| auth != SlackAuth::Ok) {
+        status = http::StatusCode::SERVICE_UNAVAILABLE;
+    }
+    let consecutive_failures = state
+        .max_consecutive_failures
+        .map(|_| state.consecutive_failures());
+    if state.failing_reconciles() {
+        status = http::StatusCode::SERVICE_UNAVAILABLE;
+    }
+    (
+        status,
+        Json(Health {
+            redis,
+            slack,
+            consecutive_failures,
+        }),
+    )
+}
+
+async fn redis_health(
+    store: Option<&mut dyn ValkeyClient>,
+) -> (http::StatusCode, Json<RedisHealth>) {
+    let Some(store) = store else {
+        error!("Health check: unable to connect to Valkey");
+        return (
+            http::StatusCode::SERVICE_UNAVAILABLE,
+            Json(RedisHealth { redis: "down" }),
+        );
+    };
+
+    match store.ping().await {
+        Ok(()) => (http::StatusCode::OK, Json(RedisHealth { redis: "ok" })),
+        Err(err) => {
+            error!(error = %err, "Health check: Valkey did not answer PING");
+            (
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                Json(RedisHealth { redis: "down" }),
+            )
+        }
+    }
+}
+
+async fn ready(State(state): State<config::AppState>) -> Response {
     if state.config.is_dry_run() {
-        return (http::StatusCode::OK, "ok");
+        return (http::StatusCode::OK, "ok").into_response();
     }
 
-    match state.config.valkey_config() {
-        Some(redis_cfg) => {
-            if crate::redis_client::ValkeyStore::connect(redis_cfg).is_some() {
-                (http::StatusCode::OK, "ok")
+    match state.valkey.as_ref().map(Store::client) {
+        Some(mut store) => {
+            if store.ping().await.is_ok() {
+                (http::StatusCode::OK, "ok").into_response()
             } else {
                 error!("Readiness check: unable to connect to Valkey");
-                (
-                    http::StatusCode::SERVICE_UNAVAILABLE,
-                    "Valkey not available",
-                )
+                valkey_unavailable()
             }
         }
         None => {
-            error!("Readiness check: no Valkey configuration in Normal mode");
-            (
+            error!("Readiness check: no Valkey connection pool in Normal mode");
+            ApiError::new(
                 http::StatusCode::SERVICE_UNAVAILABLE,
+                "valkey_not_configured",
                 "Valkey not configured",
             )
+            .into_response()
         }
     }
 }
 
 #[axum::debug_handler]
+async fn reconcile(
+    State(state): State<config::AppState>,
+    headers: http::HeaderMap,
+    body: Bytes,
+) -> Response {
+    if body.is_empty() {
+        return run_reconcile(&state).await;
+    }
+    if !state.allow_pushed_feed {
+        return ApiError::new(
+            http::StatusCode::FORBIDDEN,
+            "pushed_feed_disabled",
+            "Feeds in the request body need ALLOW_PUSHED_FEED",
+        )
+        .into_response();
+    }
+    if let Some(refused) = refuse_admin(&state, &headers) {
+        return refused;
+    }
+    if !is_feed_content_type(&headers) {
+        return ApiError::new(
+            http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_feed_type",
+            "Expected an XML or JSON Feed body",
+        )
+        .into_response();
+    }
+    if body.len() > state.max_feed_bytes {
+        return ApiError::new(
+            http::StatusCode::PAYLOAD_TOO_LARGE,
+            "feed_too_large",
+            format!(
+                "Feed is larger than MAX_FEED_BYTES ({} bytes)",
+                state.max_feed_bytes
+            ),
+        )
+        .into_response();
+    }
+
+    let response = match open_store(&state) {
+        Some(mut store) => {
+            let body = String::from_utf8_lossy(&body);
+            reconcile_pushed_feed(&state, &mut keyed(&state, store.as_mut()), &body).await
+        }
+        None => valkey_unavailable(),
+    };
+    record_reconcile(&state, &response);
+    response
+}
+
+/// Whether a pushed body is declared as something `rss::handle_feed` reads:
+/// RSS or Atom as any XML type, or JSON Feed.
+fn is_feed_content_type(headers: &http::HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    matches!(essence.as_str(), "application/xml" | "text/xml")
+        || essence.ends_with("+xml")
+        || essence == "application/feed+json"
+}
+
 #[instrument(skip(state))]
-async fn reconcile(State(state): State<config::AppState>) -> Response {
+async fn run_reconcile(state: &config::AppState) -> Response {
     info!(
         mode = %if state.config.is_dry_run() { "DryRun" } else { "Normal" },
         "Time to check the log"
     );
-    let client = state.http_client.clone();
-    match client.get("https://nais.io/log/rss.xml").send().await {
-        Ok(resp) => {
-            if !resp.status().is_success() {
-                error!("Got a response, but no XML");
-                return (
+    let response = match open_store(state) {
+        Some(mut store) => reconcile_feed(state, &mut keyed(state, store.as_mut())).await,
+        None => valkey_unavailable(),
+    };
+    record_reconcile(state, &response);
+    response
+}
+
+/// Counts a reconcile answered with a server error, or left with Slack's
+/// circuit breaker open, towards `MAX_CONSECUTIVE_FAILURES`, and starts over
+/// after any other. Runs turned away because another was going count neither
+/// way.
+fn record_reconcile(state: &config::AppState, response: &Response) {
+    if response.status() != http::StatusCode::CONFLICT {
+        let breaker_open = state
+            .slack_breaker
+            .as_ref()
+            .is_some_and(|breaker| !breaker.is_closed());
+        state.record_reconcile(response.status().is_server_error() || breaker_open);
+    }
+}
+
+fn open_store(state: &config::AppState) -> Option<Box<dyn ValkeyClient>> {
+    let store = state.valkey.as_ref().map(Store::client);
+    if store.is_none() {
+        error!("Unable to connect to Valkey");
+    }
+    store
+}
+
+/// `store` with every key under `REDIS_KEY_PREFIX`, keeping this deployment
+/// apart from others sharing the instance.
+fn keyed<'a>(state: &'a config::AppState, store: &'a mut dyn ValkeyClient) -> PrefixedValkey<'a> {
+    PrefixedValkey::new(&state.key_prefix, store)
+}
+
+fn valkey_unavailable() -> Response {
+    ApiError::new(
+        http::StatusCode::SERVICE_UNAVAILABLE,
+        "valkey_unavailable",
+        "Valkey not available",
+    )
+    .into_response()
+}
+
+/// Lists the posts already announced, as remembered in Valkey.
+async fn posts(State(state): State<config::AppState>) -> Response {
+    match open_store(&state) {
+        Some(mut store) => list_posts(&mut keyed(&state, store.as_mut())).await,
+        None => valkey_unavailable(),
+    }
+}
+
+async fn list_posts(store: &mut dyn ValkeyClient) -> Response {
+    match rss::archived_posts(store).await {
+        Ok(posts) => Json(posts).into_response(),
+        Err(err) => {
+            error!(error = %err, "Failed listing archived posts");
+            ReconcileError::from(err).into_response()
+        }
+    }
+}
+
+/// Lists the posts given up on after failing `DEAD_LETTER_AFTER` times.
+async fn deadletter(State(state): State<config::AppState>) -> Response {
+    match open_store(&state) {
+        Some(mut store) => list_dead_letters(&mut keyed(&state, store.as_mut())).await,
+        None => valkey_unavailable(),
+    }
+}
+
+async fn list_dead_letters(store: &mut dyn ValkeyClient) -> Response {
+    match failures::dead_letters(store).await {
+        Ok(letters) => Json(letters).into_response(),
+        Err(err) => {
+            error!(error = %err, "Failed listing dead letters");
+            ReconcileError::from(err).into_response()
+        }
+    }
+}
+
+/// Forgets an announced post, so the next reconcile announces it again.
+async fn delete_post(
+    State(state): State<config::AppState>,
+    headers: http::HeaderMap,
+    Path(key): Path<String>,
+) -> Response {
+    if let Some(refused) = refuse_admin(&state, &headers) {
+        return refused;
+    }
+    match open_store(&state) {
+        Some(mut store) => forget_post(&mut keyed(&state, store.as_mut()), &key).await,
+        None => valkey_unavailable(),
+    }
+}
+
+async fn forget_post(store: &mut dyn ValkeyClient, key: &str) -> Response {
+    match store.del(key).await {
+        Ok(true) => {
+            info!(key, "Deleted archived post, it will be announced again");
+            http::StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => ApiError::new(
+            http::StatusCode::NOT_FOUND,
+            "post_not_found",
+            "No such post",
+        )
+        .into_response(),
+        Err(err) => {
+            error!(key, error = %err, "Failed deleting archived post");
+            ReconcileError::from(err).into_response()
+        }
+    }
+}
+
+/// Sends one post from the feed now, without a full reconcile, and answers
+/// with what was done with it. Keys of a prefixed feed source find the post
+/// in that source's feed.
+async fn replay(
+    State(state): State<config::AppState>,
+    headers: http::HeaderMap,
+    Path(key): Path<String>,
+) -> Response {
+    if let Some(refused) = refuse_admin(&state, &headers) {
+        return refused;
+    }
+    match open_store(&state) {
+        Some(mut store) => replay_post(&state, &mut keyed(&state, store.as_mut()), &key).await,
+        None => valkey_unavailable(),
+    }
+}
+
+async fn replay_post(
+    state: &config::AppState,
+    store: &mut dyn ValkeyClient,
+    key: &str,
+) -> Response {
+    for source in state.sources() {
+        let local_key = if source.key_prefix.is_empty() {
+            Some(key)
+        } else {
+            key.strip_prefix(&format!("{}:", source.key_prefix))
+        };
+        let Some(local_key) = local_key else {
+            continue;
+        };
+        let state = state.for_source(&source);
+        let url = state.feed_url.as_str();
+        let body = match fetch_whole_feed(&state).await {
+            Ok(Some(body)) => body,
+            Ok(None) => continue,
+            Err(response) => return response,
+        };
+
+        let mut store = PrefixedValkey::new(&source.key_prefix, &mut *store);
+        match rss::replay_post(&body, local_key, &mut store, &state).await {
+            Ok(Some(action)) if action.action == "skipped" => {
+                return ApiError::new(
+                    http::StatusCode::UNPROCESSABLE_ENTITY,
+                    "post_filtered",
+                    "The post is left out by ANNOUNCE_CATEGORIES or ALLOWED_LINK_HOSTS",
+                )
+                .into_response();
+            }
+            Ok(Some(action)) if action.action == "circuit_open" => {
+                return ApiError::new(
                     http::StatusCode::SERVICE_UNAVAILABLE,
-                    format!(
-                        "https://nais.io/log/rss.xml answers with: {}",
-                        resp.status()
-                    ),
+                    "slack_unavailable",
+                    "Slack's circuit breaker is open; try again after SLACK_BREAKER_COOLDOWN_SECONDS",
                 )
-                    .into_response();
-            }
-            let body = match resp.text().await {
-                Ok(b) => b,
-                Err(e) => {
-                    error!("Unable to parse nais.io/log's rss: {e}");
-                    return (
-                        http::StatusCode::INTERNAL_SERVER_ERROR,
-                        "Unable to decode nais log",
-                    )
-                        .into_response();
-                }
+                .into_response();
+            }
+            Ok(Some(action)) => {
+                info!(key, action = action.action, "Replayed post");
+                return Json(action).into_response();
+            }
+            Ok(None) => {}
+            Err(FeedError::EmptyBody) => return empty_feed_response(url),
+            Err(FeedError::RssParse(err)) => {
+                error!("Failed to parse RSS feed: {err}");
+                return ApiError::new(
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "feed_parse_failed",
+                    "Failed to parse RSS feed",
+                )
+                .into_response();
+            }
+            Err(FeedError::ReconcileInProgress) => return reconcile_in_progress(),
+            Err(FeedError::Lock(err)) => {
+                error!("Failed taking the reconcile lock: {err}");
+                return valkey_unavailable();
+            }
+        }
+    }
+
+    ApiError::new(
+        http::StatusCode::NOT_FOUND,
+        "post_not_in_feed",
+        "No post with that key in the feed",
+    )
+    .into_response()
+}
+
+/// Forgets everything in Valkey, so the next reconcile announces the whole
+/// feed again. Meant for staging; production needs `ALLOW_PROD_RESET`.
+async fn admin_reset(State(state): State<config::AppState>, headers: http::HeaderMap) -> Response {
+    if let Some(refused) = refuse_admin(&state, &headers) {
+        return refused;
+    }
+    if !state.reset_allowed {
+        return ApiError::new(
+            http::StatusCode::FORBIDDEN,
+            "reset_not_allowed",
+            "Refusing to reset in production without ALLOW_PROD_RESET",
+        )
+        .into_response();
+    }
+    match open_store(&state) {
+        Some(mut store) => reset_store(&state, &mut keyed(&state, store.as_mut())).await,
+        None => valkey_unavailable(),
+    }
+}
+
+/// Turns away requests not carrying `ADMIN_TOKEN` as a bearer token, and
+/// every request when it isn't set.
+fn refuse_admin(state: &config::AppState, headers: &http::HeaderMap) -> Option<Response> {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Some(
+            ApiError::new(
+                http::StatusCode::FORBIDDEN,
+                "admin_disabled",
+                "Admin endpoints need ADMIN_TOKEN",
+            )
+            .into_response(),
+        );
+    };
+    let given = headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(token) if tokens_match(token.trim(), expected) => None,
+        _ => Some(
+            (
+                [(http::header::WWW_AUTHENTICATE, "Bearer")],
+                ApiError::new(
+                    http::StatusCode::UNAUTHORIZED,
+                    "admin_unauthorized",
+                    "Missing or wrong admin token",
+                ),
+            )
+                .into_response(),
+        ),
+    }
+}
+
+/// Compares every byte rather than stopping at the first difference, so the
+/// time taken doesn't give away how much of a guess was right.
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Starts a reconcile for a Slack slash command. Slack gives up on commands
+/// not answered within three seconds, so it runs in the background.
+async fn slack_command(State(state): State<config::AppState>) -> Response {
+    tokio::spawn(
+        async move {
+            let response = run_reconcile(&state).await;
+            if !response.status().is_success() {
+                error!(status = %response.status(), "Reconcile from Slack command failed");
+            }
+        }
+        .in_current_span(),
+    );
+    "Checking the feed for new posts".into_response()
+}
+
+#[derive(Debug, Serialize)]
+struct ResetSummary {
+    deleted: usize,
+}
+
+async fn reset_store(state: &config::AppState, store: &mut dyn ValkeyClient) -> Response {
+    let Ok(_running) = state.reconcile_lock.try_lock() else {
+        return reconcile_in_progress();
+    };
+    // Without a prefix of our own every key in the database is up for
+    // deletion, so keys of other deployments stop the reset instead.
+    if state.key_prefix.is_empty() {
+        let sources = state.sources();
+        let prefixes: Vec<&str> = sources
+            .iter()
+            .map(|source| source.key_prefix.as_str())
+            .collect();
+        match rss::foreign_keys(store, &prefixes).await {
+            Ok(foreign) if !foreign.is_empty() => {
+                warn!(
+                    count = foreign.len(),
+                    example = %foreign[0],
+                    "Valkey holds keys of other deployments, not resetting"
+                );
+                return ApiError::new(
+                    http::StatusCode::CONFLICT,
+                    "foreign_keys",
+                    "Valkey holds keys this app didn't write; set REDIS_KEY_PREFIX to reset only its own",
+                )
+                .into_response();
+            }
+            Ok(_) => {}
+            Err(err) => {
+                error!(error = %err, "Failed resetting Valkey");
+                return ReconcileError::from(err).into_response();
+            }
+        }
+    }
+    match rss::clear_archive(store).await {
+        Ok(deleted) => {
+            warn!(
+                deleted,
+                "Reset Valkey, the whole feed will be announced again"
+            );
+            Json(ResetSummary { deleted }).into_response()
+        }
+        Err(err) => {
+            error!(error = %err, "Failed resetting Valkey");
+            ReconcileError::from(err).into_response()
+        }
+    }
+}
+
+/// Shows what a reconcile would do right now, without touching Redis or
+/// announcing anything.
+#[axum::debug_handler]
+async fn reconcile_dry(State(state): State<config::AppState>) -> Response {
+    let Some(mut store) = open_store(&state) else {
+        return valkey_unavailable();
+    };
+    let mut store = keyed(&state, store.as_mut());
+
+    let sources = state.sources();
+    let mut outcomes = Vec::new();
+    for source in &sources {
+        let mut store = PrefixedValkey::new(&source.key_prefix, &mut store);
+        let result = preview_feed(&state.for_source(source), &mut store)
+            .instrument(info_span!("source", key_prefix = %source.key_prefix))
+            .await;
+        if sources.len() == 1 {
+            return match result {
+                Ok(preview) => Json(preview).into_response(),
+                Err(response) => response,
             };
-            if let Err(e) = rss::handle_feed(&body, &state).await {
-                match e {
-                    FeedError::RssParse(err) => {
-                        error!("Failed to parse RSS feed: {err}");
-                        return (
-                            http::StatusCode::INTERNAL_SERVER_ERROR,
-                            "Failed to parse RSS feed",
-                        )
-                            .into_response();
-                    }
-                    FeedError::InvalidArchive { key, error } => {
-                        error!("Invalid archive JSON for key {key}: {error}");
-                        return (
-                            http::StatusCode::INTERNAL_SERVER_ERROR,
-                            "Corrupted archive data in Redis",
-                        )
-                            .into_response();
-                    }
-                    FeedError::SerializeArchive { key, error } => {
-                        error!("Failed to serialize archive for key {key}: {error}");
-                        return (
-                            http::StatusCode::INTERNAL_SERVER_ERROR,
-                            "Failed to persist archive data",
-                        )
-                            .into_response();
-                    }
-                }
+        }
+        let result = result.map(|preview| (http::StatusCode::OK, preview));
+        outcomes.push(SourceOutcome::new(source, result));
+    }
+    outcomes_response(outcomes)
+}
+
+/// Fetches the feed in full, leaving out the validators from earlier
+/// fetches so there is always a body; `None` if the server answers
+/// `304 Not Modified` anyway.
+async fn fetch_whole_feed(state: &config::AppState) -> Result<Option<String>, Response> {
+    let url = state.feed_url.as_str();
+    match feed::fetch_feed(
+        &state.http_client,
+        url,
+        &mut InMemoryValkey::new(),
+        state.max_feed_bytes,
+        state.feed_timeout,
+    )
+    .await
+    {
+        Ok(FetchedFeed::Modified { body, .. }) => Ok(Some(body)),
+        Ok(FetchedFeed::NotModified) => Ok(None),
+        Err(err) => Err(fetch_error_response(url, err)),
+    }
+}
+
+async fn preview_feed(
+    state: &config::AppState,
+    store: &mut dyn ValkeyClient,
+) -> Result<ReconcilePreview, Response> {
+    let url = state.feed_url.as_str();
+    let Some(body) = fetch_whole_feed(state).await? else {
+        return Ok(ReconcilePreview {
+            summary: ReconcileSummary {
+                not_modified: true,
+                ..ReconcileSummary::default()
+            },
+            actions: Vec::new(),
+        });
+    };
+
+    rss::preview_feed(&body, store, state)
+        .await
+        .map_err(|err| match err {
+            FeedError::EmptyBody => empty_feed_response(url),
+            err => {
+                error!("Failed to parse RSS feed: {err:?}");
+                ApiError::new(
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "feed_parse_failed",
+                    "Failed to parse RSS feed",
+                )
+                .into_response()
+            }
+        })
+}
+
+/// How reconciling, or previewing, one of several feed sources went.
+#[derive(Serialize)]
+struct SourceOutcome<T> {
+    key_prefix: String,
+    feed_url: String,
+    status: u16,
+    /// Missing when the feed couldn't be fetched or read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<T>,
+}
+
+impl<T> SourceOutcome<T> {
+    fn new(source: &FeedSource, result: Result<(http::StatusCode, T), Response>) -> Self {
+        let (status, summary) = match result {
+            Ok((status, summary)) => (status, Some(summary)),
+            Err(response) => (response.status(), None),
+        };
+        Self {
+            key_prefix: source.key_prefix.clone(),
+            feed_url: source.url.to_string(),
+            status: status.as_u16(),
+            summary,
+        }
+    }
+}
+
+/// Answers with the outcome of every source: 502 when all of them failed
+/// and 207 when some did.
+fn outcomes_response<T: Serialize>(outcomes: Vec<SourceOutcome<T>>) -> Response {
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| !(200..300).contains(&outcome.status))
+        .count();
+    let status = if failed == outcomes.len() {
+        http::StatusCode::BAD_GATEWAY
+    } else if failed > 0 {
+        http::StatusCode::MULTI_STATUS
+    } else {
+        http::StatusCode::OK
+    };
+    (status, Json(outcomes)).into_response()
+}
+
+/// Reconciles every feed source in turn, each against its own keys. A lone
+/// source is answered with its summary as it is; several with a list of
+/// outcomes.
+async fn reconcile_feed(state: &config::AppState, store: &mut dyn ValkeyClient) -> Response {
+    let Ok(_running) = state.reconcile_lock.try_lock() else {
+        info!("A reconcile is already running, skipping");
+        return reconcile_in_progress();
+    };
+
+    let sources = state.sources();
+    let mut outcomes = Vec::new();
+    for source in &sources {
+        let mut store = PrefixedValkey::new(&source.key_prefix, store);
+        let result = reconcile_source(&state.for_source(source), &mut store)
+            .instrument(info_span!("source", key_prefix = %source.key_prefix))
+            .await;
+        if sources.len() == 1 {
+            return summary_response(result);
+        }
+        let result = result.map(|summary| (summary_status(&summary), summary));
+        outcomes.push(SourceOutcome::new(source, result));
+    }
+    outcomes_response(outcomes)
+}
+
+/// Fetches the feed of `state` and reconciles it, or answers with why it
+/// couldn't be.
+async fn reconcile_source(
+    state: &config::AppState,
+    store: &mut dyn ValkeyClient,
+) -> Result<ReconcileSummary, Response> {
+    let url = state.feed_url.as_str();
+    let (body, validators) = match feed::fetch_feed(
+        &state.http_client,
+        url,
+        store,
+        state.max_feed_bytes,
+        state.feed_timeout,
+    )
+    .await
+    {
+        Ok(FetchedFeed::Modified { body, validators }) => (body, validators),
+        Ok(FetchedFeed::NotModified) => {
+            return Ok(ReconcileSummary {
+                not_modified: true,
+                ..ReconcileSummary::default()
+            });
+        }
+        Err(err) => {
+            state.metrics.observe_reconcile_error();
+            return Err(fetch_error_response(url, err));
+        }
+    };
+
+    handle_feed_body(state, store, url, &body, Some(&validators)).await
+}
+
+fn summary_response(result: Result<ReconcileSummary, Response>) -> Response {
+    match result {
+        Ok(summary) => (summary_status(&summary), Json(summary)).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Reconciles against a feed pushed in the request body instead of the one
+/// at `FEED_URL`, for tests and setups that push rather than get polled.
+async fn reconcile_pushed_feed(
+    state: &config::AppState,
+    store: &mut dyn ValkeyClient,
+    body: &str,
+) -> Response {
+    let Ok(_running) = state.reconcile_lock.try_lock() else {
+        info!("A reconcile is already running, skipping");
+        return reconcile_in_progress();
+    };
+
+    info!("Reconciling the feed in the request body");
+    // The validators belong to FEED_URL, which this body may not match.
+    summary_response(handle_feed_body(state, store, "The request body", body, None).await)
+}
+
+/// Hands a feed from `source` to `rss::handle_feed`, saving `validators`
+/// once every post in it was handled.
+async fn handle_feed_body(
+    state: &config::AppState,
+    store: &mut dyn ValkeyClient,
+    source: &str,
+    body: &str,
+    validators: Option<&feed::FeedValidators>,
+) -> Result<ReconcileSummary, Response> {
+    let response = match rss::handle_feed(body, store, state).await {
+        Ok(summary) => {
+            info!(
+                feed_title = %summary.feed_title,
+                total = summary.total,
+                new = summary.new,
+                updated = summary.updated,
+                unchanged = summary.unchanged,
+                circuit_open = summary.circuit_open,
+                deferred = summary.deferred,
+                pending = summary.pending,
+                dead_lettered = summary.dead_lettered,
+                errors = summary.errors,
+                slack_errors = summary.slack_errors,
+                "Reconcile finished"
+            );
+            // Posts held back for quiet hours, deferred past
+            // `MAX_POSTS_PER_RECONCILE` or kept from Slack by the circuit
+            // breaker have to be looked at again even if the feed doesn't
+            // change in the meantime.
+            if let Some(validators) = validators.filter(|_| {
+                summary.errors == 0
+                    && summary.pending + summary.deferred + summary.circuit_open == 0
+            }) {
+                feed::save_validators(store, validators).await;
             }
+            return Ok(summary);
+        }
+        Err(FeedError::EmptyBody) => {
+            state.metrics.observe_reconcile_error();
+            empty_feed_response(source)
+        }
+        Err(FeedError::RssParse(err)) => {
+            state.metrics.observe_reconcile_error();
+            error!("Failed to parse RSS feed: {err}");
+            ApiError::new(
+                http::StatusCode::INTERNAL_SERVER_ERROR,
+                "feed_parse_failed",
+                "Failed to parse RSS feed",
+            )
+            .into_response()
+        }
+        Err(FeedError::ReconcileInProgress) => {
+            info!("Another replica is reconciling, skipping");
+            reconcile_in_progress()
         }
-        Err(e) => {
+        Err(FeedError::Lock(err)) => {
+            error!("Failed taking the reconcile lock: {err}");
+            valkey_unavailable()
+        }
+    };
+    Err(response)
+}
+
+fn empty_feed_response(url: &str) -> Response {
+    error!("Got an empty feed, not treating it as a feed without posts");
+    ApiError::new(
+        http::StatusCode::BAD_GATEWAY,
+        "feed_empty",
+        format!("{url} answered with an empty body"),
+    )
+    .into_response()
+}
+
+fn fetch_error_response(url: &str, err: FetchError) -> Response {
+    match err {
+        FetchError::Status(status) => {
+            error!("Got a response, but no XML");
+            ApiError::new(
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                "feed_unavailable",
+                format!("{url} answers with: {status}"),
+            )
+            .into_response()
+        }
+        FetchError::Body(e) => {
+            error!("Unable to parse nais.io/log's rss: {e}");
+            ApiError::new(
+                http::StatusCode::INTERNAL_SERVER_ERROR,
+                "feed_read_failed",
+                "Unable to decode nais log",
+            )
+            .into_response()
+        }
+        FetchError::TooLarge { limit } => {
+            error!(
+                limit,
+                "Feed is larger than MAX_FEED_BYTES, not reading the rest"
+            );
+            ApiError::new(
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                "feed_too_large",
+                format!("{url} is larger than MAX_FEED_BYTES ({limit} bytes)"),
+            )
+            .into_response()
+        }
+        FetchError::Timeout { after } => {
+            error!(
+                timeout_seconds = after.as_secs_f64(),
+                "Feed didn't answer in time, giving up"
+            );
+            ApiError::new(
+                http::StatusCode::GATEWAY_TIMEOUT,
+                "feed_timeout",
+                format!(
+                    "{url} didn't answer within FEED_TIMEOUT_SECONDS ({}s)",
+                    after.as_secs_f64()
+                ),
+            )
+            .into_response()
+        }
+        FetchError::Request(e) => {
             error!("Failed getting the feed: {e}");
-            return (http::StatusCode::INTERNAL_SERVER_ERROR, "HTTP client error").into_response();
+            ReconcileError::from(e).into_response()
         }
+    }
+}
+
+/// 502 when posts failed and nothing got through, 207 when only some did,
+/// so monitoring notices failures the summary body spells out.
+fn summary_status(summary: &ReconcileSummary) -> http::StatusCode {
+    if summary.failed_entirely() {
+        http::StatusCode::BAD_GATEWAY
+    } else if summary.errors > 0 {
+        http::StatusCode::MULTI_STATUS
+    } else {
+        http::StatusCode::OK
+    }
+}
+
+fn reconcile_in_progress() -> Response {
+    ApiError::new(
+        http::StatusCode::CONFLICT,
+        "reconcile_in_progress",
+        "Reconcile already in progress",
+    )
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        RedisHealth, admin_reset, build_app, forget_post, healthz, keyed, list_dead_letters,
+        list_posts, metrics, reconcile, reconcile_feed, reconcile_pushed_feed, redis_health,
+        replay_post, reset_store, run_reconcile, serve, summary_status, version,
+    };
+    use crate::{
+        circuit_breaker::CircuitBreaker,
+        clock::FixedClock,
+        config::{
+            self, AppConfig, AppState, ContentFormat, FeedSource, LinkHostFilter, LongPostMode,
+            NotifierConfig, QuietHours, SlackConfig, SlackLayout, ValkeyConfig,
+        },
+        notifier::{MessageIds, Notifier, single_message},
+        redis_client::{InMemoryValkey, Store, ValkeyClient},
+        rss::{self, ReconcileSummary},
+        slack::SlackAuth,
+    };
+    use async_trait::async_trait;
+    use axum::{
+        Json, Router,
+        body::{Body, Bytes, to_bytes},
+        extract::State,
+        http::{HeaderMap, Request, StatusCode, header},
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use chrono::{TimeZone, Utc};
+    use redis::{ErrorKind, RedisError, RedisResult};
+    use reqwest::Url;
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
     };
-    (http::StatusCode::OK, "").into_response()
+    use tower::ServiceExt;
+
+    const SAMPLE_RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Test Post</title>
+      <link>https://nais.io/log#test-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Content]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+
+    async fn body_text(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    struct FailingValkey;
+
+    #[async_trait]
+    impl ValkeyClient for FailingValkey {
+        async fn get(&mut self, _key: &str) -> RedisResult<Option<String>> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn get_many(&mut self, _keys: &[String]) -> RedisResult<Vec<Option<String>>> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn set(&mut self, _key: &str, _value: &str) -> RedisResult<()> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn set_with_ttl(
+            &mut self,
+            _key: &str,
+            _value: &str,
+            _ttl: Duration,
+        ) -> RedisResult<()> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn set_many(
+            &mut self,
+            _entries: &[(String, String)],
+            _ttl: Option<Duration>,
+        ) -> RedisResult<()> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn set_if_absent(
+            &mut self,
+            _key: &str,
+            _value: &str,
+            _ttl: Duration,
+        ) -> RedisResult<bool> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn delete_if_equals(&mut self, _key: &str, _value: &str) -> RedisResult<()> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn del(&mut self, _key: &str) -> RedisResult<bool> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn del_many(&mut self, _keys: &[String]) -> RedisResult<usize> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn scan_keys(&mut self, _pattern: &str) -> RedisResult<Vec<String>> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+
+        async fn ping(&mut self) -> RedisResult<()> {
+            Err(RedisError::from((ErrorKind::IoError, "connection refused")))
+        }
+    }
+
+    #[tokio::test]
+    async fn version_reports_crate_version() {
+        let Json(info) = version().await;
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.git_sha.is_empty());
+        assert!(!info.build_timestamp.is_empty());
+    }
+
+    #[tokio::test]
+    async fn redis_health_ok_when_ping_succeeds() {
+        let mut store = InMemoryValkey::new();
+        let (status, body) = redis_health(Some(&mut store)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.0, RedisHealth { redis: "ok" });
+    }
+
+    #[tokio::test]
+    async fn redis_health_down_when_ping_fails() {
+        let mut store = FailingValkey;
+        let (status, body) = redis_health(Some(&mut store)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body.0, RedisHealth { redis: "down" });
+    }
+
+    #[tokio::test]
+    async fn redis_health_down_without_connection() {
+        let (status, body) = redis_health(None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body.0, RedisHealth { redis: "down" });
+    }
+
+    #[tokio::test]
+    async fn healthz_reports_a_token_slack_turns_down() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = Router::new().route(
+            "/auth.test",
+            axum::routing::post(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { Json(serde_json::json!({ "ok": false, "error": "invalid_auth" })) }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let state = slack_state(&format!("http://{addr}")).with_slack_healthcheck(true);
+
+        let (status, Json(body)) = healthz(State(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body.slack, Some(SlackAuth::BadAuth));
+        assert_eq!(serde_json::to_value(&body).unwrap()["slack"], "bad_auth");
+
+        let (_, Json(body)) = healthz(State(state)).await;
+        assert_eq!(body.slack, Some(SlackAuth::BadAuth));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn healthz_leaves_slack_out_unless_asked() {
+        let (status, Json(body)) = healthz(State(AppState::new(AppConfig::DryRun))).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::to_value(&body).unwrap(),
+            serde_json::json!({ "redis": "ok" })
+        );
+    }
+
+    #[tokio::test]
+    async fn healthz_turns_unhealthy_after_too_many_failed_reconciles() {
+        let failing = AppState::new(AppConfig::DryRun)
+            .with_feed_url(serve_feed("<html>Not a feed</html>").await)
+            .with_max_consecutive_failures(Some(2));
+        let health = async |state: &AppState| {
+            let (status, Json(body)) = healthz(State(state.clone())).await;
+            (status, serde_json::to_value(&body).unwrap())
+        };
+
+        run_reconcile(&failing).await;
+        run_reconcile(&failing).await;
+        assert_eq!(
+            health(&failing).await,
+            (
+                StatusCode::OK,
+                serde_json::json!({ "redis": "ok", "consecutive_failures": 2 })
+            )
+        );
+
+        run_reconcile(&failing).await;
+        assert_eq!(
+            health(&failing).await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                serde_json::json!({ "redis": "ok", "consecutive_failures": 3 })
+            )
+        );
+
+        let recovered = failing.with_feed_url(serve_feed(SAMPLE_RSS).await);
+        assert_eq!(run_reconcile(&recovered).await.status(), StatusCode::OK);
+        assert_eq!(
+            health(&recovered).await,
+            (
+                StatusCode::OK,
+                serde_json::json!({ "redis": "ok", "consecutive_failures": 0 })
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn reconcile_with_the_breaker_open_counts_as_a_failure() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        breaker.record_failure();
+        let mut state = AppState::new(AppConfig::DryRun)
+            .with_feed_url(serve_feed(SAMPLE_RSS).await)
+            .with_max_consecutive_failures(Some(0));
+        state.slack_breaker = Some(Arc::new(breaker));
+
+        assert_eq!(run_reconcile(&state).await.status(), StatusCode::OK);
+        let (status, Json(body)) = healthz(State(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            serde_json::to_value(&body).unwrap()["consecutive_failures"],
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn reconcile_in_progress_does_not_count_as_a_failure() {
+        let state = AppState::new(AppConfig::DryRun)
+            .with_feed_url(serve_feed("<html>Not a feed</html>").await)
+            .with_max_consecutive_failures(Some(0));
+        let _running = state.reconcile_lock.lock().await;
+
+        assert_eq!(run_reconcile(&state).await.status(), StatusCode::CONFLICT);
+        let (status, _) = healthz(State(state.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn lists_archived_posts() {
+        let mut store = InMemoryValkey::new();
+        store
+            .set("second", r#"{"content_hash":"bbb","timestamp":"2"}"#)
+            .await
+            .unwrap();
+        store
+            .set(
+                "first",
+                r#"{"content_hash":"aaa","timestamps":{"C01":"1","C02":"3"}}"#,
+            )
+            .await
+            .unwrap();
+        store.set("reconcile:lock", "token").await.unwrap();
+        store
+            .set(
+                "feed:validators",
+                r#"{"etag":""abc"","last_modified":null}"#,
+            )
+            .await
+            .unwrap();
+
+        let response = list_posts(&mut store).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let posts: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(
+            posts,
+            serde_json::json!([
+                { "key": "first", "hash": "aaa", "timestamps": { "C01": "1", "C02": "3" } },
+                { "key": "second", "hash": "bbb", "timestamp": "2" },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_dead_letters() {
+        let mut store = InMemoryValkey::new();
+        store
+            .set(
+                "deadletter:broken-post",
+                r#"{"key":"broken-post","attempts":5,"error":"channel_not_found"}"#,
+            )
+            .await
+            .unwrap();
+        store.set("failures:other-post", "2").await.unwrap();
+
+        let response = list_dead_letters(&mut store).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let letters: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(
+            letters,
+            serde_json::json!([
+                { "key": "broken-post", "attempts": 5, "error": "channel_not_found" },
+            ])
+        );
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {token}").parse().unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn reset_needs_the_admin_token() {
+        let state = AppState::new(AppConfig::DryRun).with_reset_allowed(true);
+        let response = admin_reset(State(state.clone()), bearer("secret")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let state = state.with_admin_token(Some("secret".to_string()));
+        let response = admin_reset(State(state.clone()), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "Missing or wrong admin token", "code": "admin_unauthorized" })
+        );
+        let response = admin_reset(State(state.clone()), bearer("guess")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = admin_reset(State(state.clone()), bearer("secrets")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = admin_reset(State(state), bearer("secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn reset_is_refused_in_production() {
+        let state = AppState::new(AppConfig::DryRun)
+            .with_admin_token(Some("secret".to_string()))
+            .with_reset_allowed(config::reset_allowed(Some("prod-gcp"), false));
+
+        let response = admin_reset(State(state), bearer("secret")).await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(body_text(response).await.contains("ALLOW_PROD_RESET"));
+    }
+
+    #[tokio::test]
+    async fn reset_reports_deleted_keys() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+        rss::handle_feed(SAMPLE_RSS, &mut store, &state)
+            .await
+            .unwrap();
+
+        let response = reset_store(&state, &mut store).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_text(response).await, r#"{"deleted":1}"#);
+        assert_eq!(store.get("test-post").await.unwrap(), None);
+
+        let _running = state.reconcile_lock.lock().await;
+        let response = reset_store(&state, &mut store).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn reset_without_a_key_prefix_spares_other_deployments() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+        rss::handle_feed(SAMPLE_RSS, &mut store, &state)
+            .await
+            .unwrap();
+        store.set("failures:test-post", "1").await.unwrap();
+        store.set("other-app:test-post", "{}").await.unwrap();
+
+        let response = reset_store(&state, &mut store).await;
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(body["code"], "foreign_keys");
+        assert!(store.get("other-app:test-post").await.unwrap().is_some());
+        assert!(store.get("test-post").await.unwrap().is_some());
+
+        let prefixed = state.with_key_prefix("mine".to_string());
+        let response = reset_store(&prefixed, &mut keyed(&prefixed, &mut store)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(store.get("other-app:test-post").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn listing_posts_fails_without_valkey() {
+        let response = list_posts(&mut FailingValkey).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn deleted_post_is_announced_again() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+        let first = rss::handle_feed(SAMPLE_RSS, &mut store, &state)
+            .await
+            .unwrap();
+        assert_eq!(first.new, 1);
+
+        let response = forget_post(&mut store, "test-post").await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let second = rss::handle_feed(SAMPLE_RSS, &mut store, &state)
+            .await
+            .unwrap();
+        assert_eq!(second.new, 1);
+        assert_eq!(second.unchanged, 0);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_post_needs_the_admin_token() {
+        let delete = |headers: HeaderMap| {
+            let mut request = Request::delete("/posts/test-post")
+                .body(Body::empty())
+                .unwrap();
+            *request.headers_mut() = headers;
+            request
+        };
+        let app = build_app(AppState::new(AppConfig::DryRun));
+        let response = app.oneshot(delete(bearer("secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let app = build_app(
+            AppState::new(AppConfig::DryRun).with_admin_token(Some("secret".to_string())),
+        );
+        let response = app.clone().oneshot(delete(HeaderMap::new())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(delete(bearer("guess"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app.oneshot(delete(bearer("secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn deleting_unknown_post_is_not_found() {
+        let mut store = InMemoryValkey::new();
+
+        let response = forget_post(&mut store, "no-such-post").await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(body["code"], "post_not_found");
+    }
+
+    #[tokio::test]
+    async fn metrics_reflect_reconcile() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+        rss::handle_feed(SAMPLE_RSS, &mut store, &state)
+            .await
+            .unwrap();
+
+        let response = metrics(State(state)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_text(response).await;
+
+        assert!(body.contains("announcer_posts_new_total 1\n"));
+        assert!(body.contains("announcer_posts_updated_total 0\n"));
+        assert!(body.contains("announcer_slack_errors_total 0\n"));
+        assert!(body.contains("announcer_reconcile_duration_seconds_count 1\n"));
+    }
+
+    /// Serves `body` with an ETag after `delay`, answering 304 when the ETag
+    /// is sent back.
+    async fn feed_server(body: &'static str, delay: Duration) -> (Url, Arc<AtomicUsize>) {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let app = Router::new().route(
+            "/rss.xml",
+            get(move |headers: HeaderMap| {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(delay).await;
+                    if headers
+                        .get(header::IF_NONE_MATCH)
+                        .is_some_and(|etag| etag == "\"v1\"")
+                    {
+                        StatusCode::NOT_MODIFIED.into_response()
+                    } else {
+                        ([(header::ETAG, "\"v1\"")], body).into_response()
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (
+            Url::parse(&format!("http://{addr}/rss.xml")).unwrap(),
+            fetches,
+        )
+    }
+
+    /// Serves `body` as the feed.
+    async fn serve_feed(body: &'static str) -> Url {
+        let app = Router::new().route("/rss.xml", get(move || async move { body }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Url::parse(&format!("http://{addr}/rss.xml")).unwrap()
+    }
+
+    /// Fails to announce every post with "Broken" in its title.
+    struct BrokenPostNotifier;
+
+    #[async_trait]
+    impl Notifier for BrokenPostNotifier {
+        async fn post(&self, post: &rss::Post) -> Result<MessageIds, std::io::Error> {
+            if post.title.contains("Broken") {
+                return Err(std::io::Error::other("channel_not_found"));
+            }
+            Ok(single_message("1700000000.000100".to_string()))
+        }
+
+        async fn update(
+            &self,
+            _post: &rss::Post,
+            ids: &MessageIds,
+        ) -> Result<MessageIds, std::io::Error> {
+            Ok(ids.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_announces_a_post_and_then_updates_it() {
+        let mut state =
+            AppState::new(AppConfig::DryRun).with_feed_url(serve_feed(SAMPLE_RSS).await);
+        state.notifier = Arc::new(BrokenPostNotifier);
+        let mut store = InMemoryValkey::new();
+
+        let response = replay_post(&state, &mut store, "test-post").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let action: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(
+            action,
+            serde_json::json!({ "post": "test-post", "title": "Test Post", "action": "new" })
+        );
+        assert!(store.get("test-post").await.unwrap().is_some());
+
+        // Replaying sends the post again even though nothing changed.
+        let response = replay_post(&state, &mut store, "test-post").await;
+        let action: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(action["action"], "updated");
+        assert!(store.get("reconcile:lock").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn replaying_a_post_needs_the_admin_token() {
+        let replay = |headers: HeaderMap| {
+            let mut request = Request::post("/posts/test-post/replay")
+                .body(Body::empty())
+                .unwrap();
+            *request.headers_mut() = headers;
+            request
+        };
+        let state = AppState::new(AppConfig::DryRun).with_feed_url(unreachable_feed());
+        let response = build_app(state.clone())
+            .oneshot(replay(bearer("secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let app = build_app(state.with_admin_token(Some("secret".to_string())));
+        let response = app.clone().oneshot(replay(HeaderMap::new())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.oneshot(replay(bearer("secret"))).await.unwrap();
+        assert_ne!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn replaying_a_key_missing_from_the_feed_is_not_found() {
+        let state = AppState::new(AppConfig::DryRun).with_feed_url(serve_feed(SAMPLE_RSS).await);
+        let mut store = InMemoryValkey::new();
+
+        let response = replay_post(&state, &mut store, "no-such-post").await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(body["code"], "post_not_in_feed");
+        assert!(store.get("no-such-post").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn replaying_a_filtered_post_is_refused() {
+        let state = AppState::new(AppConfig::DryRun)
+            .with_feed_url(serve_feed(SAMPLE_RSS).await)
+            .with_link_hosts(LinkHostFilter::parse("example.com"));
+        let mut store = InMemoryValkey::new();
+
+        let response = replay_post(&state, &mut store, "test-post").await;
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(body["code"], "post_filtered");
+        assert!(store.get("test-post").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn replaying_with_the_breaker_open_is_unavailable() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        breaker.record_failure();
+        let mut state =
+            AppState::new(AppConfig::DryRun).with_feed_url(serve_feed(SAMPLE_RSS).await);
+        state.slack_breaker = Some(Arc::new(breaker));
+        let mut store = InMemoryValkey::new();
+
+        let response = replay_post(&state, &mut store, "test-post").await;
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(body["code"], "slack_unavailable");
+        assert!(store.get("test-post").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reconcile_fails_when_valkey_is_down() {
+        let state = AppState::new(AppConfig::DryRun).with_feed_url(serve_feed(SAMPLE_RSS).await);
+
+        let response = reconcile_feed(&state, &mut FailingValkey).await;
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn reconcile_fails_on_unparseable_feed() {
+        let state = AppState::new(AppConfig::DryRun)
+            .with_feed_url(serve_feed("<html>Not a feed</html>").await);
+
+        let response = reconcile_feed(&state, &mut InMemoryValkey::new()).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let metrics = body_text(metrics(State(state)).await.into_response()).await;
+        assert!(metrics.contains("announcer_reconcile_errors_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn reconcile_rejects_empty_feed() {
+        for body in ["", " \n\t "] {
+            let state = AppState::new(AppConfig::DryRun).with_feed_url(serve_feed(body).await);
+
+            let response = reconcile_feed(&state, &mut InMemoryValkey::new()).await;
+
+            assert_eq!(response.status(), StatusCode::BAD_GATEWAY, "{body:?}");
+            assert!(body_text(response).await.contains("empty body"));
+            let metrics = body_text(metrics(State(state)).await.into_response()).await;
+            assert!(metrics.contains("announcer_reconcile_errors_total 1\n"));
+        }
+    }
+
+    /// A feed URL nothing listens on, so any attempt to fetch it fails.
+    fn unreachable_feed() -> Url {
+        Url::parse("http://127.0.0.1:1/rss.xml").unwrap()
+    }
+
+    #[tokio::test]
+    async fn pushed_feed_is_reconciled_without_fetching() {
+        let mut state = AppState::new(AppConfig::DryRun).with_feed_url(unreachable_feed());
+        state.notifier = Arc::new(BrokenPostNotifier);
+        let mut store = InMemoryValkey::new();
+
+        let response = reconcile_pushed_feed(&state, &mut store, SAMPLE_RSS).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(summary["new"], 1);
+        assert!(store.get("test-post").await.unwrap().is_some());
+        assert_eq!(store.get("feed:validators").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn deployments_sharing_valkey_keep_their_keys_apart() {
+        let prod = AppState::new(AppConfig::DryRun).with_key_prefix("prod".to_string());
+        let dev = AppState::new(AppConfig::DryRun).with_key_prefix("dev".to_string());
+        let mut store = InMemoryValkey::new();
+        let new_posts = async |state: &AppState, store: &mut InMemoryValkey| {
+            let response = reconcile_pushed_feed(state, &mut keyed(state, store), SAMPLE_RSS).await;
+            let summary: serde_json::Value =
+                serde_json::from_str(&body_text(response).await).unwrap();
+            summary["new"].clone()
+        };
+
+        assert_eq!(new_posts(&prod, &mut store).await, 1);
+        assert_eq!(new_posts(&dev, &mut store).await, 1);
+        assert_eq!(new_posts(&prod, &mut store).await, 0);
+
+        let response = forget_post(&mut keyed(&dev, &mut store), "test-post").await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let keys = store.snapshot();
+        assert!(keys.contains_key("prod:test-post"), "{keys:?}");
+        assert!(!keys.contains_key("dev:test-post"), "{keys:?}");
+        assert!(!keys.contains_key("test-post"), "{keys:?}");
+
+        let response = list_posts(&mut keyed(&prod, &mut store)).await;
+        let posts: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(posts[0]["key"], "test-post");
+    }
+
+    fn pushed(content_type: &str) -> HeaderMap {
+        let mut headers = bearer("secret");
+        headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn reconcile_takes_feed_from_body_when_allowed() {
+        let state = AppState::new(AppConfig::DryRun)
+            .with_feed_url(unreachable_feed())
+            .with_allow_pushed_feed(true)
+            .with_admin_token(Some("secret".to_string()));
+
+        let response = reconcile(
+            State(state),
+            pushed("application/rss+xml; charset=utf-8"),
+            Bytes::from_static(SAMPLE_RSS.as_bytes()),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(summary["feed_title"], "NAIS Log");
+        assert_eq!(summary["new"], 1);
+    }
+
+    #[tokio::test]
+    async fn pushed_feed_is_refused_unless_allowed_and_typed() {
+        let state = AppState::new(AppConfig::DryRun)
+            .with_feed_url(unreachable_feed())
+            .with_admin_token(Some("secret".to_string()));
+        let body = Bytes::from_static(SAMPLE_RSS.as_bytes());
+
+        let response = reconcile(
+            State(state.clone()),
+            pushed("application/xml"),
+            body.clone(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = reconcile(
+            State(state.with_allow_pushed_feed(true)),
+            pushed("text/plain"),
+            body,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn pushed_feed_needs_the_admin_token() {
+        let state = AppState::new(AppConfig::DryRun)
+            .with_feed_url(unreachable_feed())
+            .with_allow_pushed_feed(true);
+        let body = Bytes::from_static(SAMPLE_RSS.as_bytes());
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, "application/xml".parse().unwrap());
+
+        let response = reconcile(State(state.clone()), headers.clone(), body.clone()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let state = state.with_admin_token(Some("secret".to_string()));
+        let response = reconcile(State(state.clone()), headers.clone(), body.clone()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        headers.insert(header::AUTHORIZATION, "Bearer wrong".parse().unwrap());
+        let response = reconcile(State(state), headers, body).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn failing_post_only_fails_part_of_the_reconcile() {
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Broken Post</title>
+      <link>https://nais.io/log#broken-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Content]]></encoded>
+    </item>
+    <item>
+      <title>Test Post</title>
+      <link>https://nais.io/log#test-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Content]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+        let mut state = AppState::new(AppConfig::DryRun).with_feed_url(serve_feed(feed).await);
+        state.notifier = Arc::new(BrokenPostNotifier);
+
+        let response = reconcile_feed(&state, &mut InMemoryValkey::new()).await;
+
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let summary: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(summary["new"], 1);
+        assert_eq!(summary["errors"], 1);
+        assert_eq!(summary["failures"][0]["post"], "broken-post");
+    }
+
+    #[tokio::test]
+    async fn slow_feed_times_out_with_504() {
+        let (url, _) = feed_server(SAMPLE_RSS, Duration::from_secs(5)).await;
+        let state = AppState::new(AppConfig::DryRun)
+            .with_feed_url(url)
+            .with_feed_timeout(Duration::from_millis(100));
+
+        let response = reconcile_feed(&state, &mut InMemoryValkey::new()).await;
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(
+            body_text(response)
+                .await
+                .contains("FEED_TIMEOUT_SECONDS (0.1s)")
+        );
+    }
+
+    #[tokio::test]
+    async fn unchanged_feed_is_not_handled_again() {
+        let (url, fetches) = feed_server(SAMPLE_RSS, Duration::ZERO).await;
+        let state = AppState::new(AppConfig::DryRun).with_feed_url(url);
+        let mut store = InMemoryValkey::new();
+
+        let first = reconcile_feed(&state, &mut store).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let second = reconcile_feed(&state, &mut store).await;
+        assert_eq!(second.status(), StatusCode::OK);
+
+        let summary: serde_json::Value = serde_json::from_str(&body_text(second).await).unwrap();
+        assert_eq!(summary["not_modified"], true);
+        assert_eq!(summary["total"], 0);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        // Every handled feed is timed, so a single observation means the
+        // second reconcile never reached `handle_feed`.
+        let metrics = body_text(metrics(State(state)).await.into_response()).await;
+        assert!(metrics.contains("announcer_reconcile_duration_seconds_count 1\n"));
+    }
+
+    #[tokio::test]
+    async fn posts_held_for_quiet_hours_are_announced_from_an_unchanged_feed() {
+        let (url, fetches) = feed_server(SAMPLE_RSS, Duration::ZERO).await;
+        let state_at = |hour: u32| {
+            AppState::new(AppConfig::DryRun)
+                .with_feed_url(url.clone())
+                .with_quiet_hours(Some(QuietHours::parse("22:00-07:00", "UTC").unwrap()))
+                .with_clock(FixedClock(
+                    Utc.with_ymd_and_hms(2024, 1, 2, hour, 0, 0).unwrap(),
+                ))
+        };
+        let mut store = InMemoryValkey::new();
+
+        let night = reconcile_feed(&state_at(23), &mut store).await;
+        let night: serde_json::Value = serde_json::from_str(&body_text(night).await).unwrap();
+        assert_eq!(night["pending"], 1);
+
+        let day = reconcile_feed(&state_at(12), &mut store).await;
+        let day: serde_json::Value = serde_json::from_str(&body_text(day).await).unwrap();
+        assert_eq!(day["not_modified"], false);
+        assert_eq!(day["new"], 1);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn deferred_posts_are_announced_from_an_unchanged_feed() {
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>First Post</title>
+      <link>https://nais.io/log#first-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Content]]></encoded>
+    </item>
+    <item>
+      <title>Second Post</title>
+      <link>https://nais.io/log#second-post</link>
+      <pubDate>Tue, 02 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Content]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+        let (url, fetches) = feed_server(feed, Duration::ZERO).await;
+        let state = AppState::new(AppConfig::DryRun)
+            .with_feed_url(url)
+            .with_max_new_posts(1);
+        let mut store = InMemoryValkey::new();
+
+        let first = reconcile_feed(&state, &mut store).await;
+        let first: serde_json::Value = serde_json::from_str(&body_text(first).await).unwrap();
+        assert_eq!(
+            (first["new"].as_u64(), first["deferred"].as_u64()),
+            (Some(1), Some(1))
+        );
+
+        let second = reconcile_feed(&state, &mut store).await;
+        let second: serde_json::Value = serde_json::from_str(&body_text(second).await).unwrap();
+        assert_eq!(second["not_modified"], false);
+        assert_eq!(second["new"], 1);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn posts_kept_back_by_the_breaker_are_tried_from_an_unchanged_feed() {
+        let (url, fetches) = feed_server(SAMPLE_RSS, Duration::ZERO).await;
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        breaker.record_failure();
+        let mut state = AppState::new(AppConfig::DryRun).with_feed_url(url);
+        state.slack_breaker = Some(Arc::new(breaker));
+        let mut store = InMemoryValkey::new();
+
+        for _ in 0..2 {
+            let response = reconcile_feed(&state, &mut store).await;
+            let summary: serde_json::Value =
+                serde_json::from_str(&body_text(response).await).unwrap();
+            assert_eq!(summary["not_modified"], false);
+            assert_eq!(summary["circuit_open"], 1);
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_reconciles_post_once() {
+        let (url, _) = feed_server(SAMPLE_RSS, Duration::from_millis(100)).await;
+        let state = AppState::new(AppConfig::DryRun).with_feed_url(url);
+        let mut first_store = InMemoryValkey::new();
+        let mut second_store = InMemoryValkey::new();
+
+        let (first, second) = tokio::join!(
+            reconcile_feed(&state, &mut first_store),
+            reconcile_feed(&state, &mut second_store),
+        );
+
+        let mut statuses = [first.status(), second.status()];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::CONFLICT]);
+        let metrics = body_text(metrics(State(state)).await.into_response()).await;
+        assert!(metrics.contains("announcer_posts_new_total 1\n"));
+    }
+
+    /// Serves `chat.postMessage`, recording the channel and text of every post.
+    async fn recording_slack() -> (String, Arc<std::sync::Mutex<Vec<(String, String)>>>) {
+        let posted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = posted.clone();
+        let app = Router::new().route(
+            "/chat.postMessage",
+            axum::routing::post(move |Json(body): Json<serde_json::Value>| {
+                recorded.lock().unwrap().push((
+                    body["channel"].as_str().unwrap().to_string(),
+                    body["text"].as_str().unwrap().to_string(),
+                ));
+                async { Json(serde_json::json!({ "ok": true, "ts": "1700000000.000100" })) }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), posted)
+    }
+
+    fn slack_state(api_base_url: &str) -> AppState {
+        AppState::new(AppConfig::Normal {
+            valkey: ValkeyConfig {
+                uri: "redis://localhost:6379".to_string(),
+                archive: Default::default(),
+            },
+            notifier: NotifierConfig::Slack(Box::new(SlackConfig {
+                token: "xoxb-test".to_string(),
+                channel_ids: vec!["C0000000000".to_string()],
+                max_attempts: 1,
+                use_blocks: false,
+                long_posts: LongPostMode::default(),
+                rate_limit: Duration::ZERO,
+                message_prefix: String::new(),
+                message_footer: String::new(),
+                api_base_url: config::parse_slack_api_base_url(api_base_url).unwrap(),
+                timeout: Duration::from_secs(10),
+                unfurl: false,
+                content_format: ContentFormat::Markdown,
+                layout: SlackLayout::Full,
+                react_on_update: false,
+                auto_join: false,
+                breaker_threshold: 0,
+                breaker_cooldown: Duration::ZERO,
+                threaded: false,
+                thread_root_ts: None,
+            })),
+        })
+    }
+
+    #[tokio::test]
+    async fn feed_sources_are_announced_in_their_own_channels_under_their_own_keys() {
+        let (slack, posted) = recording_slack().await;
+        let source = |url: Url, channel: Option<&str>, prefix: &str| FeedSource {
+            url,
+            slack_channel: channel.map(str::to_string),
+            key_prefix: prefix.to_string(),
+        };
+        let state = slack_state(&slack).with_feed_sources(vec![
+            source(serve_feed(SAMPLE_RSS).await, Some("C0123ABCD"), "log"),
+            source(serve_feed(SAMPLE_RSS).await, None, "mirror"),
+        ]);
+        let mut store = InMemoryValkey::new();
+
+        let response = reconcile_feed(&state, &mut store).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let outcomes: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(outcomes[0]["key_prefix"], "log");
+        assert_eq!(outcomes[0]["summary"]["new"], 1);
+        assert_eq!(outcomes[1]["key_prefix"], "mirror");
+        assert_eq!(outcomes[1]["summary"]["new"], 1);
+        let channels: Vec<String> = posted
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        assert_eq!(channels, ["C0123ABCD", "C0000000000"]);
+        let keys = store.snapshot();
+        assert!(keys.contains_key("log:test-post"), "{keys:?}");
+        assert!(keys.contains_key("mirror:test-post"), "{keys:?}");
+        assert!(!keys.contains_key("test-post"), "{keys:?}");
+    }
+
+    #[tokio::test]
+    async fn thread_root_is_kept_in_valkey_across_restarts() {
+        let (slack, posted) = recording_slack().await;
+        let feed = serve_feed(SAMPLE_RSS).await;
+        let threaded = || {
+            let mut config = slack_state(&slack).config;
+            if let AppConfig::Normal {
+                notifier: NotifierConfig::Slack(slack),
+                ..
+            } = &mut config
+            {
+                slack.threaded = true;
+            }
+            AppState::new(config).with_feed_url(feed.clone())
+        };
+        let mut store = InMemoryValkey::new();
+
+        let response = reconcile_feed(&threaded(), &mut store).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(posted.lock().unwrap().len(), 2, "the root, then the post");
+        assert_eq!(
+            store.get("slack:thread_roots").await.unwrap().as_deref(),
+            Some(r#"{"C0000000000":"1700000000.000100"}"#)
+        );
+
+        store.del("test-post").await.unwrap();
+        let response = reconcile_feed(&threaded(), &mut store).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let posted = posted.lock().unwrap();
+        assert_eq!(posted.len(), 3, "no second root after a restart");
+        assert_eq!(posted[2], posted[1]);
+    }
+
+    #[tokio::test]
+    async fn lone_feed_keeps_its_keys_unprefixed() {
+        let (slack, posted) = recording_slack().await;
+        let state = slack_state(&slack).with_feed_url(serve_feed(SAMPLE_RSS).await);
+        let mut store = InMemoryValkey::new();
+
+        let response = reconcile_feed(&state, &mut store).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(summary["new"], 1);
+        assert_eq!(posted.lock().unwrap()[0].0, "C0000000000");
+        assert!(store.snapshot().contains_key("test-post"));
+    }
+
+    #[tokio::test]
+    async fn reconcile_over_http_announces_and_archives_the_feed() {
+        let (slack, posted) = recording_slack().await;
+        let store = InMemoryValkey::new();
+        let mut state = slack_state(&slack).with_feed_url(serve_feed(SAMPLE_RSS).await);
+        state.valkey = Some(Store::InMemory(store.clone()));
+        let app = build_app(state);
+
+        let response = app
+            .clone()
+            .oneshot(Request::post("/reconcile").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key("x-request-id"));
+        let summary: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(summary["new"], 1);
+        assert_eq!(posted.lock().unwrap().len(), 1);
+        assert!(store.snapshot().contains_key("test-post"));
+
+        let response = app
+            .oneshot(Request::get("/posts").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let posts: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(posts[0]["key"], "test-post");
+    }
+
+    #[tokio::test]
+    async fn reconcile_reads_configured_feed_url() {
+        let (url, fetches) = feed_server(SAMPLE_RSS, Duration::ZERO).await;
+        let state = AppState::new(AppConfig::DryRun).with_feed_url(url);
+
+        let response = run_reconcile(&state).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(summary["feed_title"], "NAIS Log");
+        assert_eq!(summary["new"], 1);
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_running_request() {
+        let app = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "done"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, app, async {
+            stopped.await.ok();
+        }));
+
+        let request = tokio::spawn(reqwest::get(format!("http://{addr}/slow")));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stop.send(()).unwrap();
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "done");
+        server.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn summary_status_reflects_failures() {
+        let all_success = ReconcileSummary {
+            new: 1,
+            unchanged: 2,
+            ..ReconcileSummary::default()
+        };
+        let partial_failure = ReconcileSummary {
+            updated: 1,
+            errors: 1,
+            ..ReconcileSummary::default()
+        };
+        let all_failed = ReconcileSummary {
+            unchanged: 2,
+            errors: 2,
+            ..ReconcileSummary::default()
+        };
+
+        assert_eq!(summary_status(&all_success), StatusCode::OK);
+        assert_eq!(summary_status(&partial_failure), StatusCode::MULTI_STATUS);
+        assert_eq!(summary_status(&all_failed), StatusCode::BAD_GATEWAY);
+    }
 }
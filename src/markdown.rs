@@ -0,0 +1,204 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+pub(crate) fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("Hard-coded regex pattern should compile"))
+}
+
+/// Applies `f` to every line of `text` outside fenced code blocks, which are
+/// passed through untouched along with their fences.
+pub(crate) fn map_lines_outside_fences(text: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let mut in_fence = false;
+    text.split('\n')
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+                return line.to_string();
+            }
+            if in_fence {
+                return line.to_string();
+            }
+            f(line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Applies `f` to the parts of `text` outside inline code spans.
+pub(crate) fn map_outside_inline_code(text: &str, f: impl Fn(&str) -> String) -> String {
+    // Odd-numbered segments sit between backticks; an unmatched backtick
+    // leaves the trailing segment as plain text.
+    let segments: Vec<&str> = text.split('`').collect();
+    let balanced = segments.len() % 2 == 1;
+    segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let is_code = i % 2 == 1 && (balanced || i + 1 < segments.len());
+            if is_code {
+                segment.to_string()
+            } else {
+                f(segment)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("`")
+}
+
+static RE_HREF: OnceLock<Regex> = OnceLock::new();
+static RE_NUMERIC_ENTITY: OnceLock<Regex> = OnceLock::new();
+
+/// Turns the HTML some feeds put in their content into the markdown the
+/// notifiers format: paragraphs and line breaks become newlines, links,
+/// emphasis, code and lists their markdown counterparts, and any other tag
+/// is dropped, keeping its text.
+pub(crate) fn html_to_markdown(html: &str) -> String {
+    let mut out = String::new();
+    // Where the text of each open link starts in `out`, and where it goes.
+    let mut links: Vec<(usize, Option<String>)> = Vec::new();
+    let mut in_pre = false;
+    let mut skipping: Option<String> = None;
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        if skipping.is_none() {
+            push_text(&mut out, &rest[..start], in_pre);
+        }
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if let Some(skipped) = &skipping {
+            if closing && name == *skipped {
+                skipping = None;
+            }
+            continue;
+        }
+
+        match (name.as_str(), closing) {
+            ("script" | "style", false) => skipping = Some(name),
+            ("p" | "div" | "ul" | "ol" | "blockquote" | "table" | "tr", _) => end_block(&mut out),
+            ("br", _) => {
+                trim_trailing_spaces(&mut out);
+                out.push('\n');
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                end_block(&mut out);
+                out.push_str("## ");
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => end_block(&mut out),
+            ("li", false) => {
+                end_line(&mut out);
+                out.push_str("- ");
+            }
+            ("li", true) => end_line(&mut out),
+            ("strong" | "b", _) => out.push_str("**"),
+            ("em" | "i", _) => out.push('_'),
+            ("code", _) if !in_pre => out.push('`'),
+            ("pre", false) => {
+                end_block(&mut out);
+                out.push_str("```\n");
+                in_pre = true;
+            }
+            ("pre", true) => {
+                end_line(&mut out);
+                out.push_str("```");
+                end_block(&mut out);
+                in_pre = false;
+            }
+            ("a", false) => {
+                let href = regex(
+                    &RE_HREF,
+                    r#"(?i)\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#,
+                )
+                .captures(tag)
+                .and_then(|caps| caps.get(1).or(caps.get(2)).or(caps.get(3)))
+                .map(|href| decode_entities(href.as_str()));
+                links.push((out.len(), href));
+            }
+            ("a", true) => {
+                if let Some((at, Some(href))) = links.pop() {
+                    let text = out.split_off(at);
+                    out.push_str(&format!("[{}]({href})", text.trim()));
+                }
+            }
+            _ => {}
+        }
+    }
+    if skipping.is_none() {
+        push_text(&mut out, rest, in_pre);
+    }
+
+    out.trim().to_string()
+}
+
+/// Adds text found between tags. Outside `<pre>`, runs of whitespace are a
+/// single space, as a browser would show them.
+fn push_text(out: &mut String, text: &str, in_pre: bool) {
+    let text = decode_entities(text);
+    if in_pre {
+        out.push_str(&text);
+        return;
+    }
+    for c in text.chars() {
+        if !c.is_whitespace() {
+            out.push(c);
+        } else if !(out.is_empty() || out.ends_with(' ') || out.ends_with('\n')) {
+            out.push(' ');
+        }
+    }
+}
+
+fn trim_trailing_spaces(out: &mut String) {
+    out.truncate(out.trim_end_matches(' ').len());
+}
+
+/// Makes sure what comes next starts on a line of its own.
+fn end_line(out: &mut String) {
+    trim_trailing_spaces(out);
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Makes sure what comes next is separated by a blank line.
+fn end_block(out: &mut String) {
+    end_line(out);
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let text = regex(&RE_NUMERIC_ENTITY, r"&#(x[0-9a-fA-F]+|[0-9]+);").replace_all(
+        text,
+        |caps: &regex::Captures| {
+            let code = match caps[1].strip_prefix('x') {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => caps[1].parse().ok(),
+            };
+            code.and_then(char::from_u32)
+                .map_or_else(|| caps[0].to_string(), String::from)
+        },
+    );
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
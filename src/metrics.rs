@@ -0,0 +1,89 @@
+use crate::rss::ReconcileSummary;
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, Registry, TextEncoder};
+use std::time::Duration;
+
+/// Prometheus metrics for reconciles, kept in a registry of our own and
+/// served on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    posts_new: IntCounter,
+    posts_updated: IntCounter,
+    slack_errors: IntCounter,
+    reconcile_errors: IntCounter,
+    reconcile_duration: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let posts_new = IntCounter::new(
+            "announcer_posts_new_total",
+            "Posts announced to Slack for the first time",
+        )
+        .expect("Hard-coded metric should be valid");
+        let posts_updated = IntCounter::new(
+            "announcer_posts_updated_total",
+            "Slack messages updated after their post changed",
+        )
+        .expect("Hard-coded metric should be valid");
+        let slack_errors = IntCounter::new(
+            "announcer_slack_errors_total",
+            "Posts that could not be posted to or updated in Slack",
+        )
+        .expect("Hard-coded metric should be valid");
+        let reconcile_errors = IntCounter::new(
+            "announcer_reconcile_errors_total",
+            "Reconciles that failed on getting or reading the feed",
+        )
+        .expect("Hard-coded metric should be valid");
+        let reconcile_duration = Histogram::with_opts(HistogramOpts::new(
+            "announcer_reconcile_duration_seconds",
+            "Time spent handling the feed in a reconcile",
+        ))
+        .expect("Hard-coded metric should be valid");
+
+        for collector in [
+            Box::new(posts_new.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(posts_updated.clone()),
+            Box::new(slack_errors.clone()),
+            Box::new(reconcile_errors.clone()),
+            Box::new(reconcile_duration.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("Metric names should be unique");
+        }
+
+        Self {
+            registry,
+            posts_new,
+            posts_updated,
+            slack_errors,
+            reconcile_errors,
+            reconcile_duration,
+        }
+    }
+
+    pub fn observe_reconcile(&self, summary: &ReconcileSummary, duration: Duration) {
+        self.posts_new.inc_by(summary.new as u64);
+        self.posts_updated.inc_by(summary.updated as u64);
+        self.slack_errors.inc_by(summary.slack_errors as u64);
+        self.reconcile_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Counts a reconcile that stopped before any post was looked at,
+    /// because the feed couldn't be fetched or read.
+    pub fn observe_reconcile_error(&self) {
+        self.reconcile_errors.inc();
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Encoding metrics to a Vec should not fail");
+        String::from_utf8(buffer).expect("Prometheus text format is UTF-8")
+    }
+}
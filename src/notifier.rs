@@ -0,0 +1,157 @@
+use crate::rss::Post;
+use async_trait::async_trait;
+use chrono::Utc;
+use std::{
+    collections::BTreeMap,
+    io::Error,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Ids of the messages a post was announced as, keyed by channel. Notifiers
+/// with a single destination key their message by [`DEFAULT_CHANNEL`].
+pub type MessageIds = BTreeMap<String, String>;
+
+/// Key for the message in a notifier's only (or first) destination. Archive
+/// entries written before posts could go to several channels read back as it.
+pub const DEFAULT_CHANNEL: &str = "";
+
+/// Prefix of the keys under which a notifier keeps the id a chat service
+/// resolved a configured channel to, next to the message ids, when the two
+/// differ. Slack accepts a channel name when posting, but only the id when
+/// editing.
+pub const RESOLVED_CHANNEL_PREFIX: &str = "channel:";
+
+/// Key for the id `channel` resolved to, see [`RESOLVED_CHANNEL_PREFIX`].
+pub fn resolved_channel_key(channel: &str) -> String {
+    format!("{RESOLVED_CHANNEL_PREFIX}{channel}")
+}
+
+/// Wraps the id of a notifier's only message.
+pub fn single_message(id: String) -> MessageIds {
+    MessageIds::from([(DEFAULT_CHANNEL.to_string(), id)])
+}
+
+/// A chat service that posts from the feed are announced to.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Announces a new post, returning the ids of the messages so they can be
+    /// updated later. Fails only if the post went out nowhere.
+    async fn post(&self, post: &Post) -> Result<MessageIds, Error>;
+
+    /// Brings the messages in `ids` up to date with `post`, returning the ids
+    /// the messages should be tracked by from now on.
+    async fn update(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error>;
+
+    /// Announces the changes to a post as a reply under each of the messages
+    /// in `ids`, returning the ids of the replies. Notifiers without threads
+    /// update the messages instead.
+    async fn reply(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        self.update(post, ids).await
+    }
+
+    /// Announces several new posts in a single message listing a link to
+    /// each. Notifiers without a digest format of their own announce it as
+    /// a post made up of the list.
+    async fn post_digest(&self, posts: &[&Post]) -> Result<MessageIds, Error> {
+        self.post(&digest_post(posts)).await
+    }
+
+    /// The messages every post is threaded under, by channel, to be kept
+    /// across restarts. Empty for notifiers that don't thread posts.
+    async fn thread_roots(&self) -> MessageIds {
+        MessageIds::new()
+    }
+
+    /// Takes up the thread roots kept from an earlier run, for the channels
+    /// that have none yet.
+    async fn restore_thread_roots(&self, _roots: &MessageIds) {}
+}
+
+/// A post listing `posts` as markdown links, linking itself to the page the
+/// first of them is on.
+pub fn digest_post(posts: &[&Post]) -> Post {
+    let link = posts
+        .first()
+        .and_then(|post| post.link.split('#').next())
+        .unwrap_or_default()
+        .to_string();
+    Post {
+        title: format!("{} new posts", posts.len()),
+        link,
+        pub_date: posts
+            .iter()
+            .map(|post| post.pub_date)
+            .max()
+            .unwrap_or_else(Utc::now),
+        content: posts
+            .iter()
+            .map(|post| format!("- [{}]({})", post.title, post.link))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        categories: Vec::new(),
+        guid: None,
+    }
+}
+
+/// Waits `delay` before every message after the first, for workspaces that
+/// want announcements spaced out beyond what the rate limiter does.
+pub struct PacedNotifier<'a> {
+    inner: &'a dyn Notifier,
+    delay: Duration,
+    sent: AtomicBool,
+}
+
+impl<'a> PacedNotifier<'a> {
+    pub fn new(inner: &'a dyn Notifier, delay: Duration) -> Self {
+        Self {
+            inner,
+            delay,
+            sent: AtomicBool::new(false),
+        }
+    }
+
+    async fn pace(&self) {
+        if self.sent.swap(true, Ordering::Relaxed) {
+            tokio::time::sleep(self.delay).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for PacedNotifier<'_> {
+    async fn post(&self, post: &Post) -> Result<MessageIds, Error> {
+        self.pace().await;
+        self.inner.post(post).await
+    }
+
+    async fn update(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        self.pace().await;
+        self.inner.update(post, ids).await
+    }
+
+    async fn reply(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        self.pace().await;
+        self.inner.reply(post, ids).await
+    }
+
+    async fn post_digest(&self, posts: &[&Post]) -> Result<MessageIds, Error> {
+        self.pace().await;
+        self.inner.post_digest(posts).await
+    }
+}
+
+/// Announces nothing, for working out what a reconcile would do. Messages
+/// keep whatever ids they already had.
+pub struct PreviewNotifier;
+
+#[async_trait]
+impl Notifier for PreviewNotifier {
+    async fn post(&self, _post: &Post) -> Result<MessageIds, Error> {
+        Ok(MessageIds::new())
+    }
+
+    async fn update(&self, _post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        Ok(ids.clone())
+    }
+}
@@ -0,0 +1,262 @@
+use reqwest::Url;
+use serde_json::{Value, json};
+use std::{
+    fmt::Debug,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tracing::{
+    Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    warn,
+};
+use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
+
+/// Finished spans waiting to be sent; any beyond this are dropped rather
+/// than held on to while the collector is away.
+const QUEUE_SIZE: usize = 2048;
+
+/// The most spans sent in one export request.
+const BATCH_SIZE: usize = 512;
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `SPAN_KIND_INTERNAL` of the OTLP protocol.
+const SPAN_KIND_INTERNAL: u8 = 1;
+
+/// Exports the spans of this crate as OTLP traces, over HTTP with JSON
+/// bodies, to `<endpoint>/v1/traces`. Spans of other crates are left out,
+/// which also keeps the export requests themselves from being traced.
+pub struct OtlpLayer {
+    spans: mpsc::Sender<FinishedSpan>,
+}
+
+impl OtlpLayer {
+    /// Starts sending spans to `endpoint` from a background task, so it has
+    /// to be called from within a Tokio runtime.
+    pub fn new(endpoint: &Url, service_name: &str) -> Self {
+        let (spans, queue) = mpsc::channel(QUEUE_SIZE);
+        let url = traces_url(endpoint);
+        let resource = json!({
+            "attributes": [attribute("service.name", &AttributeValue::Str(service_name.to_string()))],
+        });
+        tokio::spawn(export(url, resource, queue));
+        Self { spans }
+    }
+}
+
+/// Where traces go under the OTLP base `endpoint`.
+fn traces_url(endpoint: &Url) -> String {
+    format!("{}/v1/traces", endpoint.as_str().trim_end_matches('/'))
+}
+
+async fn export(url: String, resource: Value, mut queue: mpsc::Receiver<FinishedSpan>) {
+    let client = reqwest::Client::builder()
+        .timeout(EXPORT_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while queue.recv_many(&mut batch, BATCH_SIZE).await > 0 {
+        let body = json!({
+            "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{
+                    "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                    "spans": batch.iter().map(FinishedSpan::to_json).collect::<Vec<_>>(),
+                }],
+            }],
+        });
+        let sent = batch.len();
+        batch.clear();
+        if let Err(err) = client
+            .post(&url)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+        {
+            warn!(spans = sent, error = %err, "Failed exporting traces");
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum AttributeValue {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+fn attribute(key: &str, value: &AttributeValue) -> Value {
+    let value = match value {
+        AttributeValue::Str(value) => json!({ "stringValue": value }),
+        // OTLP's JSON mapping carries 64-bit integers as strings.
+        AttributeValue::Int(value) => json!({ "intValue": value.to_string() }),
+        AttributeValue::Bool(value) => json!({ "boolValue": value }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// A span while it is open, kept in its extensions.
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    name: &'static str,
+    start: SystemTime,
+    attributes: Vec<(&'static str, AttributeValue)>,
+}
+
+struct FinishedSpan {
+    data: SpanData,
+    end: SystemTime,
+}
+
+impl FinishedSpan {
+    fn to_json(&self) -> Value {
+        let data = &self.data;
+        let mut span = json!({
+            "traceId": hex(&data.trace_id),
+            "spanId": hex(&data.span_id),
+            "name": data.name,
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": unix_nanos(data.start),
+            "endTimeUnixNano": unix_nanos(self.end),
+            "attributes": data
+                .attributes
+                .iter()
+                .map(|(key, value)| attribute(key, value))
+                .collect::<Vec<_>>(),
+        });
+        if let Some(parent) = &data.parent_span_id {
+            span["parentSpanId"] = Value::String(hex(parent));
+        }
+        span
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Collects span fields as attributes, replacing any recorded before.
+struct Fields<'a>(&'a mut Vec<(&'static str, AttributeValue)>);
+
+impl Fields<'_> {
+    fn set(&mut self, field: &Field, value: AttributeValue) {
+        match self.0.iter_mut().find(|(key, _)| *key == field.name()) {
+            Some((_, old)) => *old = value,
+            None => self.0.push((field.name(), value)),
+        }
+    }
+}
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, AttributeValue::Str(value.to_string()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field, AttributeValue::Int(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match i64::try_from(value) {
+            Ok(value) => self.set(field, AttributeValue::Int(value)),
+            Err(_) => self.set(field, AttributeValue::Str(value.to_string())),
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.set(field, AttributeValue::Bool(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.set(field, AttributeValue::Str(format!("{value:?}")));
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        if !metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        // Spans of other crates in between are skipped, so the trace links
+        // up to the nearest exported ancestor.
+        let parent = span.scope().skip(1).find_map(|ancestor| {
+            ancestor
+                .extensions()
+                .get::<SpanData>()
+                .map(|parent| (parent.trace_id, parent.span_id))
+        });
+        let mut data = SpanData {
+            trace_id: parent.map_or_else(rand::random, |(trace_id, _)| trace_id),
+            span_id: rand::random(),
+            parent_span_id: parent.map(|(_, span_id)| span_id),
+            name: metadata.name(),
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+        };
+        attrs.record(&mut Fields(&mut data.attributes));
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+            values.record(&mut Fields(&mut data.attributes));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        // A full queue means the collector can't keep up; losing spans beats
+        // holding up the app.
+        let _ = self.spans.try_send(FinishedSpan {
+            data,
+            end: SystemTime::now(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::traces_url;
+    use reqwest::Url;
+
+    #[test]
+    fn traces_go_under_the_endpoint() {
+        for endpoint in ["http://collector:4318", "http://collector:4318/"] {
+            assert_eq!(
+                traces_url(&Url::parse(endpoint).unwrap()),
+                "http://collector:4318/v1/traces"
+            );
+        }
+        assert_eq!(
+            traces_url(&Url::parse("https://otel.nais.io/otlp/").unwrap()),
+            "https://otel.nais.io/otlp/v1/traces"
+        );
+    }
+}
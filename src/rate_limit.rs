@@ -0,0 +1,88 @@
+use std::time::Duration;
+use tokio::{sync::Mutex, time::Instant};
+
+/// Token bucket spacing out calls to an API: it holds at most `burst` tokens,
+/// gains one every `interval` and every call takes one, waiting when there
+/// are none left.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    burst: u32,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// A limiter letting `burst` calls through at once and one every
+    /// `interval` after that. A zero interval never waits.
+    pub fn new(interval: Duration, burst: u32) -> Self {
+        let burst = burst.max(1);
+        Self {
+            interval,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(burst),
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a call may go out and takes its token. Callers queue on
+    /// the bucket, so they get through in the order they arrived.
+    pub async fn acquire(&self) {
+        if self.interval.is_zero() {
+            return;
+        }
+
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        let earned = (now - bucket.refilled_at).as_secs_f64() / self.interval.as_secs_f64();
+        bucket.tokens = (bucket.tokens + earned).min(f64::from(self.burst));
+        bucket.refilled_at = now;
+
+        if bucket.tokens < 1.0 {
+            tokio::time::sleep(self.interval.mul_f64(1.0 - bucket.tokens)).await;
+            bucket.tokens = 1.0;
+            bucket.refilled_at = Instant::now();
+        }
+        bucket.tokens -= 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn lets_a_burst_through_then_spaces_calls() {
+        let limiter = RateLimiter::new(Duration::from_secs(1), 2);
+        let start = Instant::now();
+
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_while_idle() {
+        let limiter = RateLimiter::new(Duration::from_secs(1), 1);
+        limiter.acquire().await;
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        let start = Instant::now();
+        limiter.acquire().await;
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -1,126 +1,1335 @@
-use crate::config::ValkeyConfig;
+use crate::config::{ValkeyConfig, ValkeyTopology};
 use async_trait::async_trait;
-use redis::{Commands, Connection, ErrorKind, RedisError, RedisResult};
-use std::collections::HashMap;
-use tokio::task;
-use tracing::error;
+use deadpool::managed::{self, Metrics, Object, RecycleResult};
+use redis::{
+    AsyncCommands, ErrorKind, RedisConnectionInfo, RedisError, RedisFuture, RedisResult, Value,
+    aio::{ConnectionLike, MultiplexedConnection},
+    cluster::ClusterClient,
+    cluster_async::ClusterConnection,
+    cluster_routing::{RoutingInfo, SingleNodeRoutingInfo},
+    sentinel::{SentinelClient, SentinelNodeConnectionInfo, SentinelServerType},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
 
 #[async_trait]
 pub trait ValkeyClient: Send {
     async fn get(&mut self, key: &str) -> RedisResult<Option<String>>;
+    /// Gets several keys in one go, returning their values in the same order.
+    async fn get_many(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>>;
     async fn set(&mut self, key: &str, value: &str) -> RedisResult<()>;
+    async fn set_with_ttl(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<()>;
+    /// Sets every key in `entries` in one round-trip, each expiring after
+    /// `ttl` when given.
+    async fn set_many(
+        &mut self,
+        entries: &[(String, String)],
+        ttl: Option<Duration>,
+    ) -> RedisResult<()>;
+    /// Sets `key` only if it doesn't exist yet, returning whether it did.
+    async fn set_if_absent(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<bool>;
+    /// Deletes `key`, returning whether it existed.
+    async fn del(&mut self, key: &str) -> RedisResult<bool>;
+    /// Deletes all of `keys` in one go, returning how many existed.
+    async fn del_many(&mut self, keys: &[String]) -> RedisResult<usize>;
+    /// Deletes `key`, but only while it still holds `value`.
+    async fn delete_if_equals(&mut self, key: &str, value: &str) -> RedisResult<()>;
+    /// Every key matching the glob-style `pattern`, in no particular order.
+    async fn scan_keys(&mut self, pattern: &str) -> RedisResult<Vec<String>>;
+    async fn ping(&mut self) -> RedisResult<()>;
 }
 
+/// How many keys each `SCAN` call asks for, keeping every call short so
+/// Valkey can serve others in between.
+const SCAN_PAGE_SIZE: usize = 100;
+
+/// Where the pool gets its connections from.
+enum Backend {
+    Single(redis::Client),
+    /// Asking the sentinels for the master takes `&mut`, so connections being
+    /// opened at the same time wait their turn.
+    Sentinel(tokio::sync::Mutex<SentinelClient>),
+    Cluster(ClusterClient),
+}
+
+impl Backend {
+    fn new(topology: ValkeyTopology) -> RedisResult<Self> {
+        match topology {
+            ValkeyTopology::Single(uri) => redis::Client::open(uri).map(Backend::Single),
+            ValkeyTopology::Sentinel {
+                sentinels,
+                service,
+                username,
+                password,
+            } => {
+                let node = SentinelNodeConnectionInfo {
+                    tls_mode: None,
+                    redis_connection_info: Some(RedisConnectionInfo {
+                        username,
+                        password,
+                        ..RedisConnectionInfo::default()
+                    }),
+                };
+                SentinelClient::build(sentinels, service, Some(node), SentinelServerType::Master)
+                    .map(|client| Backend::Sentinel(tokio::sync::Mutex::new(client)))
+            }
+            ValkeyTopology::Cluster(nodes) => ClusterClient::new(nodes).map(Backend::Cluster),
+        }
+    }
+}
+
+/// A connection to a single node, or to a cluster routing each command to
+/// the node owning its keys.
+enum ValkeyConnection {
+    Node(MultiplexedConnection),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for ValkeyConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a redis::Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            ValkeyConnection::Node(conn) => conn.req_packed_command(cmd),
+            ValkeyConnection::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a redis::Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            ValkeyConnection::Node(conn) => conn.req_packed_commands(cmd, offset, count),
+            ValkeyConnection::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            ValkeyConnection::Node(conn) => conn.get_db(),
+            ValkeyConnection::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
+
+/// Opens connections for the pool, and checks they still answer before
+/// handing them out again.
+struct ConnectionManager {
+    backend: Backend,
+}
+
+impl managed::Manager for ConnectionManager {
+    type Type = ValkeyConnection;
+    type Error = RedisError;
+
+    async fn create(&self) -> RedisResult<ValkeyConnection> {
+        match &self.backend {
+            Backend::Single(client) => client
+                .get_multiplexed_async_connection()
+                .await
+                .map(ValkeyConnection::Node),
+            Backend::Sentinel(client) => client
+                .lock()
+                .await
+                .get_async_connection()
+                .await
+                .map(ValkeyConnection::Node),
+            Backend::Cluster(client) => client
+                .get_async_connection()
+                .await
+                .map(ValkeyConnection::Cluster),
+        }
+    }
+
+    async fn recycle(&self, conn: &mut ValkeyConnection, _: &Metrics) -> RecycleResult<RedisError> {
+        redis::cmd("PING").query_async::<String>(conn).await?;
+        Ok(())
+    }
+}
+
+/// Pause before the second attempt at reaching Valkey, doubled for every
+/// attempt after that.
+const CONNECT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Pings `store` until it answers, at most `attempts` times with a growing
+/// pause in between, and tells whether it did. The pool behind
+/// [`ValkeyStore`] only connects once it's used, so this is what finds out
+/// whether Valkey can be reached at all.
+pub async fn wait_until_reachable(store: &mut dyn ValkeyClient, attempts: u32) -> bool {
+    for attempt in 1..=attempts {
+        match store.ping().await {
+            Ok(()) => {
+                if attempt > 1 {
+                    info!(attempt, "Reached Valkey");
+                }
+                return true;
+            }
+            Err(err) if attempt < attempts => {
+                let delay = CONNECT_BACKOFF * 2u32.pow(attempt - 1);
+                warn!(
+                    attempt,
+                    error = %err,
+                    delay_ms = delay.as_millis() as u64,
+                    "Valkey not reachable yet, retrying"
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(err) => {
+                error!(attempts, error = %err, "Valkey still not reachable, giving up");
+            }
+        }
+    }
+    false
+}
+
+/// Valkey behind a pool of async connections, each call checking one out.
+/// Clones share the pool.
+#[derive(Clone)]
 pub struct ValkeyStore {
-    connection: Option<Connection>,
+    pool: managed::Pool<ConnectionManager>,
 }
 
 impl ValkeyStore {
+    /// Sets up a connection pool for `config`. Connections are only opened
+    /// once they're needed, so this fails just on a malformed URI.
     pub fn connect(config: &ValkeyConfig) -> Option<Self> {
-        match redis::Client::open(config.uri.clone()) {
-            Ok(client) => match client.get_connection() {
-                Ok(connection) => Some(Self {
-                    connection: Some(connection),
-                }),
-                Err(err) => {
-                    error!("Opening connection to Valkey failed: {err}");
-                    None
-                }
-            },
+        let backend = match config
+            .topology()
+            .map_err(|err| err.to_string())
+            .and_then(|topology| Backend::new(topology).map_err(|err| err.to_string()))
+        {
+            Ok(backend) => backend,
             Err(err) => {
                 error!("Connecting to Valkey failed: {err}");
+                return None;
+            }
+        };
+        match managed::Pool::builder(ConnectionManager { backend }).build() {
+            Ok(pool) => Some(Self { pool }),
+            Err(err) => {
+                error!("Creating Valkey connection pool failed: {err}");
                 None
             }
         }
     }
+
+    async fn connection(&self) -> RedisResult<Object<ConnectionManager>> {
+        self.pool.get().await.map_err(|err| {
+            RedisError::from((
+                ErrorKind::IoError,
+                "Valkey connection not available",
+                err.to_string(),
+            ))
+        })
+    }
 }
 
 #[async_trait]
 impl ValkeyClient for ValkeyStore {
     async fn get(&mut self, key: &str) -> RedisResult<Option<String>> {
-        let key = key.to_owned();
-        let mut conn = match self.connection.take() {
-            Some(c) => c,
-            None => {
-                return Err(RedisError::from((
-                    ErrorKind::IoError,
-                    "Valkey connection not available",
-                )))
-            }
-        };
+        self.connection().await?.get(key).await
+    }
 
-        let result = task::spawn_blocking(move || {
-            let res = conn.get(key);
-            (conn, res)
-        })
-        .await;
+    async fn get_many(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>> {
+        // MGET needs at least one key.
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.connection().await?;
+        redis::cmd("MGET")
+            .arg(keys)
+            .query_async::<Vec<Option<String>>>(&mut *conn)
+            .await
+    }
+
+    async fn set(&mut self, key: &str, value: &str) -> RedisResult<()> {
+        self.connection().await?.set(key, value).await
+    }
 
-        match result {
-            Ok((conn, res)) => {
-                self.connection = Some(conn);
-                res
+    async fn set_with_ttl(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<()> {
+        self.connection()
+            .await?
+            .set_ex(key, value, ttl.as_secs())
+            .await
+    }
+
+    async fn set_many(
+        &mut self,
+        entries: &[(String, String)],
+        ttl: Option<Duration>,
+    ) -> RedisResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.connection().await?;
+        let mut pipe = redis::pipe();
+        // A cluster can't run a transaction over keys in different slots, so
+        // there the writes only share the round-trip.
+        if matches!(*conn, ValkeyConnection::Node(_)) {
+            pipe.atomic();
+        }
+        for (key, value) in entries {
+            match ttl {
+                Some(ttl) => pipe.set_ex(key, value, ttl.as_secs()),
+                None => pipe.set(key, value),
             }
-            Err(e) => Err(RedisError::from((
-                ErrorKind::IoError,
-                "spawn_blocking join error",
-                e.to_string(),
-            ))),
+            .ignore();
         }
+        pipe.query_async::<()>(&mut *conn).await
     }
 
-    async fn set(&mut self, key: &str, value: &str) -> RedisResult<()> {
-        let key = key.to_owned();
-        let value = value.to_owned();
-        let mut conn = match self.connection.take() {
-            Some(c) => c,
-            None => {
-                return Err(RedisError::from((
-                    ErrorKind::IoError,
-                    "Valkey connection not available",
-                )))
+    async fn set_if_absent(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<bool> {
+        let mut conn = self.connection().await?;
+        redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl.as_secs())
+            .query_async::<Option<String>>(&mut *conn)
+            .await
+            .map(|reply| reply.is_some())
+    }
+
+    async fn del(&mut self, key: &str) -> RedisResult<bool> {
+        self.connection()
+            .await?
+            .del::<_, usize>(key)
+            .await
+            .map(|deleted| deleted > 0)
+    }
+
+    async fn del_many(&mut self, keys: &[String]) -> RedisResult<usize> {
+        // DEL needs at least one key.
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self.connection().await?;
+        redis::cmd("DEL")
+            .arg(keys)
+            .query_async::<usize>(&mut *conn)
+            .await
+    }
+
+    async fn delete_if_equals(&mut self, key: &str, value: &str) -> RedisResult<()> {
+        let mut conn = self.connection().await?;
+        redis::Script::new(
+            r"if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) else return 0 end",
+        )
+        .key(key)
+        .arg(value)
+        .invoke_async::<i64>(&mut *conn)
+        .await
+        .map(|_| ())
+    }
+
+    /// Walks the keyspace a page at a time. A cluster keeps a keyspace per
+    /// primary, so each of them is walked in turn.
+    async fn scan_keys(&mut self, pattern: &str) -> RedisResult<Vec<String>> {
+        let mut conn = self.connection().await?;
+        let mut keys = Vec::new();
+        match &mut *conn {
+            ValkeyConnection::Cluster(cluster) => {
+                let slots = cluster
+                    .route_command(
+                        redis::cmd("CLUSTER").arg("SLOTS"),
+                        RoutingInfo::SingleNode(SingleNodeRoutingInfo::Random),
+                    )
+                    .await?;
+                for (host, port) in cluster_primaries(&slots)? {
+                    let node =
+                        RoutingInfo::SingleNode(SingleNodeRoutingInfo::ByAddress { host, port });
+                    let mut cursor = 0u64;
+                    loop {
+                        let page = cluster
+                            .route_command(&scan_page(cursor, pattern), node.clone())
+                            .await?;
+                        let (next, page) = redis::from_redis_value::<(u64, Vec<String>)>(&page)?;
+                        keys.extend(page);
+                        if next == 0 {
+                            break;
+                        }
+                        cursor = next;
+                    }
+                }
+            }
+            node => {
+                let mut cursor = 0u64;
+                loop {
+                    let (next, page) = scan_page(cursor, pattern)
+                        .query_async::<(u64, Vec<String>)>(&mut *node)
+                        .await?;
+                    keys.extend(page);
+                    if next == 0 {
+                        break;
+                    }
+                    cursor = next;
+                }
             }
+        }
+        // SCAN may hand out a key more than once while the keyspace changes.
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    async fn ping(&mut self) -> RedisResult<()> {
+        let mut conn = self.connection().await?;
+        redis::cmd("PING")
+            .query_async::<String>(&mut *conn)
+            .await
+            .map(|_| ())
+    }
+}
+
+/// One `SCAN` call, picking up at `cursor`.
+fn scan_page(cursor: u64, pattern: &str) -> redis::Cmd {
+    let mut cmd = redis::cmd("SCAN");
+    cmd.arg(cursor)
+        .arg("MATCH")
+        .arg(pattern)
+        .arg("COUNT")
+        .arg(SCAN_PAGE_SIZE);
+    cmd
+}
+
+/// The address of every primary in a `CLUSTER SLOTS` reply, once each even
+/// when it serves several slot ranges.
+fn cluster_primaries(slots: &Value) -> RedisResult<Vec<(String, u16)>> {
+    let invalid = || RedisError::from((ErrorKind::TypeError, "Unexpected CLUSTER SLOTS reply"));
+    let Value::Array(ranges) = slots else {
+        return Err(invalid());
+    };
+    let mut primaries = Vec::new();
+    for range in ranges {
+        let Value::Array(range) = range else {
+            return Err(invalid());
+        };
+        let Some(Value::Array(primary)) = range.get(2) else {
+            return Err(invalid());
+        };
+        let (Some(host), Some(port)) = (primary.first(), primary.get(1)) else {
+            return Err(invalid());
         };
+        let address = (
+            redis::from_redis_value::<String>(host)?,
+            redis::from_redis_value::<u16>(port)?,
+        );
+        if !primaries.contains(&address) {
+            primaries.push(address);
+        }
+    }
+    Ok(primaries)
+}
 
-        let result = task::spawn_blocking(move || {
-            let res = conn.set(key, value);
-            (conn, res)
-        })
-        .await;
+/// Passes reads through to the store it wraps and drops every write, for
+/// previewing a reconcile against live data. Taking a lock always succeeds.
+pub struct ReadOnlyValkey<'a>(pub &'a mut dyn ValkeyClient);
 
-        match result {
-            Ok((conn, res)) => {
-                self.connection = Some(conn);
-                res
-            }
-            Err(e) => Err(RedisError::from((
-                ErrorKind::IoError,
-                "spawn_blocking join error",
-                e.to_string(),
-            ))),
+#[async_trait]
+impl ValkeyClient for ReadOnlyValkey<'_> {
+    async fn get(&mut self, key: &str) -> RedisResult<Option<String>> {
+        self.0.get(key).await
+    }
+
+    async fn get_many(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>> {
+        self.0.get_many(keys).await
+    }
+
+    async fn set(&mut self, _key: &str, _value: &str) -> RedisResult<()> {
+        Ok(())
+    }
+
+    async fn set_with_ttl(&mut self, _key: &str, _value: &str, _ttl: Duration) -> RedisResult<()> {
+        Ok(())
+    }
+
+    async fn set_many(
+        &mut self,
+        _entries: &[(String, String)],
+        _ttl: Option<Duration>,
+    ) -> RedisResult<()> {
+        Ok(())
+    }
+
+    async fn set_if_absent(
+        &mut self,
+        _key: &str,
+        _value: &str,
+        _ttl: Duration,
+    ) -> RedisResult<bool> {
+        Ok(true)
+    }
+
+    async fn del(&mut self, key: &str) -> RedisResult<bool> {
+        Ok(self.0.get(key).await?.is_some())
+    }
+
+    async fn del_many(&mut self, keys: &[String]) -> RedisResult<usize> {
+        Ok(self.0.get_many(keys).await?.into_iter().flatten().count())
+    }
+
+    async fn delete_if_equals(&mut self, _key: &str, _value: &str) -> RedisResult<()> {
+        Ok(())
+    }
+
+    async fn scan_keys(&mut self, pattern: &str) -> RedisResult<Vec<String>> {
+        self.0.scan_keys(pattern).await
+    }
+
+    async fn ping(&mut self) -> RedisResult<()> {
+        self.0.ping().await
+    }
+}
+
+/// Keeps the keys of one feed source apart from the others sharing the
+/// store, by putting `prefix` and a colon in front of every key. Keys come
+/// back from scans without it. An empty prefix passes keys through as they
+/// are, for a single feed.
+pub struct PrefixedValkey<'a> {
+    prefix: &'a str,
+    store: &'a mut dyn ValkeyClient,
+}
+
+impl<'a> PrefixedValkey<'a> {
+    pub fn new(prefix: &'a str, store: &'a mut dyn ValkeyClient) -> Self {
+        Self { prefix, store }
+    }
+
+    fn key(&self, key: &str) -> String {
+        if self.prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}:{key}", self.prefix)
+        }
+    }
+
+    fn keys(&self, keys: &[String]) -> Vec<String> {
+        keys.iter().map(|key| self.key(key)).collect()
+    }
+}
+
+#[async_trait]
+impl ValkeyClient for PrefixedValkey<'_> {
+    async fn get(&mut self, key: &str) -> RedisResult<Option<String>> {
+        let key = self.key(key);
+        self.store.get(&key).await
+    }
+
+    async fn get_many(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>> {
+        let keys = self.keys(keys);
+        self.store.get_many(&keys).await
+    }
+
+    async fn set(&mut self, key: &str, value: &str) -> RedisResult<()> {
+        let key = self.key(key);
+        self.store.set(&key, value).await
+    }
+
+    async fn set_with_ttl(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<()> {
+        let key = self.key(key);
+        self.store.set_with_ttl(&key, value, ttl).await
+    }
+
+    async fn set_many(
+        &mut self,
+        entries: &[(String, String)],
+        ttl: Option<Duration>,
+    ) -> RedisResult<()> {
+        let entries: Vec<(String, String)> = entries
+            .iter()
+            .map(|(key, value)| (self.key(key), value.clone()))
+            .collect();
+        self.store.set_many(&entries, ttl).await
+    }
+
+    async fn set_if_absent(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<bool> {
+        let key = self.key(key);
+        self.store.set_if_absent(&key, value, ttl).await
+    }
+
+    async fn del(&mut self, key: &str) -> RedisResult<bool> {
+        let key = self.key(key);
+        self.store.del(&key).await
+    }
+
+    async fn del_many(&mut self, keys: &[String]) -> RedisResult<usize> {
+        let keys = self.keys(keys);
+        self.store.del_many(&keys).await
+    }
+
+    async fn delete_if_equals(&mut self, key: &str, value: &str) -> RedisResult<()> {
+        let key = self.key(key);
+        self.store.delete_if_equals(&key, value).await
+    }
+
+    async fn scan_keys(&mut self, pattern: &str) -> RedisResult<Vec<String>> {
+        let pattern = self.key(pattern);
+        let keys = self.store.scan_keys(&pattern).await?;
+        if self.prefix.is_empty() {
+            return Ok(keys);
+        }
+        let prefix = format!("{}:", self.prefix);
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&prefix).map(str::to_string))
+            .collect())
+    }
+
+    async fn ping(&mut self) -> RedisResult<()> {
+        self.store.ping().await
+    }
+}
+
+/// Where the app keeps what it has announced: Valkey, or a map in memory for
+/// DRY_RUN and tests. Clones share the pool or map.
+#[derive(Clone)]
+pub enum Store {
+    Valkey(ValkeyStore),
+    InMemory(InMemoryValkey),
+}
+
+impl Store {
+    /// A client for one request or reconcile.
+    pub fn client(&self) -> Box<dyn ValkeyClient> {
+        match self {
+            Self::Valkey(store) => Box::new(store.clone()),
+            Self::InMemory(store) => Box::new(store.clone()),
         }
     }
 }
 
+type Entries = HashMap<String, (String, Option<Instant>)>;
+
+/// Valkey kept in a map, for DRY_RUN and tests. Clones share the map.
+#[derive(Clone)]
 pub struct InMemoryValkey {
-    store: HashMap<String, String>,
+    store: Arc<Mutex<Entries>>,
 }
 
 impl InMemoryValkey {
     pub fn new() -> Self {
         Self {
-            store: HashMap::new(),
+            store: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// A store already holding `entries`, none of which expire.
+    #[cfg(test)]
+    pub fn from_map(entries: HashMap<String, String>) -> Self {
+        Self {
+            store: Arc::new(Mutex::new(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, (value, None)))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Every key that hasn't expired, with its value.
+    #[cfg(test)]
+    pub fn snapshot(&self) -> HashMap<String, String> {
+        let now = Instant::now();
+        self.entries()
+            .iter()
+            .filter(|(_, (_, expires_at))| expires_at.is_none_or(|at| at > now))
+            .map(|(key, (value, _))| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.store.lock().expect("In-memory Valkey lock poisoned")
+    }
+}
+
+/// The value of `key` in `entries`, dropping it once it has expired.
+fn live_value(entries: &mut Entries, key: &str) -> Option<String> {
+    match entries.get(key) {
+        Some((_, Some(expires_at))) if *expires_at <= Instant::now() => {
+            entries.remove(key);
+            None
         }
+        Some((value, _)) => Some(value.clone()),
+        None => None,
     }
 }
 
+fn insert(entries: &mut Entries, key: &str, value: &str, ttl: Option<Duration>) {
+    entries.insert(
+        key.to_string(),
+        (value.to_string(), ttl.map(|ttl| Instant::now() + ttl)),
+    );
+}
+
 #[async_trait]
 impl ValkeyClient for InMemoryValkey {
     async fn get(&mut self, key: &str) -> RedisResult<Option<String>> {
-        Ok(self.store.get(key).cloned())
+        Ok(live_value(&mut self.entries(), key))
+    }
+
+    async fn get_many(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>> {
+        let mut entries = self.entries();
+        Ok(keys
+            .iter()
+            .map(|key| live_value(&mut entries, key))
+            .collect())
     }
 
     async fn set(&mut self, key: &str, value: &str) -> RedisResult<()> {
-        self.store.insert(key.to_string(), value.to_string());
+        insert(&mut self.entries(), key, value, None);
+        Ok(())
+    }
+
+    async fn set_with_ttl(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<()> {
+        insert(&mut self.entries(), key, value, Some(ttl));
+        Ok(())
+    }
+
+    async fn set_many(
+        &mut self,
+        entries: &[(String, String)],
+        ttl: Option<Duration>,
+    ) -> RedisResult<()> {
+        for (key, value) in entries {
+            match ttl {
+                Some(ttl) => self.set_with_ttl(key, value, ttl).await?,
+                None => self.set(key, value).await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_if_absent(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<bool> {
+        let mut entries = self.entries();
+        if live_value(&mut entries, key).is_some() {
+            return Ok(false);
+        }
+        insert(&mut entries, key, value, Some(ttl));
+        Ok(true)
+    }
+
+    async fn del(&mut self, key: &str) -> RedisResult<bool> {
+        let mut entries = self.entries();
+        let existed = live_value(&mut entries, key).is_some();
+        entries.remove(key);
+        Ok(existed)
+    }
+
+    async fn del_many(&mut self, keys: &[String]) -> RedisResult<usize> {
+        let mut entries = self.entries();
+        let mut deleted = 0;
+        for key in keys {
+            deleted += usize::from(live_value(&mut entries, key).is_some());
+            entries.remove(key);
+        }
+        Ok(deleted)
+    }
+
+    async fn delete_if_equals(&mut self, key: &str, value: &str) -> RedisResult<()> {
+        let mut entries = self.entries();
+        if live_value(&mut entries, key).as_deref() == Some(value) {
+            entries.remove(key);
+        }
+        Ok(())
+    }
+
+    async fn scan_keys(&mut self, pattern: &str) -> RedisResult<Vec<String>> {
+        let now = Instant::now();
+        Ok(self
+            .entries()
+            .iter()
+            .filter(|(_, (_, expires_at))| expires_at.is_none_or(|at| at > now))
+            .map(|(key, _)| key)
+            .filter(|key| glob_matches(pattern.as_bytes(), key.as_bytes()))
+            .cloned()
+            .collect())
+    }
+
+    async fn ping(&mut self) -> RedisResult<()> {
         Ok(())
     }
 }
+
+/// Matches `key` against a `SCAN` pattern, supporting `*` and `?`.
+fn glob_matches(pattern: &[u8], key: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => key.is_empty(),
+        Some((b'*', rest)) => (0..=key.len()).any(|skip| glob_matches(rest, &key[skip..])),
+        Some((b'?', rest)) => !key.is_empty() && glob_matches(rest, &key[1..]),
+        Some((c, rest)) => key.first() == Some(c) && glob_matches(rest, &key[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Backend, InMemoryValkey, PrefixedValkey, SCAN_PAGE_SIZE, ValkeyClient, ValkeyStore,
+        cluster_primaries, wait_until_reachable,
+    };
+    use crate::config::{ArchiveConfig, ValkeyConfig};
+    use async_trait::async_trait;
+    use redis::{ErrorKind, RedisError, RedisResult, Value};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    type Keys = Arc<Mutex<HashMap<String, String>>>;
+
+    /// Refuses the first `failures` pings, like Valkey that is still starting.
+    struct FlakyValkey {
+        failures: u32,
+        pings: u32,
+        store: InMemoryValkey,
+    }
+
+    impl FlakyValkey {
+        fn new(failures: u32) -> Self {
+            Self {
+                failures,
+                pings: 0,
+                store: InMemoryValkey::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ValkeyClient for FlakyValkey {
+        async fn get(&mut self, key: &str) -> RedisResult<Option<String>> {
+            self.store.get(key).await
+        }
+
+        async fn get_many(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>> {
+            self.store.get_many(keys).await
+        }
+
+        async fn set(&mut self, key: &str, value: &str) -> RedisResult<()> {
+            self.store.set(key, value).await
+        }
+
+        async fn set_with_ttl(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<()> {
+            self.store.set_with_ttl(key, value, ttl).await
+        }
+
+        async fn set_many(
+            &mut self,
+            entries: &[(String, String)],
+            ttl: Option<Duration>,
+        ) -> RedisResult<()> {
+            self.store.set_many(entries, ttl).await
+        }
+
+        async fn set_if_absent(
+            &mut self,
+            key: &str,
+            value: &str,
+            ttl: Duration,
+        ) -> RedisResult<bool> {
+            self.store.set_if_absent(key, value, ttl).await
+        }
+
+        async fn del(&mut self, key: &str) -> RedisResult<bool> {
+            self.store.del(key).await
+        }
+
+        async fn del_many(&mut self, keys: &[String]) -> RedisResult<usize> {
+            self.store.del_many(keys).await
+        }
+
+        async fn delete_if_equals(&mut self, key: &str, value: &str) -> RedisResult<()> {
+            self.store.delete_if_equals(key, value).await
+        }
+
+        async fn scan_keys(&mut self, pattern: &str) -> RedisResult<Vec<String>> {
+            self.store.scan_keys(pattern).await
+        }
+
+        async fn ping(&mut self) -> RedisResult<()> {
+            self.pings += 1;
+            if self.pings <= self.failures {
+                return Err(RedisError::from((ErrorKind::IoError, "Connection refused")));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_until_valkey_answers() {
+        let mut store = FlakyValkey::new(2);
+        let started = tokio::time::Instant::now();
+
+        assert!(wait_until_reachable(&mut store, 5).await);
+
+        assert_eq!(store.pings, 3);
+        // Waited 500ms and then 1s before the third attempt.
+        assert_eq!(started.elapsed(), Duration::from_millis(1500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_the_last_attempt() {
+        let mut store = FlakyValkey::new(u32::MAX);
+
+        assert!(!wait_until_reachable(&mut store, 3).await);
+
+        assert_eq!(store.pings, 3);
+    }
+
+    /// Speaks just enough RESP to back the commands `ValkeyStore` uses with a
+    /// map, without expiry. Anything else is answered with OK. Transactions
+    /// run each command as it's queued and hand back the replies on EXEC.
+    async fn fake_valkey() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let keys = Keys::default();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve_connection(socket, keys.clone()));
+            }
+        });
+        format!("redis://{addr}")
+    }
+
+    async fn serve_connection(socket: TcpStream, keys: Keys) {
+        let (read, mut write) = socket.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut queued: Option<Vec<String>> = None;
+        while let Ok(Some(header)) = lines.next_line().await {
+            let Some(count) = header.strip_prefix('*').and_then(|n| n.parse().ok()) else {
+                return;
+            };
+            let mut args = Vec::with_capacity(count);
+            for _ in 0..count {
+                // Skip the `$<len>` line preceding each argument.
+                let _ = lines.next_line().await;
+                args.push(lines.next_line().await.ok().flatten().unwrap_or_default());
+            }
+            let command = args.first().map(|cmd| cmd.to_ascii_uppercase());
+            let reply =
+                {
+                    let mut keys = keys.lock().unwrap();
+                    match command.as_deref() {
+                        Some("GET") => match keys.get(&args[1]) {
+                            Some(value) => format!("${}\r\n{value}\r\n", value.len()),
+                            None => "$-1\r\n".to_string(),
+                        },
+                        Some("MGET") => args[1..].iter().fold(
+                            format!("*{}\r\n", args.len() - 1),
+                            |reply, key| match keys.get(key) {
+                                Some(value) => format!("{reply}${}\r\n{value}\r\n", value.len()),
+                                None => format!("{reply}$-1\r\n"),
+                            },
+                        ),
+                        Some("SET") => {
+                            keys.insert(args[1].clone(), args[2].clone());
+                            "+OK\r\n".to_string()
+                        }
+                        Some("SETEX") => {
+                            keys.insert(args[1].clone(), args[3].clone());
+                            "+OK\r\n".to_string()
+                        }
+                        Some("DEL") => format!(
+                            ":{}\r\n",
+                            args[1..]
+                                .iter()
+                                .filter(|key| keys.remove(*key).is_some())
+                                .count()
+                        ),
+                        Some("DBSIZE") => format!(":{}\r\n", keys.len()),
+                        // Pages through the keys in sorted order, ignoring MATCH.
+                        Some("SCAN") => {
+                            let cursor: usize = args[1].parse().unwrap();
+                            let count: usize = args[5].parse().unwrap();
+                            let mut sorted: Vec<&String> = keys.keys().collect();
+                            sorted.sort();
+                            let page: Vec<&&String> =
+                                sorted.iter().skip(cursor).take(count).collect();
+                            let next = if cursor + count < sorted.len() {
+                                cursor + count
+                            } else {
+                                0
+                            };
+                            page.iter().fold(
+                                format!(
+                                    "*2\r\n${}\r\n{next}\r\n*{}\r\n",
+                                    next.to_string().len(),
+                                    page.len()
+                                ),
+                                |reply, key| format!("{reply}${}\r\n{key}\r\n", key.len()),
+                            )
+                        }
+                        Some("PING") => "+PONG\r\n".to_string(),
+                        _ => "+OK\r\n".to_string(),
+                    }
+                };
+            let reply = match (command.as_deref(), queued.as_mut()) {
+                (Some("MULTI"), _) => {
+                    queued = Some(Vec::new());
+                    reply
+                }
+                (Some("EXEC"), Some(replies)) => {
+                    let reply = format!("*{}\r\n{}", replies.len(), replies.concat());
+                    queued = None;
+                    reply
+                }
+                (_, Some(replies)) => {
+                    replies.push(reply);
+                    "+QUEUED\r\n".to_string()
+                }
+                (_, None) => reply,
+            };
+            if write.write_all(reply.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn fake_store() -> ValkeyStore {
+        let config = ValkeyConfig {
+            uri: fake_valkey().await,
+            archive: ArchiveConfig::default(),
+        };
+        ValkeyStore::connect(&config).unwrap()
+    }
+
+    #[test]
+    fn picks_backend_for_uri_shape() {
+        let backend = |uri: &str| {
+            let config = ValkeyConfig {
+                uri: uri.to_string(),
+                archive: ArchiveConfig::default(),
+            };
+            Backend::new(config.topology().unwrap()).unwrap()
+        };
+
+        assert!(matches!(
+            backend("redis://localhost:6379"),
+            Backend::Single(_)
+        ));
+        assert!(matches!(
+            backend("redis+sentinel://localhost:26379/mymaster"),
+            Backend::Sentinel(_)
+        ));
+        assert!(matches!(
+            backend("redis+cluster://localhost:7000,localhost:7001"),
+            Backend::Cluster(_)
+        ));
+    }
+
+    #[test]
+    fn cluster_primaries_are_read_once_each_from_cluster_slots() {
+        let node = |host: &str, port: i64, id: &str| {
+            Value::Array(vec![
+                Value::BulkString(host.into()),
+                Value::Int(port),
+                Value::BulkString(id.into()),
+            ])
+        };
+        let range = |start: i64, end: i64, primary: Value, replica: Value| {
+            Value::Array(vec![Value::Int(start), Value::Int(end), primary, replica])
+        };
+        let slots = Value::Array(vec![
+            range(
+                0,
+                5460,
+                node("10.0.0.1", 7000, "a"),
+                node("10.0.0.4", 7003, "d"),
+            ),
+            range(
+                5461,
+                10922,
+                node("10.0.0.2", 7001, "b"),
+                node("10.0.0.5", 7004, "e"),
+            ),
+            range(
+                10923,
+                12000,
+                node("10.0.0.3", 7002, "c"),
+                node("10.0.0.6", 7005, "f"),
+            ),
+            range(
+                12001,
+                16383,
+                node("10.0.0.1", 7000, "a"),
+                node("10.0.0.4", 7003, "d"),
+            ),
+        ]);
+
+        assert_eq!(
+            cluster_primaries(&slots).unwrap(),
+            vec![
+                ("10.0.0.1".to_string(), 7000),
+                ("10.0.0.2".to_string(), 7001),
+                ("10.0.0.3".to_string(), 7002),
+            ]
+        );
+        assert!(cluster_primaries(&Value::Array(vec![Value::Int(0)])).is_err());
+    }
+
+    #[tokio::test]
+    async fn store_round_trips_through_async_connection() {
+        let mut store = fake_store().await;
+
+        store.ping().await.unwrap();
+        assert_eq!(store.get("key").await.unwrap(), None);
+        store.set("key", "value").await.unwrap();
+        store
+            .set_with_ttl("other", "value", Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("value"));
+        assert_eq!(store.scan_keys("*").await.unwrap().len(), 2);
+
+        assert!(store.del("key").await.unwrap());
+        assert!(!store.del("key").await.unwrap());
+        assert_eq!(store.get("key").await.unwrap(), None);
+
+        let keys = ["other".to_string(), "missing".to_string()];
+        assert_eq!(store.del_many(&keys).await.unwrap(), 1);
+        assert_eq!(store.del_many(&[]).await.unwrap(), 0);
+        assert_eq!(store.scan_keys("*").await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn set_many_matches_individual_sets() {
+        let entries: Vec<(String, String)> = (0..3)
+            .map(|i| (format!("key-{i}"), format!("value-{i}")))
+            .collect();
+        let keys: Vec<String> = entries.iter().map(|(key, _)| key.clone()).collect();
+
+        let mut individual = InMemoryValkey::new();
+        for (key, value) in &entries {
+            individual.set(key, value).await.unwrap();
+        }
+        let mut in_memory = InMemoryValkey::new();
+        let mut pooled = fake_store().await;
+        for store in [&mut in_memory as &mut dyn ValkeyClient, &mut pooled] {
+            store.set("key-0", "stale").await.unwrap();
+            store.set_many(&entries, None).await.unwrap();
+            store.set_many(&[], None).await.unwrap();
+
+            assert_eq!(
+                store.get_many(&keys).await.unwrap(),
+                individual.get_many(&keys).await.unwrap()
+            );
+            assert_eq!(store.scan_keys("*").await.unwrap().len(), 3);
+        }
+
+        pooled
+            .set_many(&entries, Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        assert_eq!(
+            pooled.get("key-2").await.unwrap().as_deref(),
+            Some("value-2")
+        );
+        in_memory
+            .set_many(&entries, Some(Duration::ZERO))
+            .await
+            .unwrap();
+        assert_eq!(in_memory.get("key-2").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn get_many_matches_individual_gets() {
+        let keys: Vec<String> = ["present", "missing", "other", "expired"]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let mut in_memory = InMemoryValkey::new();
+        let mut pooled = fake_store().await;
+        for store in [&mut in_memory as &mut dyn ValkeyClient, &mut pooled] {
+            store.set("present", "one").await.unwrap();
+            store.set("other", "two").await.unwrap();
+        }
+        in_memory
+            .set_with_ttl("expired", "three", Duration::ZERO)
+            .await
+            .unwrap();
+
+        for store in [&mut in_memory as &mut dyn ValkeyClient, &mut pooled] {
+            let mut individual = Vec::new();
+            for key in &keys {
+                individual.push(store.get(key).await.unwrap());
+            }
+            assert_eq!(store.get_many(&keys).await.unwrap(), individual);
+            assert_eq!(
+                individual,
+                vec![Some("one".to_string()), None, Some("two".to_string()), None]
+            );
+            assert!(store.get_many(&[]).await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_keys_pages_through_the_keyspace() {
+        let mut expected: Vec<String> = (0..SCAN_PAGE_SIZE * 2 + 5)
+            .map(|i| format!("post-{i:03}"))
+            .collect();
+        let mut in_memory = InMemoryValkey::new();
+        let mut pooled = fake_store().await;
+        for store in [&mut in_memory as &mut dyn ValkeyClient, &mut pooled] {
+            for key in &expected {
+                store.set(key, "{}").await.unwrap();
+            }
+        }
+        expected.sort();
+
+        let mut scanned = pooled.scan_keys("*").await.unwrap();
+        scanned.sort();
+        assert_eq!(scanned, expected);
+
+        let mut scanned = in_memory.scan_keys("post-00?").await.unwrap();
+        scanned.sort();
+        assert_eq!(scanned, expected[..10]);
+        in_memory.set("reconcile:lock", "token").await.unwrap();
+        assert_eq!(
+            in_memory.scan_keys("*:*").await.unwrap(),
+            ["reconcile:lock"]
+        );
+    }
+
+    #[tokio::test]
+    async fn prefixed_store_keeps_sources_apart() {
+        let mut store = InMemoryValkey::new();
+        store.set("post", "unprefixed").await.unwrap();
+        PrefixedValkey::new("blog", &mut store)
+            .set_many(&[("post".to_string(), "blog".to_string())], None)
+            .await
+            .unwrap();
+        PrefixedValkey::new("status", &mut store)
+            .set("post", "status")
+            .await
+            .unwrap();
+
+        let mut blog = PrefixedValkey::new("blog", &mut store);
+        assert_eq!(blog.get("post").await.unwrap().as_deref(), Some("blog"));
+        assert_eq!(blog.scan_keys("*").await.unwrap(), ["post"]);
+        assert_eq!(blog.scan_keys("*").await.unwrap().len(), 1);
+        assert!(blog.del("post").await.unwrap());
+
+        let mut keys: Vec<_> = store.snapshot().into_iter().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                ("post".to_string(), "unprefixed".to_string()),
+                ("status:post".to_string(), "status".to_string()),
+            ]
+        );
+        let mut single = PrefixedValkey::new("", &mut store);
+        assert_eq!(
+            single.get("post").await.unwrap().as_deref(),
+            Some("unprefixed")
+        );
+    }
+
+    #[tokio::test]
+    async fn pooled_store_serves_parallel_gets() {
+        let mut store = fake_store().await;
+        for i in 0..50 {
+            store
+                .set(&format!("key-{i}"), &format!("value-{i}"))
+                .await
+                .unwrap();
+        }
+
+        let gets: Vec<_> = (0..50)
+            .map(|i| {
+                let mut store = store.clone();
+                tokio::spawn(async move { store.get(&format!("key-{i}")).await })
+            })
+            .collect();
+
+        for (i, get) in gets.into_iter().enumerate() {
+            assert_eq!(get.await.unwrap().unwrap(), Some(format!("value-{i}")));
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_set_without_ttl_never_expires() {
+        let mut store = InMemoryValkey::new();
+        store.set("key", "value").await.unwrap();
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("value"));
+    }
+
+    #[tokio::test]
+    async fn in_memory_entry_is_readable_before_expiry() {
+        let mut store = InMemoryValkey::new();
+        store
+            .set_with_ttl("key", "value", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("value"));
+    }
+
+    #[tokio::test]
+    async fn in_memory_entry_is_gone_after_expiry() {
+        let mut store = InMemoryValkey::new();
+        store
+            .set_with_ttl("key", "value", Duration::from_millis(10))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(store.get("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn in_memory_set_clears_previous_ttl() {
+        let mut store = InMemoryValkey::new();
+        store
+            .set_with_ttl("key", "old", Duration::ZERO)
+            .await
+            .unwrap();
+        store.set("key", "new").await.unwrap();
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("new"));
+    }
+
+    #[tokio::test]
+    async fn in_memory_snapshot_reflects_seeded_and_written_keys() {
+        let mut store = InMemoryValkey::from_map(HashMap::from([
+            ("seeded".to_string(), "one".to_string()),
+            ("overwritten".to_string(), "old".to_string()),
+        ]));
+        assert_eq!(store.get("seeded").await.unwrap().as_deref(), Some("one"));
+
+        store.set("overwritten", "new").await.unwrap();
+        store
+            .set_with_ttl("expired", "gone", Duration::ZERO)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.snapshot(),
+            HashMap::from([
+                ("seeded".to_string(), "one".to_string()),
+                ("overwritten".to_string(), "new".to_string()),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn in_memory_set_if_absent_keeps_existing_value() {
+        let mut store = InMemoryValkey::new();
+        let ttl = Duration::from_secs(60);
+        assert!(store.set_if_absent("lock", "first", ttl).await.unwrap());
+        assert!(!store.set_if_absent("lock", "second", ttl).await.unwrap());
+
+        store.delete_if_equals("lock", "second").await.unwrap();
+        assert_eq!(store.get("lock").await.unwrap().as_deref(), Some("first"));
+        store.delete_if_equals("lock", "first").await.unwrap();
+        assert_eq!(store.get("lock").await.unwrap(), None);
+    }
+}
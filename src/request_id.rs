@@ -0,0 +1,110 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{Instrument, info_span};
+
+static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest id taken from a caller; anything longer gets one of our own.
+const MAX_ID_LEN: usize = 128;
+
+/// Runs the request in a span carrying its `X-Request-Id`, so every log line
+/// it causes can be traced back to it, and echoes the id in the response.
+/// Requests without a usable id get a fresh one.
+pub async fn propagate(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID)
+        .filter(|value| !value.is_empty() && value.len() <= MAX_ID_LEN)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(generate);
+
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    let mut response = next.run(request).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID.clone(), value);
+    }
+    response
+}
+
+/// An id unique within this process and unlikely to collide across replicas.
+fn generate() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{nanos:016x}-{count:04x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::propagate;
+    use axum::{Router, middleware, routing::get};
+    use tracing::info;
+    use tracing_test::traced_test;
+
+    async fn serve() -> String {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    info!("Handling request");
+                    "ok"
+                }),
+            )
+            .layer(middleware::from_fn(propagate));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}/")
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn echoes_and_logs_the_callers_id() {
+        let url = serve().await;
+
+        let response = reqwest::Client::new()
+            .get(&url)
+            .header("X-Request-Id", "abc-123")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+        assert!(logs_contain("request_id=abc-123"));
+        assert!(logs_contain("Handling request"));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn generates_an_id_when_missing() {
+        let url = serve().await;
+
+        let first = reqwest::get(&url).await.unwrap();
+        let second = reqwest::get(&url).await.unwrap();
+
+        let id = first.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(!id.is_empty());
+        assert_ne!(second.headers()["x-request-id"], id.as_str());
+        assert!(logs_contain(&format!("request_id={id}")));
+    }
+}
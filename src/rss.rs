@@ -1,33 +1,221 @@
 use crate::{
-    config,
-    redis_client::{InMemoryValkey, ValkeyClient, ValkeyStore},
-    slack::{self, HttpSlackClient, SlackClient, StdoutSlackClient},
+    circuit_breaker::{BreakerNotifier, CircuitOpen},
+    config::{
+        self, AnnounceOrder, ArchiveConfig, CorruptArchivePolicy, TitleEditPolicy, UpdateMode,
+    },
+    failures,
+    notifier::{
+        DEFAULT_CHANNEL, MessageIds, Notifier, PacedNotifier, PreviewNotifier,
+        RESOLVED_CHANNEL_PREFIX, resolved_channel_key,
+    },
+    redis_client::{ReadOnlyValkey, ValkeyClient},
 };
+use chrono::{DateTime, FixedOffset, ParseResult, Utc};
+use quick_xml::{
+    NsReader, Writer,
+    events::{BytesEnd, BytesStart, Event},
+    name::{Namespace, QName, ResolveResult},
+};
+use redis::RedisResult;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info, instrument};
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    io::Error,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{Instrument, Span, error, field, info, info_span, instrument, warn};
 
+/// Failures that stop a reconcile before any post is looked at. What goes
+/// wrong with a single post is counted in the [`ReconcileSummary`] instead.
 #[derive(Debug)]
 pub enum FeedError {
+    /// The feed answered with nothing but whitespace, like a CDN hiccup would.
+    EmptyBody,
     RssParse(String),
-    InvalidArchive { key: String, error: String },
-    SerializeArchive { key: String, error: String },
+    /// Another reconcile holds the lock and is still working through the feed.
+    ReconcileInProgress,
+    /// The reconcile lock couldn't be taken because Valkey failed.
+    Lock(String),
 }
 
-#[derive(Debug, Deserialize)]
+/// Key of the lock that keeps reconciles from different replicas apart.
+const LOCK_KEY: &str = "reconcile:lock";
+
+/// Lets the lock lapse should its holder die before releasing it.
+const LOCK_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Key of the messages the notifier threads posts under, by channel.
+const THREAD_ROOTS_KEY: &str = "slack:thread_roots";
+
+/// Hands the thread roots kept in Redis to `notifier`, returning them.
+async fn restore_thread_roots(store: &mut dyn ValkeyClient, notifier: &dyn Notifier) -> MessageIds {
+    let kept = match store.get(THREAD_ROOTS_KEY).await {
+        Ok(Some(raw)) => serde_json::from_str(&raw).unwrap_or_else(|err| {
+            error!(error = %err, "Invalid thread roots in Redis, ignoring them");
+            MessageIds::new()
+        }),
+        Ok(None) => MessageIds::new(),
+        Err(err) => {
+            error!(error = %err, "Failed getting thread roots from Redis");
+            MessageIds::new()
+        }
+    };
+    notifier.restore_thread_roots(&kept).await;
+    kept
+}
+
+/// Keeps the thread roots of `notifier` in Redis when they differ from
+/// `kept`, so a restart threads posts under the same messages.
+async fn save_thread_roots(
+    store: &mut dyn ValkeyClient,
+    notifier: &dyn Notifier,
+    kept: &MessageIds,
+) {
+    let roots = notifier.thread_roots().await;
+    if &roots == kept {
+        return;
+    }
+    let raw = match serde_json::to_string(&roots) {
+        Ok(raw) => raw,
+        Err(err) => {
+            error!(error = %err, "Failed serializing thread roots");
+            return;
+        }
+    };
+    if let Err(err) = store.set(THREAD_ROOTS_KEY, &raw).await {
+        error!(error = %err, "Failed saving thread roots to Redis, a new root is posted after a restart");
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Post {
     pub title: String,
     pub link: String,
-    #[serde(rename = "pubDate")]
-    pub_date: String,
-    #[serde(rename = "encoded")]
+    pub pub_date: DateTime<Utc>,
     pub content: String,
+    pub categories: Vec<String>,
+    /// Stable identity of an RSS item, used as its archive key when set.
+    pub guid: Option<String>,
+}
+
+impl Post {
+    /// The archive key of the post: its guid, or else the fragment of its link.
+    fn key(&self) -> Option<String> {
+        self.guid
+            .as_deref()
+            .map(str::trim)
+            .filter(|guid| !guid.is_empty())
+            .map(str::to_string)
+            .or_else(|| key_from_link(&self.link))
+    }
+}
+
+/// Parses the date of a post, falling back to the current time so a post
+/// with a malformed date is still handled.
+fn parse_date(raw: &str, parse: fn(&str) -> ParseResult<DateTime<FixedOffset>>) -> DateTime<Utc> {
+    match parse(raw.trim()) {
+        Ok(date) => date.with_timezone(&Utc),
+        Err(err) => {
+            error!(date = raw, error = %err, "Unparseable post date, using the current time");
+            Utc::now()
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct Feed {
     title: String,
     #[serde(rename = "item")]
-    posts: Vec<Post>,
+    posts: Vec<RssItem>,
+}
+
+/// An RSS item, with its elements named as [`resolve_namespaces`] leaves them.
+#[derive(Debug, Deserialize)]
+struct RssItem {
+    title: String,
+    link: String,
+    /// An RFC 2822 date.
+    #[serde(rename = "pubDate")]
+    pub_date: Option<String>,
+    /// `dc:date`, an RFC 3339 date some feeds give instead of `pubDate`.
+    #[serde(rename = "dc-date")]
+    dc_date: Option<String>,
+    /// `content:encoded`, the full post.
+    encoded: Option<String>,
+    description: Option<String>,
+    #[serde(rename = "category", default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    guid: Option<String>,
+}
+
+impl From<RssItem> for Post {
+    fn from(item: RssItem) -> Self {
+        let pub_date = match (item.pub_date, item.dc_date) {
+            (Some(raw), _) => parse_date(&raw, DateTime::parse_from_rfc2822),
+            (None, Some(raw)) => parse_date(&raw, DateTime::parse_from_rfc3339),
+            (None, None) => parse_date("", DateTime::parse_from_rfc2822),
+        };
+        let content = item.encoded.or(item.description).unwrap_or_else(|| {
+            warn!(link = %item.link, "Post has neither content:encoded nor description");
+            String::new()
+        });
+        Post {
+            title: item.title,
+            link: item.link,
+            pub_date,
+            content,
+            categories: item.categories,
+            guid: item.guid,
+        }
+    }
+}
+
+/// RSS's content module, home of `content:encoded`.
+const CONTENT_NAMESPACE: &[u8] = b"http://purl.org/rss/1.0/modules/content/";
+const ATOM_NAMESPACE: &[u8] = b"http://www.w3.org/2005/Atom";
+const DUBLIN_CORE_NAMESPACE: &[u8] = b"http://purl.org/dc/elements/1.1/";
+
+/// Renames the elements of a feed by their namespace, as the deserializer
+/// only sees local names and would take an `atom:link` in an RSS item for
+/// its `link`. Elements without a namespace, in one of `own` or with an
+/// undeclared prefix keep their local name, Dublin Core ones become
+/// `dc-<name>` and those in any other namespace `ext-<name>`.
+fn resolve_namespaces(xml: &str, own: &[&[u8]]) -> Result<String, FeedError> {
+    let renamed = |ns: &ResolveResult, name: QName| {
+        let local = String::from_utf8_lossy(name.local_name().into_inner()).into_owned();
+        match ns {
+            ResolveResult::Bound(Namespace(ns)) if own.contains(ns) => local,
+            ResolveResult::Bound(Namespace(DUBLIN_CORE_NAMESPACE)) => format!("dc-{local}"),
+            ResolveResult::Bound(_) => format!("ext-{local}"),
+            ResolveResult::Unbound | ResolveResult::Unknown(_) => local,
+        }
+    };
+    let start = |name: String, e: &BytesStart| {
+        let attributes = String::from_utf8_lossy(e.attributes_raw());
+        BytesStart::from_content(format!("{name}{attributes}"), name.len()).into_owned()
+    };
+
+    let mut reader = NsReader::from_str(xml);
+    let mut writer = Writer::new(Vec::with_capacity(xml.len()));
+    loop {
+        let (ns, event) = reader
+            .read_resolved_event()
+            .map_err(|e| FeedError::RssParse(e.to_string()))?;
+        let event = match event {
+            Event::Eof => break,
+            Event::Start(e) => Event::Start(start(renamed(&ns, e.name()), &e)),
+            Event::Empty(e) => Event::Empty(start(renamed(&ns, e.name()), &e)),
+            Event::End(e) => Event::End(BytesEnd::new(renamed(&ns, e.name()))),
+            other => other,
+        };
+        writer
+            .write_event(event)
+            .map_err(|e| FeedError::RssParse(e.to_string()))?;
+    }
+    String::from_utf8(writer.into_inner()).map_err(|e| FeedError::RssParse(e.to_string()))
 }
 
 #[derive(Debug, Deserialize)]
@@ -35,179 +223,3384 @@ struct Rss {
     channel: Feed,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize)]
+struct AtomFeed {
+    title: String,
+    #[serde(rename = "entry", default)]
+    entries: Vec<AtomEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtomEntry {
+    title: String,
+    #[serde(rename = "link", default)]
+    links: Vec<AtomLink>,
+    updated: String,
+    content: AtomContent,
+    #[serde(rename = "category", default)]
+    categories: Vec<AtomCategory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtomCategory {
+    #[serde(rename = "@term")]
+    term: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtomLink {
+    #[serde(rename = "@href")]
+    href: String,
+    #[serde(rename = "@rel")]
+    rel: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtomContent {
+    #[serde(rename = "$text", default)]
+    value: String,
+}
+
+impl From<AtomEntry> for Post {
+    fn from(entry: AtomEntry) -> Self {
+        // An entry may carry several links; the alternate (or unlabelled) one
+        // points at the post itself.
+        let link = entry
+            .links
+            .iter()
+            .find(|l| l.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+            .or(entry.links.first())
+            .map(|l| l.href.clone())
+            .unwrap_or_default();
+
+        Post {
+            title: entry.title,
+            link,
+            pub_date: parse_date(&entry.updated, DateTime::parse_from_rfc3339),
+            content: entry.content.value,
+            categories: entry.categories.into_iter().map(|c| c.term).collect(),
+            guid: None,
+        }
+    }
+}
+
+/// A JSON Feed (https://jsonfeed.org), version 1 or 1.1.
+#[derive(Debug, Deserialize)]
+struct JsonFeed {
+    title: String,
+    #[serde(default)]
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFeedItem {
+    id: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    url: String,
+    content_html: Option<String>,
+    content_text: Option<String>,
+    date_published: Option<String>,
+    date_modified: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl From<JsonFeedItem> for Post {
+    fn from(item: JsonFeedItem) -> Self {
+        let date = item
+            .date_published
+            .or(item.date_modified)
+            .unwrap_or_default();
+        Post {
+            title: item.title,
+            link: item.url,
+            pub_date: parse_date(&date, DateTime::parse_from_rfc3339),
+            content: item.content_html.or(item.content_text).unwrap_or_default(),
+            categories: item.tags,
+            // Items must have an id, and it's meant to stay put like an RSS guid.
+            guid: Some(item.id),
+        }
+    }
+}
+
+/// The `version` every JSON Feed starts with, used to tell it apart from
+/// other JSON before parsing the rest.
+#[derive(Debug, Deserialize)]
+struct JsonFeedVersion {
+    version: String,
+}
+
+/// The feed formats we know how to read, told apart by their root element,
+/// or by the `version` of a JSON body.
+#[derive(Debug, PartialEq, Eq)]
+enum FeedKind {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl FeedKind {
+    fn detect(xml: &str) -> Result<Self, FeedError> {
+        if xml.trim_start().starts_with('{') {
+            let feed: JsonFeedVersion =
+                serde_json::from_str(xml).map_err(|e| FeedError::RssParse(e.to_string()))?;
+            return if feed.version.starts_with("https://jsonfeed.org/version/") {
+                Ok(FeedKind::Json)
+            } else {
+                Err(FeedError::RssParse(format!(
+                    "Unsupported JSON feed version {:?}",
+                    feed.version
+                )))
+            };
+        }
+
+        let mut reader = quick_xml::Reader::from_str(xml);
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                    return match e.local_name().as_ref() {
+                        b"rss" => Ok(FeedKind::Rss),
+                        b"feed" => Ok(FeedKind::Atom),
+                        other => Err(FeedError::RssParse(format!(
+                            "Unsupported feed root element <{}>",
+                            String::from_utf8_lossy(other)
+                        ))),
+                    };
+                }
+                Ok(Event::Eof) => {
+                    return Err(FeedError::RssParse("Feed has no root element".to_string()));
+                }
+                Err(e) => return Err(FeedError::RssParse(e.to_string())),
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+/// Feed contents normalized across the supported formats.
+struct ParsedFeed {
+    title: String,
+    posts: Vec<Post>,
+}
+
+fn parse_feed(xml: &str) -> Result<ParsedFeed, FeedError> {
+    if xml.trim().is_empty() {
+        return Err(FeedError::EmptyBody);
+    }
+    let (title, posts) = match FeedKind::detect(xml)? {
+        FeedKind::Rss => {
+            let xml = resolve_namespaces(xml, &[CONTENT_NAMESPACE])?;
+            let doc: Rss =
+                quick_xml::de::from_str(&xml).map_err(|e| FeedError::RssParse(e.to_string()))?;
+            let posts = doc.channel.posts.into_iter().map(Post::from).collect();
+            (doc.channel.title, posts)
+        }
+        FeedKind::Atom => {
+            let xml = resolve_namespaces(xml, &[ATOM_NAMESPACE])?;
+            let doc: AtomFeed =
+                quick_xml::de::from_str(&xml).map_err(|e| FeedError::RssParse(e.to_string()))?;
+            (doc.title, doc.entries.into_iter().map(Post::from).collect())
+        }
+        FeedKind::Json => {
+            let doc: JsonFeed =
+                serde_json::from_str(xml).map_err(|e| FeedError::RssParse(e.to_string()))?;
+            (doc.title, doc.items.into_iter().map(Post::from).collect())
+        }
+    };
+    Ok(ParsedFeed {
+        title,
+        posts: dedup_posts(posts),
+    })
+}
+
+/// Drops all but the last of the posts sharing an archive key. Archive
+/// entries are all read before any post is announced, so duplicates would
+/// each look new and be announced twice.
+fn dedup_posts(posts: Vec<Post>) -> Vec<Post> {
+    let keys: Vec<Option<String>> = posts.iter().map(Post::key).collect();
+    let last: HashMap<&str, usize> = keys
+        .iter()
+        .enumerate()
+        .filter_map(|(i, key)| key.as_deref().map(|key| (key, i)))
+        .collect();
+    let keep: Vec<bool> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| key.as_deref().is_none_or(|key| last[key] == i))
+        .collect();
+
+    posts
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(post, keep)| {
+            if !keep {
+                warn!(key = ?post.key(), title = %post.title, "Feed has the same post more than once, keeping the last");
+            }
+            keep.then_some(post)
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Archive {
+    /// SHA-256 of the post's content, or md5 in older entries. Entries from
+    /// before titles were tracked on their own store a hash of title and
+    /// content under `hash` instead.
+    #[serde(alias = "hash")]
+    pub content_hash: String,
+    /// Hash of the post's title; empty in entries from before it was tracked.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub title_hash: String,
+    /// Id of the message when the post went to a single channel, which is
+    /// also the only id entries from before fan-out have.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub timestamp: String,
+    /// Ids of the messages by channel when the post went to several.
+    #[serde(default, skip_serializing_if = "MessageIds::is_empty")]
+    pub timestamps: MessageIds,
+    /// Ids of the latest update replies by channel, in `UPDATE_MODE=thread`.
+    /// The messages above stay the thread roots.
+    #[serde(default, skip_serializing_if = "MessageIds::is_empty")]
+    pub replies: MessageIds,
+    /// Ids Slack resolved configured channels to, by configured channel,
+    /// where they differ, so edits go to the channel the message is in.
+    #[serde(default, skip_serializing_if = "MessageIds::is_empty")]
+    pub channels: MessageIds,
+    /// Set for a post found during `QUIET_HOURS` and not announced yet.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pending: bool,
+}
+
+impl Archive {
+    fn new(post: &Post, ids: MessageIds) -> Self {
+        let (ids, channels) = split_channels(ids);
+        let (timestamp, timestamps) = match ids.get(DEFAULT_CHANNEL) {
+            Some(timestamp) if ids.len() == 1 => (timestamp.clone(), MessageIds::new()),
+            _ => (String::new(), ids),
+        };
+        Archive {
+            content_hash: content_fingerprint(post),
+            title_hash: sha256_hex(&post.title),
+            timestamp,
+            timestamps,
+            replies: MessageIds::new(),
+            channels,
+            pending: false,
+        }
+    }
+
+    /// Which parts of `post` differ from what this entry was written for.
+    fn changes(&self, post: &Post) -> Changes {
+        if self.title_hash.is_empty() {
+            // The combined hash can't tell title and content edits apart.
+            let combined = format!("{}-{}", post.title, post.content);
+            let changed = !fingerprint_matches(&self.content_hash, &combined);
+            return Changes {
+                title: changed,
+                content: changed,
+            };
+        }
+        Changes {
+            title: !fingerprint_matches(&self.title_hash, &post.title),
+            content: !fingerprint_matches(&self.content_hash, &post.content),
+        }
+    }
+
+    fn message_ids(&self) -> MessageIds {
+        let mut ids = self.timestamps.clone();
+        if !self.timestamp.is_empty() {
+            ids.entry(DEFAULT_CHANNEL.to_string())
+                .or_insert_with(|| self.timestamp.clone());
+        }
+        ids.extend(
+            self.channels
+                .iter()
+                .map(|(channel, id)| (resolved_channel_key(channel), id.clone())),
+        );
+        ids
+    }
+}
+
+/// Separates the channel ids a notifier resolved configured channels to from
+/// the message ids it returned alongside them.
+fn split_channels(ids: MessageIds) -> (MessageIds, MessageIds) {
+    let mut channels = MessageIds::new();
+    let ids = ids
+        .into_iter()
+        .filter_map(
+            |(key, id)| match key.strip_prefix(RESOLVED_CHANNEL_PREFIX) {
+                Some(channel) => {
+                    channels.insert(channel.to_string(), id);
+                    None
+                }
+                None => Some((key, id)),
+            },
+        )
+        .collect();
+    (ids, channels)
+}
+
+/// A post as remembered in the archive.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ArchivedPost {
+    pub key: String,
     pub hash: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub timestamp: String,
+    #[serde(skip_serializing_if = "MessageIds::is_empty")]
+    pub timestamps: MessageIds,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub pending: bool,
 }
 
-#[instrument(skip(xml, app_state))]
-pub async fn handle_feed(xml: &str, app_state: &config::AppState) -> Result<(), FeedError> {
-    let doc: Rss = quick_xml::de::from_str(xml).map_err(|e| FeedError::RssParse(e.to_string()))?;
-    info!(
-        "Found {} posts in {}",
-        doc.channel.posts.len(),
-        doc.channel.title
-    );
+/// Lists every post in the archive, ordered by key. Keys holding anything
+/// but an archive entry, like the reconcile lock, are left out.
+pub async fn archived_posts(store: &mut dyn ValkeyClient) -> RedisResult<Vec<ArchivedPost>> {
+    let mut keys = store.scan_keys("*").await?;
+    keys.sort();
+    let values = store.get_many(&keys).await?;
+
+    Ok(keys
+        .into_iter()
+        .zip(values)
+        .filter_map(|(key, raw)| {
+            let archive = serde_json::from_str::<Archive>(&raw?).ok()?;
+            Some(ArchivedPost {
+                key,
+                hash: archive.content_hash,
+                timestamp: archive.timestamp,
+                timestamps: archive.timestamps,
+                pending: archive.pending,
+            })
+        })
+        .collect())
+}
 
-    let mut redis_client: Option<Box<dyn ValkeyClient>> = if app_state.config.is_dry_run() {
-        info!("DRY_RUN is set, using in-memory Valkey");
-        Some(Box::new(InMemoryValkey::new()))
-    } else if let Some(redis_cfg) = app_state.config.valkey_config() {
-        ValkeyStore::connect(redis_cfg).map(|store| Box::new(store) as Box<dyn ValkeyClient>)
+/// How many keys `clear_archive` deletes per `DEL`.
+const CLEAR_BATCH_SIZE: usize = 100;
+
+/// Forgets every announced post, along with failure counts, dead letters and
+/// feed validators, so the next reconcile announces the whole feed again.
+/// The reconcile locks of every feed source are left alone, as another
+/// replica may be holding them, and so are the Slack thread roots, which
+/// would otherwise be posted and pinned again in every channel. Returns how
+/// many keys were deleted.
+pub async fn clear_archive(store: &mut dyn ValkeyClient) -> RedisResult<usize> {
+    let keys: Vec<String> = store
+        .scan_keys("*")
+        .await?
+        .into_iter()
+        .filter(|key| !is_source_key(key, LOCK_KEY) && !is_source_key(key, THREAD_ROOTS_KEY))
+        .collect();
+    let mut deleted = 0;
+    for batch in keys.chunks(CLEAR_BATCH_SIZE) {
+        deleted += store.del_many(batch).await?;
+    }
+    Ok(deleted)
+}
+
+/// What every key this app writes with a `:` in it starts with, besides the
+/// `key_prefix` of a feed source.
+const APP_NAMESPACES: [&str; 6] = [
+    "failures",
+    "deadletter",
+    "feed",
+    "channel",
+    "reconcile",
+    "slack",
+];
+
+/// The keys in `store` that look like another deployment's, namespaced under
+/// something neither this app nor any of `source_prefixes` writes. Without
+/// `REDIS_KEY_PREFIX` a reset would take these along. A post keyed by a guid
+/// with a `:` in it looks foreign too, which errs on the side of deleting
+/// nothing.
+pub async fn foreign_keys(
+    store: &mut dyn ValkeyClient,
+    source_prefixes: &[&str],
+) -> RedisResult<Vec<String>> {
+    Ok(store
+        .scan_keys("*")
+        .await?
+        .into_iter()
+        .filter(|key| {
+            key.split_once(':').is_some_and(|(namespace, _)| {
+                !APP_NAMESPACES.contains(&namespace) && !source_prefixes.contains(&namespace)
+            })
+        })
+        .collect())
+}
+
+/// Whether `key` is `name` for some feed source, with or without its key
+/// prefix.
+fn is_source_key(key: &str, name: &str) -> bool {
+    key.strip_suffix(name)
+        .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with(':'))
+}
+
+/// Fingerprint of a post's content, compared against the archive to tell
+/// whether the post changed.
+fn content_fingerprint(post: &Post) -> String {
+    sha256_hex(&post.content)
+}
+
+fn sha256_hex(text: &str) -> String {
+    Sha256::digest(text)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Length of the hex md5 digests archive entries were written with before
+/// fingerprints moved to SHA-256.
+const LEGACY_MD5_LEN: usize = 32;
+
+/// Checks a stored fingerprint against `text`, recognizing legacy md5 ones
+/// so existing entries aren't all announced again as edits.
+fn fingerprint_matches(stored: &str, text: &str) -> bool {
+    if stored.len() == LEGACY_MD5_LEN {
+        stored == format!("{:x}", md5::compute(text))
     } else {
-        info!("No Valkey configuration available, skipping Valkey connectivity and persistence");
-        None
+        stored == sha256_hex(text)
+    }
+}
+
+/// Parts of a post that changed since it was last announced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Changes {
+    title: bool,
+    content: bool,
+}
+
+/// What a reconcile did with the posts in the feed. Every post is counted in
+/// exactly one of `new`, `updated`, `unchanged`, `skipped`, `circuit_open`,
+/// `deferred`, `pending`, `dead_lettered`, `seeded` or `errors`.
+#[derive(Debug, Default, Serialize, PartialEq, Eq)]
+pub struct ReconcileSummary {
+    pub feed_title: String,
+    pub total: usize,
+    pub new: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    /// Posts left alone for being outside `MIN_POST_AGE`/`MAX_POST_AGE` or
+    /// `ANNOUNCE_CATEGORIES`.
+    pub skipped: usize,
+    /// Posts not sent while Slack's circuit breaker was open, to be tried
+    /// again next reconcile.
+    pub circuit_open: usize,
+    /// New posts left for the next reconcile once `MAX_POSTS_PER_RECONCILE`
+    /// had been announced.
+    pub deferred: usize,
+    /// New posts held back until `QUIET_HOURS` are over.
+    pub pending: usize,
+    /// Posts left alone for having failed `DEAD_LETTER_AFTER` times in a row.
+    pub dead_lettered: usize,
+    /// Posts archived without being announced, on a first run with `SEED_ONLY`.
+    pub seeded: usize,
+    pub errors: usize,
+    /// The part of `errors` caused by the notifier rejecting a post or update.
+    pub slack_errors: usize,
+    /// Set when the feed hadn't changed since the last reconcile, in which
+    /// case no posts were looked at.
+    pub not_modified: bool,
+    /// What went wrong with each post counted in `errors`.
+    pub failures: Vec<PostFailure>,
+    /// What was done with each post, in the order they were handled. Only
+    /// reported by previews.
+    #[serde(skip)]
+    pub actions: Vec<PostAction>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct PostAction {
+    /// The archive key of the post, or its link when it has none.
+    pub post: String,
+    pub title: String,
+    /// One of `new`, `updated`, `unchanged`, `skipped`, `circuit_open`,
+    /// `deferred`, `pending`, `dead_letter`, `seeded` or `error`.
+    pub action: &'static str,
+}
+
+/// What a reconcile would do with the feed, without having done any of it.
+#[derive(Debug, Serialize)]
+pub struct ReconcilePreview {
+    #[serde(flatten)]
+    pub summary: ReconcileSummary,
+    pub actions: Vec<PostAction>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct PostFailure {
+    /// The archive key of the post, or its link when it has none.
+    pub post: String,
+    pub error: String,
+}
+
+impl ReconcileSummary {
+    /// True when posts failed and none got announced or updated, so the
+    /// reconcile achieved nothing it set out to.
+    pub fn failed_entirely(&self) -> bool {
+        self.errors > 0 && self.new + self.updated == 0
+    }
+}
+
+#[instrument(skip(xml, store, app_state))]
+pub async fn handle_feed(
+    xml: &str,
+    store: &mut dyn ValkeyClient,
+    app_state: &config::AppState,
+) -> Result<ReconcileSummary, FeedError> {
+    let started = Instant::now();
+    let feed = parse_feed(xml)?;
+    info!("Found {} posts in {}", feed.posts.len(), feed.title);
+    check_feed_title(&feed.title, app_state);
+
+    let archive_config = archive_config(app_state);
+
+    let token = lock_token();
+    match store.set_if_absent(LOCK_KEY, &token, LOCK_TTL).await {
+        Ok(true) => {}
+        Ok(false) => return Err(FeedError::ReconcileInProgress),
+        Err(err) => return Err(FeedError::Lock(err.to_string())),
+    }
+
+    let (mut paced, mut guarded) = (None, None);
+    let notifier = reconcile_notifier(app_state, &mut paced, &mut guarded);
+
+    let kept_roots = restore_thread_roots(store, app_state.notifier.as_ref()).await;
+    let total = feed.posts.len();
+    let summary = reconcile_posts(feed.posts, store, notifier, app_state, &archive_config)
+        .instrument(feed_span(&feed.title))
+        .await;
+    save_thread_roots(store, app_state.notifier.as_ref(), &kept_roots).await;
+
+    if let Err(err) = store.delete_if_equals(LOCK_KEY, &token).await {
+        error!(error = %err, "Failed releasing reconcile lock, it will expire on its own");
+    }
+
+    let summary = ReconcileSummary {
+        feed_title: feed.title,
+        total,
+        ..summary
     };
+    app_state
+        .metrics
+        .observe_reconcile(&summary, started.elapsed());
 
-    let slack_client: Box<dyn SlackClient> = if app_state.config.is_dry_run() {
-        Box::new(StdoutSlackClient::default())
-    } else {
-        match app_state.config.slack_config() {
-            Ok(cfg) => Box::new(HttpSlackClient::new(
-                cfg.clone(),
-                app_state.http_client.clone(),
-            )),
-            Err(e) => {
-                error!("Slack configuration missing when trying to post: {e}");
-                Box::new(StdoutSlackClient::default())
-            }
+    Ok(summary)
+}
+
+/// The notifier a reconcile sends through: `app_state.notifier`, spaced out
+/// by `SLACK_POST_DELAY_MS` and kept from Slack while its circuit breaker is
+/// open. `paced` and `guarded` hold the wrappers for as long as it is used.
+fn reconcile_notifier<'a>(
+    app_state: &'a config::AppState,
+    paced: &'a mut Option<PacedNotifier<'a>>,
+    guarded: &'a mut Option<BreakerNotifier<'a>>,
+) -> &'a dyn Notifier {
+    let mut notifier: &'a dyn Notifier = app_state.notifier.as_ref();
+    if !app_state.post_delay.is_zero() {
+        notifier = paced.insert(PacedNotifier::new(notifier, app_state.post_delay));
+    }
+    if let Some(breaker) = &app_state.slack_breaker {
+        notifier = guarded.insert(BreakerNotifier::new(notifier, breaker));
+    }
+    notifier
+}
+
+/// Sends the post keyed `key` in the feed right away: announces it if it
+/// hasn't been, or brings its announcement up to date even if nothing
+/// changed. It goes through the same notifier and `ANNOUNCE_CATEGORIES` and
+/// `ALLOWED_LINK_HOSTS` checks as a reconcile, coming back as `skipped` when
+/// those leave it out and `circuit_open` when Slack's breaker is open.
+/// `None` when the feed has no such post.
+pub async fn replay_post(
+    xml: &str,
+    key: &str,
+    store: &mut dyn ValkeyClient,
+    app_state: &config::AppState,
+) -> Result<Option<PostAction>, FeedError> {
+    let feed = parse_feed(xml)?;
+    let Some(item) = feed
+        .posts
+        .into_iter()
+        .find(|post| post.key().as_deref() == Some(key))
+    else {
+        return Ok(None);
+    };
+    if filtered_out(&item, app_state) {
+        return Ok(Some(PostAction {
+            post: key.to_string(),
+            title: item.title,
+            action: "skipped",
+        }));
+    }
+    let archive_config = archive_config(app_state);
+
+    let token = lock_token();
+    match store.set_if_absent(LOCK_KEY, &token, LOCK_TTL).await {
+        Ok(true) => {}
+        Ok(false) => return Err(FeedError::ReconcileInProgress),
+        Err(err) => return Err(FeedError::Lock(err.to_string())),
+    }
+
+    let span = info_span!("post", key = %key, title = %item.title, action = field::Empty);
+    let stored = prefetch_archives(std::slice::from_ref(&item), store)
+        .await
+        .pop()
+        .unwrap_or(Ok(None));
+    let archive = match stored {
+        Ok(raw) => raw
+            .and_then(|raw| serde_json::from_str::<Archive>(&raw).ok())
+            .filter(|archive| !archive.pending),
+        Err(err) => {
+            error!(key, error = %err, "Failed getting key from Redis, announcing the post as new");
+            None
         }
     };
+    let mut writes = ArchiveWrites::default();
+    let (mut paced, mut guarded) = (None, None);
+    let notifier = reconcile_notifier(app_state, &mut paced, &mut guarded);
+    let kept_roots = restore_thread_roots(store, app_state.notifier.as_ref()).await;
+    let outcome = match &archive {
+        None => {
+            announce_post(&item, key, &mut writes, notifier)
+                .instrument(span)
+                .await
+        }
+        Some(archive) => {
+            let changes = Changes {
+                title: true,
+                content: true,
+            };
+            update_post(
+                &item,
+                key,
+                archive,
+                changes,
+                &mut writes,
+                notifier,
+                &archive_config,
+            )
+            .instrument(span)
+            .await
+        }
+    };
+    let mut summary = ReconcileSummary::default();
+    record_outcome(
+        &mut summary,
+        store,
+        &archive_config,
+        &item,
+        key.to_string(),
+        outcome,
+    )
+    .await;
+    writes.flush(store, archive_config.ttl, &mut summary).await;
+    save_thread_roots(store, app_state.notifier.as_ref(), &kept_roots).await;
 
-    for item in doc.channel.posts {
-        let key = item
-            .link
-            .split('#')
-            .collect::<Vec<&str>>()
-            .get(1)
-            .copied()
-            .unwrap_or(&item.link);
-        info!(
-            post_key = %key,
-            title = %item.title,
-            pub_date = %item.pub_date,
-            "Handling post"
+    if let Err(err) = store.delete_if_equals(LOCK_KEY, &token).await {
+        error!(error = %err, "Failed releasing reconcile lock, it will expire on its own");
+    }
+    Ok(summary.actions.pop())
+}
+
+/// Works out what a reconcile would do with the feed, against Redis as it is
+/// now, without writing to it or announcing anything.
+pub async fn preview_feed(
+    xml: &str,
+    store: &mut dyn ValkeyClient,
+    app_state: &config::AppState,
+) -> Result<ReconcilePreview, FeedError> {
+    let feed = parse_feed(xml)?;
+    info!("Previewing {} posts in {}", feed.posts.len(), feed.title);
+    check_feed_title(&feed.title, app_state);
+
+    let total = feed.posts.len();
+    let mut summary = reconcile_posts(
+        feed.posts,
+        &mut ReadOnlyValkey(store),
+        &PreviewNotifier,
+        app_state,
+        &archive_config(app_state),
+    )
+    .instrument(feed_span(&feed.title))
+    .await;
+    let actions = std::mem::take(&mut summary.actions);
+
+    Ok(ReconcilePreview {
+        summary: ReconcileSummary {
+            feed_title: feed.title,
+            total,
+            ..summary
+        },
+        actions,
+    })
+}
+
+/// Span the posts of a feed are gone through in, so the log lines of every
+/// post say which feed it came from.
+fn feed_span(title: &str) -> Span {
+    info_span!("feed", feed_title = %title)
+}
+
+/// Warns when the feed isn't titled `EXPECTED_FEED_TITLE`, which likely
+/// means `FEED_URL` points at the wrong feed. Its posts are still handled,
+/// as a renamed feed shouldn't stop announcements.
+fn check_feed_title(title: &str, app_state: &config::AppState) {
+    let Some(expected) = app_state.expected_feed_title.as_deref() else {
+        return;
+    };
+    if title.trim() != expected.trim() {
+        warn!(
+            expected,
+            found = title,
+            "Feed title doesn't match EXPECTED_FEED_TITLE, check FEED_URL"
         );
+    }
+}
 
-        let hashed_post = format!(
-            "{:x}",
-            md5::compute(format!("{}-{}", item.title, item.content))
-        );
-
-        if let Some(store) = &mut redis_client {
-            match store.get(&key).await {
-                Ok(None) => {
-                    info!(post_key = %key, "New post, pushing to Slack");
-                    match slack_client.post_message(&item).await {
-                        Ok(response) => {
-                            let archive = Archive {
-                                hash: hashed_post,
-                                timestamp: response.ts,
-                            };
-                            let raw = serde_json::to_string(&archive).map_err(|e| {
-                                FeedError::SerializeArchive {
-                                    key: key.to_string(),
-                                    error: e.to_string(),
-                                }
-                            })?;
-                            match store.set(&key, &raw).await {
-                                Ok(()) => {
-                                    info!(post_key = %key, "Posted to Slack, and saved to Redis")
-                                }
-                                Err(err) => {
-                                    error!(post_key = %key, error = %err, "Failed saving to Redis")
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            error!(post_key = %key, error = %err, "Failed posting to Slack")
-                        }
-                    };
-                }
-                Ok(Some(raw)) => {
-                    let mut archive = serde_json::from_str::<Archive>(&raw).map_err(|e| {
-                        FeedError::InvalidArchive {
-                            key: key.to_string(),
-                            error: e.to_string(),
-                        }
-                    })?;
-                    if archive.hash == hashed_post {
-                        info!(post_key = %key, "No changes here");
-                        // Continue processing the rest of the feed; an older post
-                        // might still have changed even if this one has not.
-                        continue;
-                    }
-
-                    info!(post_key = %key, "Post has changed, updating Slack");
-                    match slack_client.update_message(&item, &archive.timestamp).await {
-                        Ok(_) => {
-                            archive.hash = hashed_post;
-                            let raw = serde_json::to_string(&archive).map_err(|e| {
-                                FeedError::SerializeArchive {
-                                    key: key.to_string(),
-                                    error: e.to_string(),
-                                }
-                            })?;
-                            match store.set(&key, &raw).await {
-                                Ok(()) => {
-                                    info!(post_key = %key, "Finished updating Slack, and Redis")
-                                }
-                                Err(err) => {
-                                    error!(post_key = %key, error = %err, "Failed saving to Redis")
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            error!(post_key = %key, error = %err, "Failed posting to Slack")
-                        }
-                    };
-                }
-                Err(err) => error!(post_key = %key, error = %err, "Failed getting key from Redis"),
-            }
-        } else {
-            let preview = format!(
-                "<{}|{}>\n{}",
-                item.link,
-                item.title,
-                slack::format_slack_post(&item.content)
-            );
-            info!(
-                post_key = %key,
-                title = %item.title,
-                "No Redis connection available (DRY_RUN or connection error), would post Slack message and skip persistence"
-            );
-            tracing::debug!(post_key = %key, %preview, "DRY_RUN Slack preview body");
+fn archive_config(app_state: &config::AppState) -> ArchiveConfig {
+    app_state
+        .config
+        .valkey_config()
+        .map(|cfg| cfg.archive.clone())
+        .unwrap_or_default()
+}
+
+/// Seeds the archive on a first run with `SEED_ONLY`, and announces the
+/// posts otherwise.
+async fn reconcile_posts(
+    posts: Vec<Post>,
+    store: &mut dyn ValkeyClient,
+    notifier: &dyn Notifier,
+    app_state: &config::AppState,
+    archive_config: &ArchiveConfig,
+) -> ReconcileSummary {
+    if archive_config.seed_only && keyspace_is_fresh(store).await {
+        seed_posts(&posts, store, archive_config).await
+    } else {
+        announce_posts(posts, store, notifier, app_state, archive_config).await
+    }
+}
+
+/// Whether `ANNOUNCE_CATEGORIES` or `ALLOWED_LINK_HOSTS` keep `post` from
+/// being announced.
+fn filtered_out(post: &Post, app_state: &config::AppState) -> bool {
+    if !app_state.categories.allows(&post.categories) {
+        info!(title = %post.title, categories = ?post.categories, "Post not in an announced category, skipping");
+        return true;
+    }
+    if !app_state.link_hosts.allows(&post.link) {
+        warn!(title = %post.title, link = %post.link, "Post links outside ALLOWED_LINK_HOSTS, skipping");
+        return true;
+    }
+    false
+}
+
+/// Announces the posts within the allowed age, in `ANNOUNCE_ORDER`, and no
+/// more new ones than `max_new_posts`.
+async fn announce_posts(
+    mut posts: Vec<Post>,
+    store: &mut dyn ValkeyClient,
+    notifier: &dyn Notifier,
+    app_state: &config::AppState,
+    archive_config: &ArchiveConfig,
+) -> ReconcileSummary {
+    let now = app_state.clock.now();
+    match app_state.announce_order {
+        AnnounceOrder::Feed => {}
+        AnnounceOrder::OldestFirst => posts.sort_by_key(|post| post.pub_date),
+        AnnounceOrder::NewestFirst => posts.sort_by_key(|post| Reverse(post.pub_date)),
+    }
+    let (posts, skipped): (Vec<Post>, Vec<Post>) = posts.into_iter().partition(|post| {
+        let age = (now - post.pub_date).to_std().unwrap_or_default();
+        if !app_state.post_age.allows(age) {
+            info!(title = %post.title, pub_date = %post.pub_date, "Post outside the allowed age, skipping");
+            return false;
         }
+        !filtered_out(post, app_state)
+    });
+    let mut actions: Vec<PostAction> = skipped
+        .iter()
+        .map(|post| PostAction {
+            post: post.key().unwrap_or_else(|| post.link.clone()),
+            title: post.title.clone(),
+            action: "skipped",
+        })
+        .collect();
+
+    let quiet = app_state
+        .quiet_hours
+        .is_some_and(|quiet_hours| quiet_hours.contains(now));
+    if quiet {
+        info!("Within QUIET_HOURS, holding back new posts");
+    }
+    let mut summary = sync_posts(
+        &posts,
+        store,
+        notifier,
+        archive_config,
+        app_state.max_new_posts,
+        quiet,
+    )
+    .await;
+    actions.append(&mut summary.actions);
+    ReconcileSummary {
+        skipped: skipped.len() + summary.skipped,
+        actions,
+        ..summary
     }
+}
 
-    Ok(())
+/// Whether Valkey holds nothing but reconcile locks and Slack thread roots,
+/// neither of which says anything was announced. Our own lock may or may not
+/// be among them, as a preview doesn't take it.
+async fn keyspace_is_fresh(store: &mut dyn ValkeyClient) -> bool {
+    match store.scan_keys("*").await {
+        Ok(keys) => keys
+            .iter()
+            .all(|key| is_source_key(key, LOCK_KEY) || is_source_key(key, THREAD_ROOTS_KEY)),
+        Err(err) => {
+            error!(error = %err, "Failed counting keys in Redis, not seeding");
+            false
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::handle_feed;
-    use crate::config::{AppConfig, AppState};
+/// Archives every post in the feed as it is now without announcing any, so
+/// only posts that show up later get announced.
+async fn seed_posts(
+    posts: &[Post],
+    store: &mut dyn ValkeyClient,
+    archive_config: &ArchiveConfig,
+) -> ReconcileSummary {
+    info!("Redis is empty, seeding the archive without announcing anything");
+    let mut summary = ReconcileSummary::default();
+    let mut writes = ArchiveWrites::default();
+    for item in posts {
+        let Some(key) = item.key() else {
+            missing_key(&mut summary, item);
+            continue;
+        };
+        let archive = Archive::new(item, MessageIds::new());
+        let action = match save_archive(&mut writes, &key, &archive) {
+            Ok(()) => {
+                summary.seeded += 1;
+                "seeded"
+            }
+            Err(error) => {
+                summary.errors += 1;
+                summary.failures.push(PostFailure {
+                    post: key.clone(),
+                    error,
+                });
+                "error"
+            }
+        };
+        summary.actions.push(PostAction {
+            post: key,
+            title: item.title.clone(),
+            action,
+        });
+    }
+    writes.flush(store, archive_config.ttl, &mut summary).await;
+    summary
+}
 
-    const SAMPLE_RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
-<rss version="2.0">
-  <channel>
-    <title>NAIS Log</title>
-    <item>
-      <title>Test Post</title>
-      <link>https://nais.io/log#test-post</link>
-      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
-      <encoded><![CDATA[This is **content** with a [link](https://example.com).]]></encoded>
-    </item>
-  </channel>
-</rss>"#;
+/// Counts a post whose link gives no archive key as failed.
+fn missing_key(summary: &mut ReconcileSummary, item: &Post) {
+    error!(link = %item.link, title = %item.title, "Post link has no fragment, skipping");
+    summary.errors += 1;
+    summary.failures.push(PostFailure {
+        post: item.link.clone(),
+        error: "Post link has no fragment".to_string(),
+    });
+    summary.actions.push(PostAction {
+        post: item.link.clone(),
+        title: item.title.clone(),
+        action: "error",
+    });
+}
 
-    #[tokio::test]
-    async fn handle_feed_succeeds_in_dry_run() {
-        let config = AppConfig::DryRun;
-        let state = AppState::new(config);
+/// Identifies this reconcile as the lock holder, so it never releases a lock
+/// that expired and was taken by someone else.
+fn lock_token() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_default();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("{host}-{}-{nanos}", std::process::id())
+}
+
+/// Derives the archive key for a post from the fragment of its link, e.g.
+/// `https://nais.io/log#some-post` becomes `some-post`.
+fn key_from_link(link: &str) -> Option<String> {
+    link.split_once('#')
+        .map(|(_, fragment)| fragment)
+        .filter(|fragment| !fragment.is_empty())
+        .map(str::to_string)
+}
 
-        let result = handle_feed(SAMPLE_RSS, &state).await;
-        assert!(result.is_ok());
+/// Archive entries written during a reconcile, saved to Redis together once
+/// it's done with the feed rather than a round-trip per post.
+#[derive(Debug, Default)]
+struct ArchiveWrites(Vec<(String, String)>);
+
+impl ArchiveWrites {
+    /// Saves the entries in one pipeline. Should that fail, every post whose
+    /// entry didn't make it is counted as failed.
+    async fn flush(
+        self,
+        store: &mut dyn ValkeyClient,
+        ttl: Option<Duration>,
+        summary: &mut ReconcileSummary,
+    ) {
+        let Err(err) = store.set_many(&self.0, ttl).await else {
+            return;
+        };
+        let keys: Vec<String> = self.0.into_iter().map(|(key, _)| key).collect();
+        error!(error = %err, keys = ?keys, "Failed saving archive entries to Redis");
+        for action in &mut summary.actions {
+            if keys.contains(&action.post) {
+                action.action = "error";
+            }
+        }
+        for key in keys {
+            summary.errors += 1;
+            summary.failures.push(PostFailure {
+                post: key,
+                error: format!("Failed saving to Redis: {err}"),
+            });
+        }
+    }
+}
+
+/// What happened to a single post during a reconcile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    New,
+    Updated,
+    Unchanged,
+    Deferred,
+    Pending,
+    /// New, and left for after the feed is gone through to see whether it
+    /// goes out on its own or in a digest.
+    Collected,
+    /// Not sent, as the circuit breaker had stopped calling Slack.
+    CircuitOpen,
+    Error(String),
+    NotifierError(String),
+}
+
+/// What came of a notifier call failing with `err`: left for later when the
+/// circuit breaker turned it away before anything was sent, else an error.
+fn notifier_failure(context: &str, err: &Error) -> Outcome {
+    if CircuitOpen::caused(err) {
+        info!("Slack circuit is open, skipping");
+        return Outcome::CircuitOpen;
+    }
+    error!(error = %err, "{context}");
+    Outcome::NotifierError(format!("{context}: {err}"))
+}
+
+/// Reads the archive entries of the posts that have a key, in order, with
+/// one round-trip rather than one per post. A post keyed by its guid with
+/// nothing stored under it gets the entry under its link fragment, if any,
+/// so posts announced before guids were used aren't announced again.
+async fn prefetch_archives(
+    posts: &[Post],
+    store: &mut dyn ValkeyClient,
+) -> Vec<Result<Option<String>, String>> {
+    let keyed: Vec<(&Post, String)> = posts
+        .iter()
+        .filter_map(|item| item.key().map(|key| (item, key)))
+        .collect();
+    let keys: Vec<String> = keyed.iter().map(|(_, key)| key.clone()).collect();
+    let mut values = match store.get_many(&keys).await {
+        Ok(values) => values,
+        Err(err) => {
+            error!(error = %err, "Failed getting keys from Redis");
+            let error = format!("Failed getting key from Redis: {err}");
+            return keys.iter().map(|_| Err(error.clone())).collect();
+        }
+    };
+
+    let (missing, old_keys): (Vec<usize>, Vec<String>) = keyed
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| values[*i].is_none())
+        .filter_map(|(i, (item, key))| {
+            key_from_link(&item.link)
+                .filter(|old_key| old_key != key)
+                .map(|old_key| (i, old_key))
+        })
+        .unzip();
+    if !old_keys.is_empty() {
+        match store.get_many(&old_keys).await {
+            Ok(found) => {
+                for (i, value) in missing.into_iter().zip(found) {
+                    values[i] = value;
+                }
+            }
+            Err(err) => error!(error = %err, "Failed getting keys from before guids from Redis"),
+        }
+    }
+    values.into_iter().map(Ok).collect()
+}
+
+/// Announces new posts and updates changed ones. With `quiet` set, new posts
+/// are archived as pending instead, to be announced by a later reconcile.
+async fn sync_posts(
+    posts: &[Post],
+    store: &mut dyn ValkeyClient,
+    notifier: &dyn Notifier,
+    archive_config: &ArchiveConfig,
+    max_new_posts: usize,
+    quiet: bool,
+) -> ReconcileSummary {
+    let mut summary = ReconcileSummary::default();
+    let mut writes = ArchiveWrites::default();
+    let mut new_posts = NewPosts {
+        allowed: max_new_posts,
+        quiet,
+        collect: archive_config.digest_threshold.is_some(),
+    };
+    let mut collected = Vec::new();
+
+    let mut stored = prefetch_archives(posts, store).await.into_iter();
+    let dead_letters = match archive_config.dead_letter_after {
+        Some(_) => failures::dead_letter_keys(store)
+            .await
+            .unwrap_or_else(|err| {
+                error!(error = %err, "Failed getting dead letters from Redis");
+                HashSet::new()
+            }),
+        None => HashSet::new(),
+    };
+
+    for item in posts {
+        let Some(key) = item.key() else {
+            missing_key(&mut summary, item);
+            continue;
+        };
+        let raw = stored.next().unwrap_or(Ok(None));
+        if dead_letters.contains(&key) {
+            info!(key = %key, title = %item.title, "Post is in the dead letters, skipping");
+            summary.dead_lettered += 1;
+            summary.actions.push(PostAction {
+                post: key,
+                title: item.title.clone(),
+                action: "dead_letter",
+            });
+            continue;
+        }
+
+        let span = info_span!(
+            "post",
+            key = %key,
+            title = %item.title,
+            action = field::Empty
+        );
+        let outcome = sync_post(
+            item,
+            &key,
+            raw,
+            &mut writes,
+            notifier,
+            archive_config,
+            &mut new_posts,
+        )
+        .instrument(span)
+        .await;
+        if outcome == Outcome::Collected {
+            collected.push((item, key));
+            continue;
+        }
+        record_outcome(&mut summary, store, archive_config, item, key, outcome).await;
+    }
+
+    if let Some(threshold) = archive_config.digest_threshold {
+        let outcomes = announce_collected(&collected, &mut writes, notifier, threshold).await;
+        for ((item, key), outcome) in collected.into_iter().zip(outcomes) {
+            record_outcome(&mut summary, store, archive_config, item, key, outcome).await;
+        }
+    }
+
+    writes.flush(store, archive_config.ttl, &mut summary).await;
+
+    if summary.deferred > 0 {
+        warn!(
+            deferred = summary.deferred,
+            max_new_posts, "Too many new posts for one reconcile, leaving the rest for the next"
+        );
+    }
+    summary
+}
+
+/// Tallies what happened to a post in the summary, keeping count of its
+/// failures for the dead letters.
+async fn record_outcome(
+    summary: &mut ReconcileSummary,
+    store: &mut dyn ValkeyClient,
+    archive_config: &ArchiveConfig,
+    item: &Post,
+    key: String,
+    outcome: Outcome,
+) {
+    if let Some(max_attempts) = archive_config.dead_letter_after {
+        match &outcome {
+            Outcome::New | Outcome::Updated => failures::clear_failures(store, &key).await,
+            Outcome::NotifierError(error) => {
+                if let Err(err) = failures::record_failure(store, &key, error, max_attempts).await {
+                    error!(key = %key, error = %err, "Failed counting failure in Redis");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let action = match outcome {
+        Outcome::New => {
+            summary.new += 1;
+            "new"
+        }
+        Outcome::Collected => unreachable!("Collected posts are announced before being recorded"),
+        Outcome::Updated => {
+            summary.updated += 1;
+            "updated"
+        }
+        Outcome::Unchanged => {
+            summary.unchanged += 1;
+            "unchanged"
+        }
+        Outcome::Deferred => {
+            summary.deferred += 1;
+            "deferred"
+        }
+        Outcome::CircuitOpen => {
+            summary.circuit_open += 1;
+            "circuit_open"
+        }
+        Outcome::Pending => {
+            summary.pending += 1;
+            "pending"
+        }
+        Outcome::Error(error) => {
+            summary.errors += 1;
+            summary.failures.push(PostFailure {
+                post: key.clone(),
+                error,
+            });
+            "error"
+        }
+        Outcome::NotifierError(error) => {
+            summary.errors += 1;
+            summary.slack_errors += 1;
+            summary.failures.push(PostFailure {
+                post: key.clone(),
+                error,
+            });
+            "error"
+        }
+    };
+    summary.actions.push(PostAction {
+        post: key,
+        title: item.title.clone(),
+        action,
+    });
+}
+
+/// Announces the new posts collected while going through the feed: in one
+/// digest when there are at least `threshold` of them, one by one otherwise.
+async fn announce_collected(
+    posts: &[(&Post, String)],
+    writes: &mut ArchiveWrites,
+    notifier: &dyn Notifier,
+    threshold: usize,
+) -> Vec<Outcome> {
+    if posts.len() < threshold {
+        let mut outcomes = Vec::with_capacity(posts.len());
+        for (item, key) in posts {
+            let span = info_span!("post", key = %key, title = %item.title, action = "new");
+            outcomes.push(
+                announce_post(item, key, writes, notifier)
+                    .instrument(span)
+                    .await,
+            );
+        }
+        return outcomes;
+    }
+
+    info!(
+        count = posts.len(),
+        threshold, "Many new posts at once, announcing them in a digest"
+    );
+    let items: Vec<&Post> = posts.iter().map(|(item, _)| *item).collect();
+    if let Err(err) = notifier.post_digest(&items).await {
+        let outcome = notifier_failure("Failed announcing digest", &err);
+        return posts.iter().map(|_| outcome.clone()).collect();
+    }
+    // Editing the digest for one post would drop the others from it, so a
+    // post that changes later is announced on its own instead.
+    posts
+        .iter()
+        .map(|(item, key)| {
+            match save_archive(writes, key, &Archive::new(item, MessageIds::new())) {
+                Ok(()) => Outcome::New,
+                Err(err) => Outcome::Error(err),
+            }
+        })
+        .collect()
+}
+
+/// What a reconcile may still do with posts it hasn't announced before.
+struct NewPosts {
+    /// How many more may be announced before the rest are deferred.
+    allowed: usize,
+    /// Hold them back as pending rather than announce them.
+    quiet: bool,
+    /// Collect them to announce once the whole feed is gone through.
+    collect: bool,
+}
+
+/// Announces or updates a single post given its archive entry as read from
+/// Redis, recording on the current span which of the two it turned out to be.
+async fn sync_post(
+    item: &Post,
+    key: &str,
+    raw: Result<Option<String>, String>,
+    writes: &mut ArchiveWrites,
+    notifier: &dyn Notifier,
+    archive_config: &ArchiveConfig,
+    new_posts: &mut NewPosts,
+) -> Outcome {
+    let span = Span::current();
+    info!(pub_date = %item.pub_date, "Handling post");
+
+    let existing = match raw {
+        Ok(None) => None,
+        Ok(Some(raw)) => match serde_json::from_str::<Archive>(&raw) {
+            Ok(archive) => Some(archive),
+            Err(err) => match archive_config.corrupt {
+                CorruptArchivePolicy::Repost => {
+                    error!(error = %err, "Invalid archive JSON in Redis, treating post as new");
+                    None
+                }
+                CorruptArchivePolicy::Skip => {
+                    error!(error = %err, "Invalid archive JSON in Redis, skipping post");
+                    return Outcome::Error(format!("Invalid archive JSON in Redis: {err}"));
+                }
+            },
+        },
+        Err(err) => return Outcome::Error(err),
+    };
+
+    if new_posts.quiet && existing.as_ref().is_none_or(|archive| archive.pending) {
+        return hold_post(item, key, writes);
+    }
+    // Posts held back during quiet hours are announced like new ones.
+    let existing = existing.filter(|archive| !archive.pending);
+
+    match existing {
+        None if new_posts.allowed == 0 => {
+            span.record("action", "deferred");
+            info!("New post over the limit for this reconcile, deferring it");
+            Outcome::Deferred
+        }
+        None => {
+            new_posts.allowed -= 1;
+            span.record("action", "new");
+            if new_posts.collect {
+                return Outcome::Collected;
+            }
+            announce_post(item, key, writes, notifier).await
+        }
+        Some(archive) => match archive.changes(item) {
+            Changes {
+                title: false,
+                content: false,
+            } => {
+                span.record("action", "unchanged");
+                // Not an early exit from the feed; an older post might still
+                // have changed even if this one has not.
+                info!("No changes here");
+                Outcome::Unchanged
+            }
+            Changes {
+                title: true,
+                content: false,
+            } if archive_config.title_edits == TitleEditPolicy::Ignore => {
+                info!("Only the title changed, leaving the announcement as it is");
+                remember_changes(item, key, &archive, writes)
+            }
+            _ if archive_config.updates == UpdateMode::Ignore => {
+                info!(
+                    "Post has changed, but updates are ignored; leaving the announcement as it is"
+                );
+                remember_changes(item, key, &archive, writes)
+            }
+            changes => {
+                update_post(
+                    item,
+                    key,
+                    &archive,
+                    changes,
+                    writes,
+                    notifier,
+                    archive_config,
+                )
+                .await
+            }
+        },
+    }
+}
+
+/// Announces a post not seen before on its own.
+async fn announce_post(
+    item: &Post,
+    key: &str,
+    writes: &mut ArchiveWrites,
+    notifier: &dyn Notifier,
+) -> Outcome {
+    info!("New post, announcing it");
+    let ids = match notifier.post(item).await {
+        Ok(ids) => ids,
+        Err(err) => return notifier_failure("Failed announcing post", &err),
+    };
+    let archive = Archive::new(item, ids);
+    match save_archive(writes, key, &archive) {
+        Ok(()) => {
+            info!("Announced post");
+            Outcome::New
+        }
+        Err(err) => Outcome::Error(err),
+    }
+}
+
+/// Archives a new post as pending without announcing it, for the first
+/// reconcile after `QUIET_HOURS` to pick up.
+fn hold_post(item: &Post, key: &str, writes: &mut ArchiveWrites) -> Outcome {
+    Span::current().record("action", "pending");
+    info!("New post during quiet hours, holding it back");
+    let archive = Archive {
+        pending: true,
+        ..Archive::new(item, MessageIds::new())
+    };
+    match save_archive(writes, key, &archive) {
+        Ok(()) => Outcome::Pending,
+        Err(err) => Outcome::Error(err),
+    }
+}
+
+/// Stores what a post looks like now without touching its announcement, so
+/// the change isn't picked up again on the next reconcile.
+fn remember_changes(
+    item: &Post,
+    key: &str,
+    archive: &Archive,
+    writes: &mut ArchiveWrites,
+) -> Outcome {
+    Span::current().record("action", "unchanged");
+    let archive = Archive::new(item, archive.message_ids());
+    match save_archive(writes, key, &archive) {
+        Ok(()) => Outcome::Unchanged,
+        Err(err) => Outcome::Error(err),
+    }
+}
+
+/// Brings the announcement of a post that changed up to date, by editing it
+/// or announcing the post anew depending on `UPDATE_MODE`.
+async fn update_post(
+    item: &Post,
+    key: &str,
+    archive: &Archive,
+    changes: Changes,
+    writes: &mut ArchiveWrites,
+    notifier: &dyn Notifier,
+    archive_config: &ArchiveConfig,
+) -> Outcome {
+    Span::current().record("action", "updated");
+    info!(
+        title_changed = changes.title,
+        content_changed = changes.content,
+        "Post has changed, updating announcement"
+    );
+    let result = match archive_config.updates {
+        UpdateMode::Repost => notifier.post(item).await.map(|ids| Archive::new(item, ids)),
+        UpdateMode::Thread => {
+            let roots = archive.message_ids();
+            notifier.reply(item, &roots).await.map(|replies| Archive {
+                replies: split_channels(replies).0,
+                ..Archive::new(item, roots)
+            })
+        }
+        UpdateMode::Edit | UpdateMode::Ignore => notifier
+            .update(item, &archive.message_ids())
+            .await
+            .map(|ids| Archive::new(item, ids)),
+    };
+    let archive = match result {
+        Ok(archive) => archive,
+        Err(err) => return notifier_failure("Failed updating announcement", &err),
+    };
+    match save_archive(writes, key, &archive) {
+        Ok(()) => {
+            info!("Finished updating announcement");
+            Outcome::Updated
+        }
+        Err(err) => Outcome::Error(err),
+    }
+}
+
+/// Serializes an archive entry to be saved with the rest of the reconcile,
+/// logging whatever goes wrong.
+fn save_archive(writes: &mut ArchiveWrites, key: &str, archive: &Archive) -> Result<(), String> {
+    let raw = serde_json::to_string(archive).map_err(|err| {
+        error!(error = %err, "Failed serializing archive, skipping Redis write");
+        format!("Failed serializing archive: {err}")
+    })?;
+    writes.0.push((key.to_string(), raw));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Archive, Changes, FeedError, FeedKind, LOCK_KEY, Post, PostAction, PostFailure,
+        ReconcileSummary, THREAD_ROOTS_KEY, announce_posts, clear_archive, dedup_posts,
+        handle_feed, key_from_link, parse_date, parse_feed, preview_feed, replay_post, sync_posts,
+    };
+    use crate::{
+        circuit_breaker::CircuitBreaker,
+        clock::FixedClock,
+        config::{
+            AnnounceOrder, AppConfig, AppState, ArchiveConfig, CategoryFilter,
+            CorruptArchivePolicy, DiscordConfig, LinkHostFilter, NotifierConfig, PostAgeFilter,
+            QuietHours, TitleEditPolicy, UpdateMode, ValkeyConfig,
+        },
+        notifier::{MessageIds, Notifier, single_message},
+        redis_client::{InMemoryValkey, PrefixedValkey, ValkeyClient},
+    };
+    use async_trait::async_trait;
+    use chrono::{DateTime, TimeZone, Utc};
+    use redis::{ErrorKind, RedisError, RedisResult};
+    use std::{
+        collections::HashMap,
+        io::Error,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::time::Instant;
+    use tracing_test::traced_test;
+
+    #[derive(Default)]
+    struct RecordingNotifier {
+        /// Channels to announce in; none means a single destination.
+        channels: Vec<&'static str>,
+        posted: Mutex<Vec<String>>,
+        updated: Mutex<Vec<String>>,
+        updated_ids: Mutex<Vec<MessageIds>>,
+        replied: Mutex<Vec<MessageIds>>,
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn post(&self, post: &Post) -> Result<MessageIds, Error> {
+            self.posted.lock().unwrap().push(post.title.clone());
+            if self.channels.is_empty() {
+                return Ok(single_message("1700000000.000100".to_string()));
+            }
+            Ok(self
+                .channels
+                .iter()
+                .map(|channel| (channel.to_string(), format!("{channel}-1700000000.000100")))
+                .collect())
+        }
+
+        async fn update(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+            self.updated.lock().unwrap().push(post.title.clone());
+            self.updated_ids.lock().unwrap().push(ids.clone());
+            Ok(ids.clone())
+        }
+
+        async fn reply(&self, _post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+            self.replied.lock().unwrap().push(ids.clone());
+            Ok(single_message("1700000000.000200".to_string()))
+        }
+    }
+
+    fn post(title: &str, fragment: &str, content: &str) -> Post {
+        Post {
+            title: title.to_string(),
+            link: format!("https://nais.io/log#{fragment}"),
+            pub_date: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            content: content.to_string(),
+            categories: Vec::new(),
+            guid: None,
+        }
+    }
+
+    fn corrupt(policy: CorruptArchivePolicy) -> ArchiveConfig {
+        ArchiveConfig {
+            corrupt: policy,
+            ..ArchiveConfig::default()
+        }
+    }
+
+    fn legacy_hash_of(post: &Post) -> String {
+        format!(
+            "{:x}",
+            md5::compute(format!("{}-{}", post.title, post.content))
+        )
+    }
+
+    fn archived(post: &Post) -> String {
+        let archive = Archive::new(post, single_message("1600000000.000100".to_string()));
+        serde_json::to_string(&archive).unwrap()
+    }
+
+    const SAMPLE_RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Test Post</title>
+      <link>https://nais.io/log#test-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[This is **content** with a [link](https://example.com).]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+
+    const SAMPLE_ATOM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>NAIS Log</title>
+  <id>https://nais.io/log</id>
+  <updated>2024-01-01T00:00:00Z</updated>
+  <link href="https://nais.io/log/atom.xml" rel="self"/>
+  <entry>
+    <title>Test Post</title>
+    <link href="https://nais.io/log#test-post"/>
+    <id>https://nais.io/log#test-post</id>
+    <updated>2024-01-01T00:00:00Z</updated>
+    <content type="html"><![CDATA[This is **content** with a [link](https://example.com).]]></content>
+  </entry>
+</feed>"#;
+
+    const SAMPLE_JSON: &str = r#"{
+  "version": "https://jsonfeed.org/version/1.1",
+  "title": "NAIS Log",
+  "home_page_url": "https://nais.io/log",
+  "items": [
+    {
+      "id": "https://nais.io/log#test-post",
+      "url": "https://nais.io/log#test-post",
+      "title": "Test Post",
+      "content_html": "This is **content** with a [link](https://example.com).",
+      "date_published": "2024-01-01T00:00:00Z"
+    }
+  ]
+}"#;
+
+    #[test]
+    fn json_feed_yields_the_same_posts_keyed_by_id() {
+        let rss = parse_feed(SAMPLE_RSS).unwrap();
+        let json = parse_feed(SAMPLE_JSON).unwrap();
+
+        assert_eq!(rss.title, json.title);
+        assert_eq!(
+            json.posts,
+            vec![Post {
+                guid: Some("https://nais.io/log#test-post".to_string()),
+                ..post(
+                    "Test Post",
+                    "test-post",
+                    "This is **content** with a [link](https://example.com)."
+                )
+            }]
+        );
+        assert_eq!(rss.posts[0].pub_date, json.posts[0].pub_date);
+        assert_eq!(
+            json.posts[0].key().as_deref(),
+            Some("https://nais.io/log#test-post")
+        );
+    }
+
+    #[test]
+    fn detects_feed_kind_from_root_element() {
+        assert_eq!(FeedKind::detect(SAMPLE_RSS).unwrap(), FeedKind::Rss);
+        assert_eq!(FeedKind::detect(SAMPLE_ATOM).unwrap(), FeedKind::Atom);
+        assert_eq!(FeedKind::detect(SAMPLE_JSON).unwrap(), FeedKind::Json);
+        assert!(FeedKind::detect("<html></html>").is_err());
+        assert!(FeedKind::detect(r#"{"version":"2.0","items":[]}"#).is_err());
+    }
+
+    #[test]
+    fn empty_feed_is_told_apart_from_broken_xml() {
+        assert!(matches!(parse_feed(""), Err(FeedError::EmptyBody)));
+        assert!(matches!(parse_feed(" \n\t "), Err(FeedError::EmptyBody)));
+        assert!(matches!(
+            parse_feed("Service Unavailable"),
+            Err(FeedError::RssParse(_))
+        ));
+    }
+
+    #[test]
+    fn rss_and_atom_yield_identical_posts() {
+        let rss = parse_feed(SAMPLE_RSS).unwrap();
+        let atom = parse_feed(SAMPLE_ATOM).unwrap();
+
+        assert_eq!(rss.title, atom.title);
+        assert_eq!(rss.posts, atom.posts);
+        assert_eq!(
+            atom.posts,
+            vec![post(
+                "Test Post",
+                "test-post",
+                "This is **content** with a [link](https://example.com)."
+            )]
+        );
+    }
+
+    const NAMESPACED_RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"
+     xmlns:atom="http://www.w3.org/2005/Atom"
+     xmlns:c="http://purl.org/rss/1.0/modules/content/"
+     xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <channel>
+    <title>NAIS Log</title>
+    <atom:link href="https://nais.io/log/rss.xml" rel="self" type="application/rss+xml"/>
+    <item>
+      <title>Test Post</title>
+      <link>https://nais.io/log#test-post</link>
+      <atom:link href="https://nais.io/log/rss.xml" rel="self"/>
+      <dc:creator>Nais</dc:creator>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <description>A teaser.</description>
+      <c:encoded><![CDATA[This is **content** with a [link](https://example.com).]]></c:encoded>
+    </item>
+  </channel>
+</rss>"#;
+
+    const DUBLIN_CORE_RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Test Post</title>
+      <link>https://nais.io/log#test-post</link>
+      <dc:date>2024-01-01T00:00:00Z</dc:date>
+      <description><![CDATA[This is **content** with a [link](https://example.com).]]></description>
+    </item>
+  </channel>
+</rss>"#;
+
+    const NAMESPACED_ATOM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<a:feed xmlns:a="http://www.w3.org/2005/Atom" xmlns:media="http://search.yahoo.com/mrss/">
+  <a:title>NAIS Log</a:title>
+  <a:entry>
+    <a:title>Test Post</a:title>
+    <a:link href="https://nais.io/log#test-post"/>
+    <a:updated>2024-01-01T00:00:00Z</a:updated>
+    <media:content url="https://nais.io/og.png" medium="image"/>
+    <a:content type="html"><![CDATA[This is **content** with a [link](https://example.com).]]></a:content>
+  </a:entry>
+</a:feed>"#;
+
+    #[test]
+    fn namespaced_elements_are_read_by_namespace_not_prefix() {
+        let expected = vec![post(
+            "Test Post",
+            "test-post",
+            "This is **content** with a [link](https://example.com).",
+        )];
+
+        for feed in [NAMESPACED_RSS, DUBLIN_CORE_RSS, NAMESPACED_ATOM] {
+            let parsed = parse_feed(feed).unwrap();
+            assert_eq!(parsed.title, "NAIS Log");
+            assert_eq!(parsed.posts, expected, "feed: {feed}");
+        }
+    }
+
+    #[test]
+    fn parses_post_dates() {
+        let expected = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        assert_eq!(
+            parse_date(
+                "Mon, 01 Jan 2024 01:00:00 +0100",
+                DateTime::parse_from_rfc2822
+            ),
+            expected
+        );
+        assert_eq!(
+            parse_date("2024-01-01T00:00:00Z", DateTime::parse_from_rfc3339),
+            expected
+        );
+    }
+
+    #[test]
+    fn unparseable_date_defaults_to_now() {
+        let before = Utc::now();
+        let date = parse_date("sometime last week", DateTime::parse_from_rfc2822);
+
+        assert!(date >= before && date <= Utc::now());
+    }
+
+    #[tokio::test]
+    async fn posts_outside_allowed_age_are_skipped() {
+        let state = AppState::new(AppConfig::DryRun).with_post_age(PostAgeFilter {
+            min: None,
+            max: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        });
+        let mut store = InMemoryValkey::new();
+
+        let summary = handle_feed(SAMPLE_RSS, &mut store, &state).await.unwrap();
+
+        assert_eq!(summary.total, 1);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.new, 0);
+        assert!(store.get("test-post").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn post_age_is_measured_against_the_clock() {
+        // The post is from 2024-01-01 00:00, so 30 days old on the 31st.
+        let state_at = |now: DateTime<Utc>| {
+            AppState::new(AppConfig::DryRun)
+                .with_post_age(PostAgeFilter {
+                    min: None,
+                    max: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+                })
+                .with_clock(FixedClock(now))
+        };
+        let thirty_days = Utc.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap();
+
+        let fresh = handle_feed(
+            SAMPLE_RSS,
+            &mut InMemoryValkey::new(),
+            &state_at(thirty_days),
+        )
+        .await
+        .unwrap();
+        let stale = handle_feed(
+            SAMPLE_RSS,
+            &mut InMemoryValkey::new(),
+            &state_at(thirty_days + chrono::Duration::seconds(1)),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((fresh.new, fresh.skipped), (1, 0));
+        assert_eq!((stale.new, stale.skipped), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn quiet_hours_are_checked_against_the_clock() {
+        let state_at = |hour: u32| {
+            AppState::new(AppConfig::DryRun)
+                .with_quiet_hours(Some(QuietHours::parse("22:00-07:00", "UTC").unwrap()))
+                .with_clock(FixedClock(
+                    Utc.with_ymd_and_hms(2024, 1, 2, hour, 0, 0).unwrap(),
+                ))
+        };
+
+        let night = handle_feed(SAMPLE_RSS, &mut InMemoryValkey::new(), &state_at(23))
+            .await
+            .unwrap();
+        let day = handle_feed(SAMPLE_RSS, &mut InMemoryValkey::new(), &state_at(12))
+            .await
+            .unwrap();
+
+        assert_eq!((night.new, night.pending), (0, 1));
+        assert_eq!((day.new, day.pending), (1, 0));
+    }
+
+    const CATEGORIZED_RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Security Post</title>
+      <link>https://nais.io/log#security-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <category>Release</category>
+      <category>Security</category>
+      <encoded><![CDATA[Patch now.]]></encoded>
+    </item>
+    <item>
+      <title>Release Post</title>
+      <link>https://nais.io/log#release-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <category>Release</category>
+      <encoded><![CDATA[New version.]]></encoded>
+    </item>
+    <item>
+      <title>Plain Post</title>
+      <link>https://nais.io/log#plain-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[No categories.]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+
+    #[test]
+    fn parses_rss_and_atom_categories() {
+        let rss = parse_feed(CATEGORIZED_RSS).unwrap();
+        let categories: Vec<_> = rss.posts.iter().map(|p| p.categories.clone()).collect();
+        assert_eq!(
+            categories,
+            vec![vec!["Release", "Security"], vec!["Release"], vec![]]
+        );
+
+        let atom = SAMPLE_ATOM.replace(
+            "<updated>2024-01-01T00:00:00Z</updated>\n    <content",
+            "<updated>2024-01-01T00:00:00Z</updated>\n    <category term=\"Security\"/>\n    <content",
+        );
+        let atom = parse_feed(&atom).unwrap();
+        assert_eq!(atom.posts[0].categories, vec!["Security"]);
+    }
+
+    #[tokio::test]
+    async fn posts_outside_announced_categories_are_skipped() {
+        let state =
+            AppState::new(AppConfig::DryRun).with_categories(CategoryFilter::parse("security"));
+        let mut store = InMemoryValkey::new();
+
+        let summary = handle_feed(CATEGORIZED_RSS, &mut store, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.total, 3);
+        assert_eq!(summary.new, 1);
+        assert_eq!(summary.skipped, 2);
+        assert!(store.get("security-post").await.unwrap().is_some());
+        assert!(store.get("release-post").await.unwrap().is_none());
+        assert!(store.get("plain-post").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn posts_linking_to_other_hosts_are_skipped() {
+        let feed = TWO_POST_RSS
+            .replace(
+                "https://nais.io/log#second-post",
+                "https://nais.io.evil.example/log#second-post",
+            )
+            .replace("https://nais.io/log#first-post", "not a url#first-post");
+        let state =
+            AppState::new(AppConfig::DryRun).with_link_hosts(LinkHostFilter::parse("nais.io"));
+        let mut store = InMemoryValkey::new();
+
+        let summary = handle_feed(&feed, &mut store, &state).await.unwrap();
+
+        assert_eq!(summary.new, 0);
+        assert_eq!(summary.skipped, 2);
+        assert!(store.get("second-post").await.unwrap().is_none());
+        assert!(store.get("first-post").await.unwrap().is_none());
+
+        let summary = handle_feed(TWO_POST_RSS, &mut store, &state).await.unwrap();
+        assert_eq!(summary.new, 2);
+        assert_eq!(summary.skipped, 0);
+    }
+
+    #[tokio::test]
+    async fn empty_category_filter_announces_everything() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+
+        let summary = handle_feed(CATEGORIZED_RSS, &mut store, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.new, 3);
+        assert_eq!(summary.skipped, 0);
+    }
+
+    const TWO_POST_RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Second Post</title>
+      <link>https://nais.io/log#second-post</link>
+      <pubDate>Tue, 02 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[More content.]]></encoded>
+    </item>
+    <item>
+      <title>First Post</title>
+      <link>https://nais.io/log#first-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Content.]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+
+    /// Notes when each post is announced, on tokio's clock.
+    #[derive(Default)]
+    struct TimedNotifier {
+        posted_at: Mutex<Vec<Instant>>,
+    }
+
+    #[async_trait]
+    impl Notifier for TimedNotifier {
+        async fn post(&self, _post: &Post) -> Result<MessageIds, Error> {
+            self.posted_at.lock().unwrap().push(Instant::now());
+            Ok(single_message("1700000000.000100".to_string()))
+        }
+
+        async fn update(&self, _post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+            Ok(ids.clone())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn post_delay_is_waited_out_between_posts() {
+        let notifier = Arc::new(TimedNotifier::default());
+        let mut state =
+            AppState::new(AppConfig::DryRun).with_post_delay(Duration::from_millis(1500));
+        state.notifier = notifier.clone();
+        let start = Instant::now();
+
+        let summary = handle_feed(TWO_POST_RSS, &mut InMemoryValkey::new(), &state)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.new, 2);
+        let posted_at = notifier.posted_at.lock().unwrap();
+        assert_eq!(posted_at[0], start);
+        assert_eq!(posted_at[1] - posted_at[0], Duration::from_millis(1500));
+    }
+
+    fn seeding_state(notifier: Arc<RecordingNotifier>) -> AppState {
+        let config = AppConfig::Normal {
+            valkey: ValkeyConfig {
+                uri: "redis://localhost:6379".to_string(),
+                archive: ArchiveConfig {
+                    seed_only: true,
+                    ..ArchiveConfig::default()
+                },
+            },
+            notifier: NotifierConfig::Discord(DiscordConfig {
+                webhook_url: "http://localhost/webhook".to_string(),
+            }),
+        };
+        let mut state = AppState::new(config);
+        state.notifier = notifier;
+        state
+    }
+
+    #[tokio::test]
+    async fn seed_only_archives_without_announcing_on_empty_store() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let state = seeding_state(notifier.clone());
+        let mut store = InMemoryValkey::new();
+
+        let summary = handle_feed(TWO_POST_RSS, &mut store, &state).await.unwrap();
+
+        assert_eq!(summary.seeded, 2);
+        assert_eq!(summary.new, 0);
+        assert!(notifier.posted.lock().unwrap().is_empty());
+        assert_eq!(store.scan_keys("*").await.unwrap().len(), 2);
+        for key in ["first-post", "second-post"] {
+            assert!(store.get(key).await.unwrap().is_some(), "{key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn seed_only_announces_normally_once_store_has_keys() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let state = seeding_state(notifier.clone());
+        let mut store = InMemoryValkey::new();
+        store
+            .set(
+                "first-post",
+                &archived(&post("First Post", "first-post", "Content.")),
+            )
+            .await
+            .unwrap();
+
+        let summary = handle_feed(TWO_POST_RSS, &mut store, &state).await.unwrap();
+
+        assert_eq!(summary.seeded, 0);
+        assert_eq!(summary.new, 1);
+        assert_eq!(*notifier.posted.lock().unwrap(), vec!["Second Post"]);
+    }
+
+    #[tokio::test]
+    async fn seeding_preview_sees_a_single_archived_post() {
+        let state = seeding_state(Arc::new(RecordingNotifier::default()));
+        let mut store = InMemoryValkey::new();
+        store
+            .set(
+                "first-post",
+                &archived(&post("First Post", "first-post", "Content.")),
+            )
+            .await
+            .unwrap();
+
+        let preview = preview_feed(TWO_POST_RSS, &mut store, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(preview.summary.seeded, 0);
+        assert_eq!(preview.summary.new, 1);
+    }
+
+    #[tokio::test]
+    async fn preview_plans_actions_without_writing() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut state = AppState::new(AppConfig::DryRun);
+        state.notifier = notifier.clone();
+        let mut store = InMemoryValkey::new();
+        let stale = archived(&post("First Post", "first-post", "Old content."));
+        store.set("first-post", &stale).await.unwrap();
+
+        let preview = preview_feed(TWO_POST_RSS, &mut store, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(preview.summary.total, 2);
+        assert_eq!(preview.summary.new, 1);
+        assert_eq!(preview.summary.updated, 1);
+        assert_eq!(
+            preview.actions,
+            vec![
+                PostAction {
+                    post: "first-post".to_string(),
+                    title: "First Post".to_string(),
+                    action: "updated",
+                },
+                PostAction {
+                    post: "second-post".to_string(),
+                    title: "Second Post".to_string(),
+                    action: "new",
+                },
+            ]
+        );
+        assert!(notifier.posted.lock().unwrap().is_empty());
+        assert!(notifier.updated.lock().unwrap().is_empty());
+        assert_eq!(store.scan_keys("*").await.unwrap().len(), 1);
+        assert_eq!(store.get("first-post").await.unwrap(), Some(stale));
+    }
+
+    #[tokio::test]
+    async fn handle_feed_succeeds_in_dry_run() {
+        let config = AppConfig::DryRun;
+        let state = AppState::new(config);
+
+        let mut store = InMemoryValkey::new();
+
+        let summary = handle_feed(SAMPLE_RSS, &mut store, &state).await.unwrap();
+        assert_eq!(summary.feed_title, "NAIS Log");
+        assert_eq!(summary.total, 1);
+        assert_eq!(summary.new, 1);
+
+        // The synthetic "dry-run" timestamp only comes from the stdout client,
+        // so seeing it in the archive means no request went to Slack.
+        let raw = store.get("test-post").await.unwrap().unwrap();
+        let archive: Archive = serde_json::from_str(&raw).unwrap();
+        assert_eq!(archive.timestamp, "dry-run");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn warns_when_the_feed_title_is_unexpected() {
+        let state = AppState::new(AppConfig::DryRun)
+            .with_expected_feed_title(Some("NAIS Changelog".to_string()));
+
+        let summary = handle_feed(SAMPLE_RSS, &mut InMemoryValkey::new(), &state)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.new, 1);
+        assert!(logs_contain(
+            "Feed title doesn't match EXPECTED_FEED_TITLE, check FEED_URL"
+        ));
+        assert!(logs_contain("found=\"NAIS Log\""));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn post_logs_carry_the_feed_title() {
+        let state =
+            AppState::new(AppConfig::DryRun).with_expected_feed_title(Some("NAIS Log".to_string()));
+
+        handle_feed(SAMPLE_RSS, &mut InMemoryValkey::new(), &state)
+            .await
+            .unwrap();
+
+        assert!(!logs_contain("EXPECTED_FEED_TITLE"));
+        assert!(logs_contain("feed{feed_title=NAIS Log}:post{"));
+    }
+
+    #[tokio::test]
+    async fn handle_feed_releases_lock() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+
+        handle_feed(SAMPLE_RSS, &mut store, &state).await.unwrap();
+
+        assert_eq!(store.get(LOCK_KEY).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn handle_feed_refuses_while_locked() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+        store.set(LOCK_KEY, "other-replica").await.unwrap();
+
+        let result = handle_feed(SAMPLE_RSS, &mut store, &state).await;
+
+        assert!(matches!(result, Err(FeedError::ReconcileInProgress)));
+        assert_eq!(store.get("test-post").await.unwrap(), None);
+        assert_eq!(
+            store.get(LOCK_KEY).await.unwrap().as_deref(),
+            Some("other-replica")
+        );
+    }
+
+    #[tokio::test]
+    async fn clear_archive_forgets_everything_but_the_lock() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+        handle_feed(TWO_POST_RSS, &mut store, &state).await.unwrap();
+        store.set("failures:other-post", "2").await.unwrap();
+        store.set(LOCK_KEY, "other-replica").await.unwrap();
+
+        assert_eq!(clear_archive(&mut store).await.unwrap(), 3);
+
+        assert_eq!(store.scan_keys("*").await.unwrap().len(), 1);
+        assert!(store.get(LOCK_KEY).await.unwrap().is_some());
+        store.del(LOCK_KEY).await.unwrap();
+        let summary = handle_feed(TWO_POST_RSS, &mut store, &state).await.unwrap();
+        assert_eq!(summary.new, 2);
+    }
+
+    #[tokio::test]
+    async fn clear_archive_spares_the_lock_of_every_source() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+        for prefix in ["log", "status"] {
+            let mut source = PrefixedValkey::new(prefix, &mut store);
+            handle_feed(SAMPLE_RSS, &mut source, &state).await.unwrap();
+            source.set(LOCK_KEY, "other-replica").await.unwrap();
+        }
+
+        assert_eq!(clear_archive(&mut store).await.unwrap(), 2);
+
+        let mut left = store.scan_keys("*").await.unwrap();
+        left.sort();
+        assert_eq!(left, vec!["log:reconcile:lock", "status:reconcile:lock"]);
+    }
+
+    #[tokio::test]
+    async fn clear_archive_keeps_the_thread_roots() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+        handle_feed(SAMPLE_RSS, &mut store, &state).await.unwrap();
+        store
+            .set(THREAD_ROOTS_KEY, r#"{"C01":"1700000000.000100"}"#)
+            .await
+            .unwrap();
+        PrefixedValkey::new("status", &mut store)
+            .set(THREAD_ROOTS_KEY, r#"{"C02":"1700000000.000200"}"#)
+            .await
+            .unwrap();
+
+        assert_eq!(clear_archive(&mut store).await.unwrap(), 1);
+
+        let mut left = store.scan_keys("*").await.unwrap();
+        left.sort();
+        assert_eq!(
+            left,
+            vec!["slack:thread_roots", "status:slack:thread_roots"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unchanged_post_does_not_stop_later_posts() {
+        let unchanged = post("Old Post", "old-post", "Nothing new");
+        let fresh = post("New Post", "new-post", "Brand new");
+
+        let mut store = InMemoryValkey::new();
+        store.set("old-post", &archived(&unchanged)).await.unwrap();
+
+        let notifier = RecordingNotifier::default();
+        sync_posts(
+            &[unchanged, fresh],
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(*notifier.posted.lock().unwrap(), vec!["New Post"]);
+        assert!(notifier.updated.lock().unwrap().is_empty());
+        assert!(store.get("new-post").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn batched_writes_match_writing_each_post() {
+        let unchanged = post("Old Post", "old-post", "Nothing new");
+        let edited = post("Edited Post", "edited-post", "Fixed a typo");
+        let fresh = post("New Post", "new-post", "Brand new");
+        let keys: Vec<String> = ["old-post", "edited-post", "new-post"]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+
+        let mut store = InMemoryValkey::new();
+        store.set("old-post", &archived(&unchanged)).await.unwrap();
+        store
+            .set(
+                "edited-post",
+                &archived(&post("Edited Post", "edited-post", "Fixed a tpyo")),
+            )
+            .await
+            .unwrap();
+        let posts = [unchanged, edited, fresh];
+        sync_posts(
+            &posts,
+            &mut store,
+            &RecordingNotifier::default(),
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        let mut per_write = InMemoryValkey::new();
+        per_write
+            .set("old-post", &archived(&posts[0]))
+            .await
+            .unwrap();
+        per_write
+            .set("edited-post", &archived(&posts[1]))
+            .await
+            .unwrap();
+        let announced = Archive::new(&posts[2], single_message("1700000000.000100".to_string()));
+        per_write
+            .set("new-post", &serde_json::to_string(&announced).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_many(&keys).await.unwrap(),
+            per_write.get_many(&keys).await.unwrap()
+        );
+    }
+
+    /// Titles of the posts announced from a feed listing them newest first,
+    /// with the one published in between left last.
+    async fn announced_in(order: AnnounceOrder) -> Vec<String> {
+        let dated = |title: &str, fragment: &str, day: u32| Post {
+            pub_date: Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap(),
+            ..post(title, fragment, "Content")
+        };
+        let posts = vec![
+            dated("Newest", "newest", 3),
+            dated("Oldest", "oldest", 1),
+            dated("Middle", "middle", 2),
+        ];
+        let notifier = RecordingNotifier::default();
+        let state = AppState::new(AppConfig::DryRun)
+            .with_announce_order(order)
+            .with_clock(FixedClock(
+                Utc.with_ymd_and_hms(2024, 1, 4, 0, 0, 0).unwrap(),
+            ));
+
+        announce_posts(
+            posts,
+            &mut InMemoryValkey::new(),
+            &notifier,
+            &state,
+            &ArchiveConfig::default(),
+        )
+        .await;
+        notifier.posted.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn announces_oldest_first_by_default() {
+        assert_eq!(
+            announced_in(AnnounceOrder::default()).await,
+            vec!["Oldest", "Middle", "Newest"]
+        );
+    }
+
+    #[tokio::test]
+    async fn announces_newest_first_when_asked() {
+        assert_eq!(
+            announced_in(AnnounceOrder::NewestFirst).await,
+            vec!["Newest", "Middle", "Oldest"]
+        );
+    }
+
+    #[tokio::test]
+    async fn announces_in_feed_order_when_asked() {
+        assert_eq!(
+            announced_in(AnnounceOrder::Feed).await,
+            vec!["Newest", "Oldest", "Middle"]
+        );
+    }
+
+    #[tokio::test]
+    async fn few_new_posts_are_announced_individually() {
+        let posts = [
+            post("First Post", "first-post", "One"),
+            post("Second Post", "second-post", "Two"),
+        ];
+        let notifier = RecordingNotifier::default();
+        let archive_config = ArchiveConfig {
+            digest_threshold: Some(3),
+            ..ArchiveConfig::default()
+        };
+
+        let summary = sync_posts(
+            &posts,
+            &mut InMemoryValkey::new(),
+            &notifier,
+            &archive_config,
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(summary.new, 2);
+        assert_eq!(
+            *notifier.posted.lock().unwrap(),
+            vec!["First Post", "Second Post"]
+        );
+    }
+
+    #[tokio::test]
+    async fn many_new_posts_are_announced_in_a_digest() {
+        let posts = [
+            post("First Post", "first-post", "One"),
+            post("Second Post", "second-post", "Two"),
+            post("Third Post", "third-post", "Three"),
+        ];
+        let mut store = InMemoryValkey::new();
+        let notifier = RecordingNotifier::default();
+        let archive_config = ArchiveConfig {
+            digest_threshold: Some(3),
+            ..ArchiveConfig::default()
+        };
+
+        let summary = sync_posts(
+            &posts,
+            &mut store,
+            &notifier,
+            &archive_config,
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(summary.new, 3);
+        assert_eq!(*notifier.posted.lock().unwrap(), vec!["3 new posts"]);
+        for (item, key) in posts
+            .iter()
+            .zip(["first-post", "second-post", "third-post"])
+        {
+            let saved = store.get(key).await.unwrap().expect("post archived");
+            assert_eq!(
+                saved,
+                serde_json::to_string(&Archive::new(item, MessageIds::new())).unwrap()
+            );
+        }
+
+        // Nothing is new the next time round.
+        sync_posts(
+            &posts,
+            &mut store,
+            &notifier,
+            &archive_config,
+            usize::MAX,
+            false,
+        )
+        .await;
+        assert_eq!(notifier.posted.lock().unwrap().len(), 1);
+    }
+
+    /// Holds on to everything but a batch of writes, which it refuses.
+    struct UnbatchableValkey(InMemoryValkey);
+
+    #[async_trait]
+    impl ValkeyClient for UnbatchableValkey {
+        async fn get(&mut self, key: &str) -> RedisResult<Option<String>> {
+            self.0.get(key).await
+        }
+
+        async fn get_many(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>> {
+            self.0.get_many(keys).await
+        }
+
+        async fn set(&mut self, key: &str, value: &str) -> RedisResult<()> {
+            self.0.set(key, value).await
+        }
+
+        async fn set_with_ttl(&mut self, key: &str, value: &str, ttl: Duration) -> RedisResult<()> {
+            self.0.set_with_ttl(key, value, ttl).await
+        }
+
+        async fn set_many(
+            &mut self,
+            _entries: &[(String, String)],
+            _ttl: Option<Duration>,
+        ) -> RedisResult<()> {
+            Err(RedisError::from((ErrorKind::IoError, "connection reset")))
+        }
+
+        async fn set_if_absent(
+            &mut self,
+            key: &str,
+            value: &str,
+            ttl: Duration,
+        ) -> RedisResult<bool> {
+            self.0.set_if_absent(key, value, ttl).await
+        }
+
+        async fn del(&mut self, key: &str) -> RedisResult<bool> {
+            self.0.del(key).await
+        }
+
+        async fn del_many(&mut self, keys: &[String]) -> RedisResult<usize> {
+            self.0.del_many(keys).await
+        }
+
+        async fn delete_if_equals(&mut self, key: &str, value: &str) -> RedisResult<()> {
+            self.0.delete_if_equals(key, value).await
+        }
+
+        async fn scan_keys(&mut self, pattern: &str) -> RedisResult<Vec<String>> {
+            self.0.scan_keys(pattern).await
+        }
+
+        async fn ping(&mut self) -> RedisResult<()> {
+            self.0.ping().await
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn failed_batch_reports_unsaved_posts() {
+        let unchanged = post("Old Post", "old-post", "Nothing new");
+        let fresh = post("New Post", "new-post", "Brand new");
+        let mut store = UnbatchableValkey(InMemoryValkey::new());
+        store.set("old-post", &archived(&unchanged)).await.unwrap();
+
+        let summary = sync_posts(
+            &[unchanged, fresh],
+            &mut store,
+            &RecordingNotifier::default(),
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(summary.new, 1);
+        assert_eq!(summary.errors, 1);
+        assert_eq!(summary.failures[0].post, "new-post");
+        assert!(summary.failures[0].error.contains("connection reset"));
+        let actions: Vec<_> = summary.actions.iter().map(|action| action.action).collect();
+        assert_eq!(actions, vec!["unchanged", "error"]);
+        assert!(logs_contain("Failed saving archive entries to Redis"));
+        assert!(logs_contain("new-post"));
+    }
+
+    #[tokio::test]
+    async fn quiet_hours_hold_new_posts_until_they_are_over() {
+        let posts = vec![post("Some Post", "some-post", "Content")];
+        let mut store = InMemoryValkey::new();
+        let notifier = RecordingNotifier::default();
+        let config = ArchiveConfig::default();
+
+        for _ in 0..2 {
+            let summary = sync_posts(&posts, &mut store, &notifier, &config, 10, true).await;
+
+            assert_eq!(summary.pending, 1);
+            assert!(notifier.posted.lock().unwrap().is_empty());
+            let archive: Archive =
+                serde_json::from_str(&store.get("some-post").await.unwrap().unwrap()).unwrap();
+            assert!(archive.pending);
+        }
+
+        let summary = sync_posts(&posts, &mut store, &notifier, &config, 10, false).await;
+
+        assert_eq!(summary.new, 1);
+        assert_eq!(*notifier.posted.lock().unwrap(), vec!["Some Post"]);
+        let archive: Archive =
+            serde_json::from_str(&store.get("some-post").await.unwrap().unwrap()).unwrap();
+        assert!(!archive.pending);
+        assert_eq!(archive.timestamp, "1700000000.000100");
+    }
+
+    #[tokio::test]
+    async fn quiet_hours_still_update_announced_posts() {
+        let original = post("Some Post", "some-post", "Content");
+        let mut store = InMemoryValkey::new();
+        store.set("some-post", &archived(&original)).await.unwrap();
+        let notifier = RecordingNotifier::default();
+
+        let edited = vec![post("Some Post", "some-post", "Edited content")];
+        let summary = sync_posts(
+            &edited,
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            10,
+            true,
+        )
+        .await;
+
+        assert_eq!(summary.updated, 1);
+        assert_eq!(summary.pending, 0);
+    }
+
+    /// Rejects every post and update, like a channel the app was removed from.
+    struct FailingNotifier;
+
+    #[async_trait]
+    impl Notifier for FailingNotifier {
+        async fn post(&self, _post: &Post) -> Result<MessageIds, Error> {
+            Err(Error::other("channel_not_found"))
+        }
+
+        async fn update(&self, _post: &Post, _ids: &MessageIds) -> Result<MessageIds, Error> {
+            Err(Error::other("channel_not_found"))
+        }
+    }
+
+    #[tokio::test]
+    async fn open_breaker_skips_posts_instead_of_failing_them() {
+        let mut state = AppState::new(AppConfig::DryRun);
+        state.notifier = Arc::new(FailingNotifier);
+        state.slack_breaker = Some(Arc::new(CircuitBreaker::new(1, Duration::from_secs(60))));
+
+        let summary = handle_feed(TWO_POST_RSS, &mut InMemoryValkey::new(), &state)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.errors, 1);
+        assert_eq!((summary.skipped, summary.circuit_open), (0, 1));
+        assert!(summary.failures[0].error.contains("channel_not_found"));
+    }
+
+    #[tokio::test]
+    async fn post_failing_too_often_is_dead_lettered() {
+        let posts = vec![post("Some Post", "some-post", "Content")];
+        let mut store = InMemoryValkey::new();
+        let config = ArchiveConfig {
+            dead_letter_after: Some(2),
+            ..ArchiveConfig::default()
+        };
+
+        for _ in 0..2 {
+            let summary =
+                sync_posts(&posts, &mut store, &FailingNotifier, &config, 10, false).await;
+            assert_eq!(summary.errors, 1);
+        }
+        assert!(store.get("deadletter:some-post").await.unwrap().is_some());
+
+        let summary = sync_posts(&posts, &mut store, &FailingNotifier, &config, 10, false).await;
+
+        assert_eq!(summary.errors, 0);
+        assert_eq!(summary.dead_lettered, 1);
+        assert_eq!(summary.actions[0].action, "dead_letter");
+    }
+
+    #[tokio::test]
+    async fn announcing_clears_earlier_failures() {
+        let posts = vec![post("Some Post", "some-post", "Content")];
+        let mut store = InMemoryValkey::new();
+        let config = ArchiveConfig {
+            dead_letter_after: Some(3),
+            ..ArchiveConfig::default()
+        };
+
+        sync_posts(&posts, &mut store, &FailingNotifier, &config, 10, false).await;
+        assert_eq!(
+            store.get("failures:some-post").await.unwrap().as_deref(),
+            Some("1")
+        );
+
+        let summary = sync_posts(
+            &posts,
+            &mut store,
+            &RecordingNotifier::default(),
+            &config,
+            10,
+            false,
+        )
+        .await;
+
+        assert_eq!(summary.new, 1);
+        assert_eq!(store.get("failures:some-post").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn new_posts_beyond_the_cap_are_deferred() {
+        let posts: Vec<Post> = (0..50)
+            .map(|n| post(&format!("Post {n}"), &format!("post-{n}"), "Content"))
+            .collect();
+        let mut store = InMemoryValkey::new();
+        let notifier = RecordingNotifier::default();
+
+        let summary = sync_posts(
+            &posts,
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            10,
+            false,
+        )
+        .await;
+
+        assert_eq!(notifier.posted.lock().unwrap().len(), 10);
+        assert_eq!(summary.new, 10);
+        assert_eq!(summary.deferred, 40);
+        assert!(store.get("post-9").await.unwrap().is_some());
+        assert!(store.get("post-10").await.unwrap().is_none());
+
+        let summary = sync_posts(
+            &posts,
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            10,
+            false,
+        )
+        .await;
+
+        assert_eq!(notifier.posted.lock().unwrap().len(), 20);
+        assert_eq!(summary.unchanged, 10);
+        assert_eq!(summary.new, 10);
+        assert_eq!(summary.deferred, 30);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn post_span_records_key_title_and_action() {
+        let unchanged = post("Old Post", "old-post", "Nothing new");
+        let fresh = post("New Post", "new-post", "Brand new");
+
+        let mut store = InMemoryValkey::new();
+        store.set("old-post", &archived(&unchanged)).await.unwrap();
+
+        let notifier = RecordingNotifier::default();
+        sync_posts(
+            &[unchanged, fresh],
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert!(logs_contain(
+            r#"post{key=old-post title=Old Post action="unchanged"}: announcer::rss: No changes here"#
+        ));
+        assert!(logs_contain(
+            r#"post{key=new-post title=New Post action="new"}: announcer::rss: Announced post"#
+        ));
+    }
+
+    const GUID_RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Guid Post</title>
+      <link>https://nais.io/log/guid-post</link>
+      <guid isPermaLink="false">nais-log-42</guid>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Content.]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+
+    #[test]
+    #[traced_test]
+    fn duplicate_posts_keep_the_last() {
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Some Post</title>
+      <link>https://nais.io/log#some-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[First.]]></encoded>
+    </item>
+    <item>
+      <title>Other Post</title>
+      <link>https://nais.io/log#other-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Other.]]></encoded>
+    </item>
+    <item>
+      <title>Some Post</title>
+      <link>https://nais.io/log#some-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Second.]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+
+        let feed = parse_feed(feed).unwrap();
+
+        let contents: Vec<&str> = feed.posts.iter().map(|p| p.content.as_str()).collect();
+        assert_eq!(contents, vec!["Other.", "Second."]);
+        assert!(logs_contain("Feed has the same post more than once"));
+    }
+
+    #[tokio::test]
+    async fn duplicate_posts_are_announced_once() {
+        let posts = dedup_posts(vec![
+            post("Some Post", "some-post", "First"),
+            post("Some Post", "some-post", "Second"),
+        ]);
+        let mut store = InMemoryValkey::new();
+        let notifier = RecordingNotifier::default();
+
+        let summary = sync_posts(
+            &posts,
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(summary.new, 1);
+        assert_eq!(notifier.posted.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn key_prefers_guid_over_fragment() {
+        let feed = parse_feed(GUID_RSS).unwrap();
+        assert_eq!(feed.posts[0].guid.as_deref(), Some("nais-log-42"));
+        assert_eq!(feed.posts[0].key().as_deref(), Some("nais-log-42"));
+
+        let without_guid = post("Some Post", "some-post", "Content");
+        assert_eq!(without_guid.key().as_deref(), Some("some-post"));
+
+        let with_both = Post {
+            guid: Some("https://nais.io/log#some-post-v2".to_string()),
+            ..post("Some Post", "some-post", "Content")
+        };
+        assert_eq!(
+            with_both.key().as_deref(),
+            Some("https://nais.io/log#some-post-v2")
+        );
+
+        let blank_guid = Post {
+            guid: Some("  ".to_string()),
+            ..post("Some Post", "some-post", "Content")
+        };
+        assert_eq!(blank_guid.key().as_deref(), Some("some-post"));
+    }
+
+    #[tokio::test]
+    async fn post_with_guid_is_archived_under_it() {
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+
+        let summary = handle_feed(GUID_RSS, &mut store, &state).await.unwrap();
+
+        assert_eq!(summary.new, 1);
+        assert!(store.get("nais-log-42").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn guid_post_finds_entry_under_its_fragment() {
+        let original = post("Some Post", "some-post", "Content");
+        let mut store = InMemoryValkey::new();
+        store.set("some-post", &archived(&original)).await.unwrap();
+        let with_guid = Post {
+            guid: Some("nais-log-7".to_string()),
+            ..post("Some Post", "some-post", "Content")
+        };
+
+        let notifier = RecordingNotifier::default();
+        let summary = sync_posts(
+            &[with_guid],
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(summary.unchanged, 1);
+        assert!(notifier.posted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replayed_guid_post_finds_entry_under_its_fragment() {
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Some Post</title>
+      <link>https://nais.io/log#some-post</link>
+      <guid isPermaLink="false">nais-log-7</guid>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Content]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+        let state = AppState::new(AppConfig::DryRun);
+        let mut store = InMemoryValkey::new();
+        store
+            .set(
+                "some-post",
+                &archived(&post("Some Post", "some-post", "Content")),
+            )
+            .await
+            .unwrap();
+
+        let action = replay_post(feed, "nais-log-7", &mut store, &state)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(action.action, "updated");
+    }
+
+    #[test]
+    fn key_from_link_without_fragment() {
+        assert_eq!(key_from_link("https://nais.io/log"), None);
+        assert_eq!(key_from_link("https://nais.io/log#"), None);
+    }
+
+    #[test]
+    fn key_from_link_with_fragment() {
+        assert_eq!(
+            key_from_link("https://nais.io/log#some-post"),
+            Some("some-post".to_string())
+        );
+    }
+
+    #[test]
+    fn key_from_link_with_multiple_hashes() {
+        assert_eq!(
+            key_from_link("https://nais.io/log#some-post#section"),
+            Some("some-post#section".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn corrupt_archive_is_reposted() {
+        let item = post("Some Post", "some-post", "Content");
+        let mut store = InMemoryValkey::new();
+        store.set("some-post", "not json at all").await.unwrap();
+
+        let notifier = RecordingNotifier::default();
+        sync_posts(
+            &[item],
+            &mut store,
+            &notifier,
+            &corrupt(CorruptArchivePolicy::Repost),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(*notifier.posted.lock().unwrap(), vec!["Some Post"]);
+        let raw = store.get("some-post").await.unwrap().unwrap();
+        assert!(serde_json::from_str::<Archive>(&raw).is_ok());
+    }
+
+    #[tokio::test]
+    async fn corrupt_archive_is_skipped() {
+        let item = post("Some Post", "some-post", "Content");
+        let mut store = InMemoryValkey::new();
+        store.set("some-post", "not json at all").await.unwrap();
+
+        let notifier = RecordingNotifier::default();
+        sync_posts(
+            &[item],
+            &mut store,
+            &notifier,
+            &corrupt(CorruptArchivePolicy::Skip),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert!(notifier.posted.lock().unwrap().is_empty());
+        assert_eq!(
+            store.get("some-post").await.unwrap().as_deref(),
+            Some("not json at all")
+        );
+    }
+
+    #[test]
+    fn archive_reads_previously_stored_json() {
+        let stored =
+            r#"{"hash":"5d41402abc4b2a76b9719d911017c592","timestamp":"1600000000.000100"}"#;
+
+        let archive: Archive = serde_json::from_str(stored).unwrap();
+
+        assert_eq!(archive.content_hash, "5d41402abc4b2a76b9719d911017c592");
+        assert!(archive.title_hash.is_empty());
+        assert_eq!(archive.timestamp, "1600000000.000100");
+    }
+
+    #[tokio::test]
+    async fn legacy_archive_matching_post_is_unchanged() {
+        let item = post("Some Post", "some-post", "Content");
+        let mut store = InMemoryValkey::new();
+        let stored = format!(
+            r#"{{"hash":"{}","timestamp":"1600000000.000100"}}"#,
+            legacy_hash_of(&item)
+        );
+        store.set("some-post", &stored).await.unwrap();
+
+        let notifier = RecordingNotifier::default();
+        let summary = sync_posts(
+            &[item],
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(summary.unchanged, 1);
+        assert!(notifier.updated.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn legacy_md5_fingerprints_are_recognized() {
+        let item = post("Some Post", "some-post", "Content");
+        let mut store = InMemoryValkey::new();
+        let stored = format!(
+            r#"{{"content_hash":"{:x}","title_hash":"{:x}","timestamp":"1600000000.000100"}}"#,
+            md5::compute("Content"),
+            md5::compute("Some Post")
+        );
+        store.set("some-post", &stored).await.unwrap();
+
+        let notifier = RecordingNotifier::default();
+        let summary = sync_posts(
+            &[item],
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(summary.unchanged, 1);
+        assert!(notifier.updated.lock().unwrap().is_empty());
+    }
+
+    async fn sync_edit(
+        original: &Post,
+        edited: Post,
+        title_edits: TitleEditPolicy,
+    ) -> (RecordingNotifier, Option<String>) {
+        let archive_config = ArchiveConfig {
+            title_edits,
+            ..ArchiveConfig::default()
+        };
+        sync_edit_with(original, edited, &archive_config).await
+    }
+
+    async fn sync_edit_with(
+        original: &Post,
+        edited: Post,
+        archive_config: &ArchiveConfig,
+    ) -> (RecordingNotifier, Option<String>) {
+        let mut store = InMemoryValkey::new();
+        store.set("some-post", &archived(original)).await.unwrap();
+
+        let notifier = RecordingNotifier::default();
+        sync_posts(
+            &[edited],
+            &mut store,
+            &notifier,
+            archive_config,
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        (notifier, store.get("some-post").await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn title_only_edit_updates_by_default() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post, fixed", "some-post", "Content");
+
+        let (notifier, _) = sync_edit(&original, edited, TitleEditPolicy::Update).await;
+
+        assert_eq!(*notifier.updated.lock().unwrap(), vec!["Some Post, fixed"]);
+    }
+
+    #[tokio::test]
+    async fn title_only_edit_can_be_ignored() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post, fixed", "some-post", "Content");
+
+        let (notifier, stored) = sync_edit(&original, edited, TitleEditPolicy::Ignore).await;
+
+        assert!(notifier.updated.lock().unwrap().is_empty());
+        let expected = post("Some Post, fixed", "some-post", "Content");
+        assert_eq!(stored, Some(archived(&expected)));
+    }
+
+    #[tokio::test]
+    async fn content_edit_updates_even_when_ignoring_titles() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post", "some-post", "Edited content");
+
+        let (notifier, _) = sync_edit(&original, edited, TitleEditPolicy::Ignore).await;
+
+        assert_eq!(*notifier.updated.lock().unwrap(), vec!["Some Post"]);
+    }
+
+    fn update_mode(updates: UpdateMode) -> ArchiveConfig {
+        ArchiveConfig {
+            updates,
+            ..ArchiveConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn edit_mode_edits_the_announcement() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post", "some-post", "Edited content");
+        let expected = archived(&edited);
+
+        let (notifier, stored) =
+            sync_edit_with(&original, edited, &update_mode(UpdateMode::Edit)).await;
+
+        assert_eq!(*notifier.updated.lock().unwrap(), vec!["Some Post"]);
+        assert!(notifier.posted.lock().unwrap().is_empty());
+        assert_eq!(stored, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn repost_mode_announces_again_and_stores_new_message() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post", "some-post", "Edited content");
+
+        let (notifier, stored) =
+            sync_edit_with(&original, edited, &update_mode(UpdateMode::Repost)).await;
+
+        assert_eq!(*notifier.posted.lock().unwrap(), vec!["Some Post"]);
+        assert!(notifier.updated.lock().unwrap().is_empty());
+        let archive: Archive = serde_json::from_str(&stored.unwrap()).unwrap();
+        assert_eq!(archive.timestamp, "1700000000.000100");
+    }
+
+    #[tokio::test]
+    async fn ignore_mode_only_remembers_the_change() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post", "some-post", "Edited content");
+        let expected = archived(&edited);
+
+        let (notifier, stored) =
+            sync_edit_with(&original, edited, &update_mode(UpdateMode::Ignore)).await;
+
+        assert!(notifier.posted.lock().unwrap().is_empty());
+        assert!(notifier.updated.lock().unwrap().is_empty());
+        assert_eq!(stored, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn thread_mode_replies_under_the_announcement() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post", "some-post", "Edited content");
+        let expected: Archive = serde_json::from_str(&archived(&edited)).unwrap();
+
+        let (notifier, stored) =
+            sync_edit_with(&original, edited, &update_mode(UpdateMode::Thread)).await;
+
+        assert!(notifier.posted.lock().unwrap().is_empty());
+        assert!(notifier.updated.lock().unwrap().is_empty());
+        assert_eq!(
+            *notifier.replied.lock().unwrap(),
+            vec![single_message("1600000000.000100".to_string())]
+        );
+        let archive: Archive = serde_json::from_str(&stored.unwrap()).unwrap();
+        assert_eq!(archive.timestamp, "1600000000.000100");
+        assert_eq!(
+            archive.replies,
+            single_message("1700000000.000200".to_string())
+        );
+        assert_eq!(archive.content_hash, expected.content_hash);
+    }
+
+    #[tokio::test]
+    async fn title_and_content_edit_updates() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post, fixed", "some-post", "Edited content");
+
+        let (notifier, _) = sync_edit(&original, edited, TitleEditPolicy::Ignore).await;
+
+        assert_eq!(*notifier.updated.lock().unwrap(), vec!["Some Post, fixed"]);
+    }
+
+    #[tokio::test]
+    async fn update_rewrites_stored_archive() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post", "some-post", "Edited content");
+        let expected = archived(&edited);
+        let mut store = InMemoryValkey::from_map(HashMap::from([(
+            "some-post".to_string(),
+            archived(&original),
+        )]));
+
+        let summary = sync_posts(
+            &[edited],
+            &mut store,
+            &RecordingNotifier::default(),
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(summary.updated, 1);
+        assert_eq!(
+            store.snapshot(),
+            HashMap::from([("some-post".to_string(), expected)])
+        );
+    }
+
+    #[test]
+    fn archive_tells_title_and_content_changes_apart() {
+        let original = post("Some Post", "some-post", "Content");
+        let archive = Archive::new(&original, single_message(String::new()));
+
+        let cases = [
+            (post("Some Post", "some-post", "Content"), false, false),
+            (post("Renamed", "some-post", "Content"), true, false),
+            (post("Some Post", "some-post", "Edited"), false, true),
+            (post("Renamed", "some-post", "Edited"), true, true),
+        ];
+        for (item, title, content) in cases {
+            assert_eq!(
+                archive.changes(&item),
+                Changes { title, content },
+                "{item:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn new_post_writes_archive_json() {
+        let item = post("Some Post", "some-post", "Content");
+        // SHA-256 of "Content" and "Some Post".
+        let expected = concat!(
+            r#"{"content_hash":"47bd29075f8b8019f0beec6d86beda7c9bf67aaf05053dcbe0b3bcb63968517f","#,
+            r#""title_hash":"0dc89603f82ba7b1cf4b388ac66ee43df3ac8d77942a4a70e23839e2cc4152d7","#,
+            r#""timestamp":"1700000000.000100"}"#
+        )
+        .to_string();
+        let mut store = InMemoryValkey::new();
+
+        let notifier = RecordingNotifier::default();
+        sync_posts(
+            &[item],
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(store.get("some-post").await.unwrap(), Some(expected));
+    }
+
+    #[test]
+    fn archive_keeps_resolved_channels_apart_from_messages() {
+        let ids: MessageIds = [
+            ("#announcements", "1700000000.000100"),
+            ("channel:#announcements", "C0123456789"),
+        ]
+        .into_iter()
+        .map(|(key, id)| (key.to_string(), id.to_string()))
+        .collect();
+
+        let archive = Archive::new(&post("Some Post", "some-post", "Content"), ids.clone());
+        let raw: serde_json::Value = serde_json::to_value(&archive).unwrap();
+
+        assert_eq!(
+            raw["timestamps"],
+            serde_json::json!({ "#announcements": "1700000000.000100" })
+        );
+        assert_eq!(
+            raw["channels"],
+            serde_json::json!({ "#announcements": "C0123456789" })
+        );
+        assert_eq!(archive.message_ids(), ids);
+    }
+
+    #[tokio::test]
+    async fn archive_keeps_timestamp_per_channel() {
+        let original = post("Some Post", "some-post", "Content");
+        let edited = post("Some Post", "some-post", "Edited content");
+        let notifier = RecordingNotifier {
+            channels: vec!["C0123ABCD", "C0456EFGH"],
+            ..RecordingNotifier::default()
+        };
+        let mut store = InMemoryValkey::new();
+
+        sync_posts(
+            &[original],
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+        let raw = store.get("some-post").await.unwrap().unwrap();
+        let archive: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        assert_eq!(
+            archive["timestamps"],
+            serde_json::json!({
+                "C0123ABCD": "C0123ABCD-1700000000.000100",
+                "C0456EFGH": "C0456EFGH-1700000000.000100",
+            })
+        );
+        assert!(archive.get("timestamp").is_none());
+
+        sync_posts(
+            &[edited],
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+        let expected: MessageIds = [
+            ("C0123ABCD", "C0123ABCD-1700000000.000100"),
+            ("C0456EFGH", "C0456EFGH-1700000000.000100"),
+        ]
+        .into_iter()
+        .map(|(channel, ts)| (channel.to_string(), ts.to_string()))
+        .collect();
+        assert_eq!(*notifier.updated_ids.lock().unwrap(), vec![expected]);
+    }
+
+    #[tokio::test]
+    async fn legacy_archive_updates_default_message() {
+        let item = post("Some Post", "some-post", "Edited content");
+        let mut store = InMemoryValkey::new();
+        store
+            .set(
+                "some-post",
+                r#"{"hash":"stale-hash","timestamp":"1600000000.000100"}"#,
+            )
+            .await
+            .unwrap();
+
+        let notifier = RecordingNotifier::default();
+        sync_posts(
+            &[item],
+            &mut store,
+            &notifier,
+            &ArchiveConfig::default(),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(
+            *notifier.updated_ids.lock().unwrap(),
+            vec![single_message("1600000000.000100".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn summary_counts_mixed_state() {
+        let unchanged = post("Unchanged", "unchanged", "Same as before");
+        let changed = post("Changed", "changed", "Edited content");
+        let fresh = post("Fresh", "fresh", "Brand new");
+        let unreadable = post("Corrupt", "corrupt", "Whatever");
+        let broken_link = Post {
+            link: "https://nais.io/log".to_string(),
+            ..post("No fragment", "", "Content")
+        };
+
+        let mut store = InMemoryValkey::new();
+        store.set("unchanged", &archived(&unchanged)).await.unwrap();
+        store
+            .set(
+                "changed",
+                &archived(&post("Changed", "changed", "Old content")),
+            )
+            .await
+            .unwrap();
+        store.set("corrupt", "not json at all").await.unwrap();
+
+        let notifier = RecordingNotifier::default();
+        let summary = sync_posts(
+            &[unchanged, changed, fresh, unreadable, broken_link],
+            &mut store,
+            &notifier,
+            &corrupt(CorruptArchivePolicy::Skip),
+            usize::MAX,
+            false,
+        )
+        .await;
+
+        assert_eq!(
+            summary,
+            ReconcileSummary {
+                new: 1,
+                updated: 1,
+                unchanged: 1,
+                errors: 2,
+                failures: vec![
+                    PostFailure {
+                        post: "corrupt".to_string(),
+                        error: "Invalid archive JSON in Redis: expected ident at line 1 column 2"
+                            .to_string(),
+                    },
+                    PostFailure {
+                        post: "https://nais.io/log".to_string(),
+                        error: "Post link has no fragment".to_string(),
+                    },
+                ],
+                actions: [
+                    ("unchanged", "Unchanged", "unchanged"),
+                    ("changed", "Changed", "updated"),
+                    ("fresh", "Fresh", "new"),
+                    ("corrupt", "Corrupt", "error"),
+                    ("https://nais.io/log", "No fragment", "error"),
+                ]
+                .into_iter()
+                .map(|(post, title, action)| PostAction {
+                    post: post.to_string(),
+                    title: title.to_string(),
+                    action,
+                })
+                .collect(),
+                ..ReconcileSummary::default()
+            }
+        );
     }
 }
@@ -0,0 +1,46 @@
+use std::{future::Future, time::Duration};
+use tokio::time::{Instant, MissedTickBehavior, interval_at};
+
+/// Runs `job` every `period`, starting one period from now. A run that takes
+/// longer than `period` delays the next one rather than overlapping it.
+pub async fn run_every<F, Fut>(period: Duration, mut job: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut ticks = interval_at(Instant::now() + period, period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        ticks.tick().await;
+        job().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_every;
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn fires_repeatedly() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let scheduler = tokio::spawn(run_every(Duration::from_secs(60), move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
+
+        tokio::time::sleep(Duration::from_secs(150)).await;
+        scheduler.abort();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
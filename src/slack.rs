@@ -1,160 +1,2304 @@
-use crate::{config::SlackConfig, rss::Post};
+use crate::{
+    config::{ContentFormat, LongPostMode, SlackConfig, SlackLayout},
+    markdown::{html_to_markdown, map_lines_outside_fences, map_outside_inline_code, regex},
+    notifier::{DEFAULT_CHANNEL, MessageIds, Notifier, resolved_channel_key, single_message},
+    rate_limit::RateLimiter,
+    rss::Post,
+};
 use async_trait::async_trait;
 use regex::Regex;
+use reqwest::{StatusCode, header::RETRY_AFTER};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    io::{Error, ErrorKind},
-    sync::OnceLock,
+    io::Error,
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tracing::{debug, info};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
 
 #[derive(Debug, Serialize)]
 struct Message {
     channel: String,
     ts: String,
+    /// Rendered on its own when there are no blocks, otherwise only used for notifications.
+    text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    blocks: Vec<Block>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_ts: Option<String>,
+    /// Let Slack preview the links in the message. Sent either way, as Slack
+    /// otherwise unfurls some links on its own.
+    unfurl_links: bool,
+    unfurl_media: bool,
+    /// Carries the idempotency key of a post, to find it again in the channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+}
+
+/// A message being posted with `metadata`, for finding it again after an
+/// attempt that may have gone through.
+#[derive(Clone, Copy)]
+struct Posted<'a> {
+    channel: &'a str,
+    /// The thread the message is a reply in, if any.
+    thread_ts: Option<&'a str>,
+    metadata: &'a Metadata,
+    /// Shortly before the first attempt.
+    since: Duration,
+}
+
+/// Message metadata, which Slack keeps with the message and hands back from
+/// `conversations.history`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Metadata {
+    event_type: String,
+    event_payload: IdempotencyKey,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct IdempotencyKey {
+    idempotency_key: String,
+}
+
+/// Event type of the metadata announcements are posted with.
+const POSTED_EVENT: &str = "announcer_post";
+
+/// How long before the first attempt to look for a post, for clocks that
+/// disagree with Slack's.
+const CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+impl Metadata {
+    /// Metadata naming `post` by a key derived from its link and
+    /// publication date, the same for every attempt at announcing it.
+    fn for_post(post: &Post) -> Self {
+        let digest = Sha256::new()
+            .chain_update(&post.link)
+            .chain_update(post.pub_date.to_rfc3339())
+            .finalize();
+        Self {
+            event_type: POSTED_EVENT.to_string(),
+            event_payload: IdempotencyKey {
+                idempotency_key: digest[..16]
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// Payload of `reactions.add`, naming the message by its channel and `ts`.
+#[derive(Debug, Serialize)]
+struct Reaction<'a> {
+    channel: &'a str,
+    timestamp: &'a str,
+    name: &'a str,
+}
+
+/// Payload of `pins.add`.
+#[derive(Debug, Serialize)]
+struct Pin<'a> {
+    channel: &'a str,
+    timestamp: &'a str,
+}
+
+/// Text of the message posts are threaded under with `SLACK_THREADED`.
+const THREAD_ROOT_TEXT: &str = "*NAIS Log*: new posts are announced in the thread below";
+
+/// Payload of `conversations.join`.
+#[derive(Debug, Serialize)]
+struct Join<'a> {
+    channel: &'a str,
+}
+
+/// Reaction put on an announcement when its post is edited, with `SLACK_REACT_ON_UPDATE`.
+const UPDATED_REACTION: &str = "pencil2";
+
+/// The subset of Slack Block Kit we render posts into.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Block {
+    Header { text: TextObject },
+    Section { text: TextObject },
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct TextObject {
+    #[serde(rename = "type")]
+    kind: &'static str,
     text: String,
 }
 
+/// Slack's limit on the text of a single section block.
+const SECTION_TEXT_LIMIT: usize = 3000;
+
+/// Longest message text we send; Slack rejects or cuts off anything much longer.
+const MESSAGE_TEXT_LIMIT: usize = 3000;
+
+/// Slack's limit on the text of a header block.
+const HEADER_TEXT_LIMIT: usize = 150;
+
+/// Why a call to the Slack API failed. Reaches callers of the [`Notifier`]
+/// as the source of the `std::io::Error` it returns.
+#[derive(Debug, thiserror::Error)]
+pub enum SlackError {
+    /// The request didn't get through, or Slack answered with an error status.
+    #[error("{message}")]
+    Http {
+        status: Option<StatusCode>,
+        message: String,
+    },
+    /// Slack answered with something other than the response we expect.
+    #[error("Failed decoding Slack response: {0}")]
+    Decode(String),
+    /// Slack turned the call down, e.g. with `channel_not_found`.
+    #[error("Slack API error: {code}")]
+    Api { code: String },
+    /// Slack didn't answer within `SLACK_TIMEOUT_SECONDS`.
+    #[error("Slack didn't answer within {}s", .0.as_secs_f64())]
+    Timeout(Duration),
+}
+
+impl SlackError {
+    /// Whether the call may have gone through even though no answer came
+    /// back, so that trying again could do it twice.
+    fn may_have_landed(&self) -> bool {
+        match self {
+            SlackError::Timeout(_) => true,
+            SlackError::Http { status, .. } => status.is_some_and(|s| s.is_server_error()),
+            SlackError::Decode(_) | SlackError::Api { .. } => false,
+        }
+    }
+
+    /// Whether the bot can't post because it isn't in the channel, or can't
+    /// see it, which no retry fixes until someone invites it.
+    pub fn is_missing_channel(&self) -> bool {
+        matches!(self, SlackError::Api { code } if code == "not_in_channel" || code == "channel_not_found")
+    }
+
+    /// Whether Slack asked us to slow down, either by status or error code.
+    pub fn is_rate_limited(&self) -> bool {
+        match self {
+            SlackError::Http { status, .. } => *status == Some(StatusCode::TOO_MANY_REQUESTS),
+            SlackError::Api { code } => code == "ratelimited" || code == "rate_limited",
+            SlackError::Decode(_) | SlackError::Timeout(_) => false,
+        }
+    }
+}
+
+impl From<SlackError> for Error {
+    fn from(err: SlackError) -> Self {
+        Error::other(err)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Response {
+    ok: bool,
+    #[serde(default)]
+    ts: String,
+    /// Id of the channel the message is in, even when posted by channel name.
+    #[serde(default)]
+    channel: String,
+    #[serde(default)]
+    error: String,
+}
+
+/// The subset of a `conversations.history` response needed to find a post.
 #[derive(Debug, Deserialize)]
-pub struct Response {
+struct History {
     ok: bool,
     #[serde(default)]
-    pub ts: String,
+    messages: Vec<HistoryMessage>,
     #[serde(default)]
     error: String,
 }
 
-static RE_PATTERN: OnceLock<Regex> = OnceLock::new();
+#[derive(Debug, Deserialize)]
+struct HistoryMessage {
+    ts: String,
+    #[serde(default)]
+    metadata: Option<Metadata>,
+}
+
+static RE_LINK: OnceLock<Regex> = OnceLock::new();
+static RE_BOLD: OnceLock<Regex> = OnceLock::new();
+static RE_HEADING: OnceLock<Regex> = OnceLock::new();
+static RE_BULLET: OnceLock<Regex> = OnceLock::new();
 
+/// Translates the CommonMark used in nais.io posts into Slack mrkdwn. Fenced
+/// code blocks and inline code are passed through untouched.
 pub(crate) fn format_slack_post(org: &str) -> String {
-    RE_PATTERN
-        .get_or_init(|| {
-            Regex::new(r"\[(.*?)\]\((.*?)\)").expect("Hard-coded regex pattern should compile")
-        })
-        .replace_all(org, "<$2|$1>")
-        .to_string()
+    map_lines_outside_fences(org, format_line)
 }
 
-#[async_trait]
-pub trait SlackClient: Send + Sync {
-    async fn post_message(&self, post: &Post) -> Result<Response, Error>;
-    async fn update_message(&self, post: &Post, timestamp: &str) -> Result<Response, Error>;
+/// Turns the content of a post, written in `format`, into Slack mrkdwn.
+pub(crate) fn format_content(content: &str, format: ContentFormat) -> String {
+    match format {
+        ContentFormat::Markdown => format_slack_post(content),
+        ContentFormat::Html => format_slack_post(&html_to_markdown(content)),
+        ContentFormat::Raw => content.to_string(),
+    }
+}
+
+fn format_line(line: &str) -> String {
+    if let Some(caps) = regex(&RE_HEADING, r"^#{1,6}\s+(.*?)\s*#*$").captures(line) {
+        let heading = caps[1].replace("**", "").replace("__", "");
+        return format!("*{}*", format_inline(&heading));
+    }
+
+    let line = regex(&RE_BULLET, r"^(\s*)[-*+]\s+").replace(line, "$1• ");
+    format_inline(&line)
+}
+
+fn format_inline(text: &str) -> String {
+    map_outside_inline_code(text, |segment| {
+        let bold = regex(&RE_BOLD, r"\*\*(.+?)\*\*|__(.+?)__").replace_all(
+            segment,
+            |caps: &regex::Captures| {
+                format!(
+                    "*{}*",
+                    caps.get(1).or(caps.get(2)).map_or("", |m| m.as_str())
+                )
+            },
+        );
+        regex(&RE_LINK, r"\[(.*?)\]\((.*?)\)")
+            .replace_all(&bold, "<$2|$1>")
+            .to_string()
+    })
 }
 
+/// Delay before the first retry; doubled for every attempt after that.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
 #[derive(Debug, Clone)]
-pub struct HttpSlackClient {
+pub struct SlackNotifier {
     config: SlackConfig,
     client: reqwest::Client,
+    rate_limiter: Arc<RateLimiter>,
+    /// The message posts are threaded under in each channel, with
+    /// `SLACK_THREADED`. Held while a missing one is posted, so there is
+    /// never more than one.
+    thread_roots: Arc<tokio::sync::Mutex<MessageIds>>,
+}
+
+/// Outcome of a single failed call, telling `send` whether it's worth trying again.
+enum Failure {
+    Retryable {
+        error: SlackError,
+        retry_after: Option<Duration>,
+    },
+    Fatal(SlackError),
 }
 
-impl HttpSlackClient {
-    pub fn new(config: SlackConfig, client: reqwest::Client) -> Self {
-        Self { config, client }
+impl SlackNotifier {
+    pub fn new(
+        config: SlackConfig,
+        client: reqwest::Client,
+        rate_limiter: Arc<RateLimiter>,
+    ) -> Self {
+        let thread_roots = match (&config.thread_root_ts, config.channel_ids.first()) {
+            (Some(ts), Some(channel)) => MessageIds::from([(channel.clone(), ts.clone())]),
+            _ => MessageIds::new(),
+        };
+        Self {
+            config,
+            client,
+            rate_limiter,
+            thread_roots: Arc::new(tokio::sync::Mutex::new(thread_roots)),
+        }
+    }
+
+    #[cfg(test)]
+    fn with_base_url(mut self, base_url: &str) -> Self {
+        self.config.api_base_url =
+            crate::config::parse_slack_api_base_url(base_url).expect("Test base URL should parse");
+        self
+    }
+
+    async fn send(
+        &self,
+        method: &str,
+        payload: &(impl Serialize + Sync),
+    ) -> Result<Response, SlackError> {
+        self.send_retrying(method, payload, None).await
+    }
+
+    /// Posts a message, making sure a retry doesn't post it twice.
+    ///
+    /// Slack has no idempotency key of its own, so `payload` carries one in
+    /// its metadata. When an attempt fails in a way that may still have
+    /// posted it, the channel history since the first attempt is searched
+    /// for the key before trying again. This only covers the retries of one
+    /// call: the search needs `channels:history` (or `groups:history`) and a
+    /// channel id rather than a name, and when it fails the message is
+    /// posted again rather than risk losing it.
+    ///
+    /// With `SLACK_AUTO_JOIN`, a channel the bot isn't in is joined and the
+    /// message posted once more; this only works for public channels.
+    async fn post_message(&self, payload: &Message) -> Result<Response, SlackError> {
+        let since = SystemTime::now()
+            .checked_sub(CLOCK_SKEW)
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let posted = payload.metadata.as_ref().map(|metadata| Posted {
+            channel: &payload.channel,
+            thread_ts: payload.thread_ts.as_deref(),
+            metadata,
+            since,
+        });
+        match self
+            .send_retrying("chat.postMessage", payload, posted)
+            .await
+        {
+            Err(SlackError::Api { code }) if code == "not_in_channel" && self.config.auto_join => {
+                let channel = payload.channel.as_str();
+                if let Err(err) = self.send("conversations.join", &Join { channel }).await {
+                    warn!(channel, error = %err, "Failed joining channel the bot isn't in");
+                    return Err(SlackError::Api { code });
+                }
+                info!(channel, "Joined channel the bot wasn't in, posting again");
+                self.send_retrying("chat.postMessage", payload, posted)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    /// Calls `method`, retrying as configured. With `posted`, a retry after
+    /// an attempt that may have gone through first looks for the message
+    /// with that metadata in the channel, and answers with it if found.
+    async fn send_retrying(
+        &self,
+        method: &str,
+        payload: &(impl Serialize + Sync),
+        posted: Option<Posted<'_>>,
+    ) -> Result<Response, SlackError> {
+        let mut attempt = 1;
+        loop {
+            match self.send_once(method, payload).await {
+                Ok(response) => return Ok(response),
+                Err(Failure::Retryable { error, retry_after })
+                    if attempt < self.config.max_attempts =>
+                {
+                    if let Some(posted) = posted.as_ref().filter(|_| error.may_have_landed()) {
+                        let channel = posted.channel;
+                        match self.find_posted(posted).await {
+                            Ok(Some(response)) => {
+                                info!(method, channel, attempt, error = %error, "Earlier attempt reached Slack, not sending again");
+                                return Ok(response);
+                            }
+                            Ok(None) => {}
+                            Err(err) => {
+                                warn!(method, channel, error = %err, "Failed checking whether Slack got the earlier attempt");
+                            }
+                        }
+                    }
+                    let delay = retry_after.unwrap_or(INITIAL_BACKOFF * 2u32.pow(attempt - 1));
+                    warn!(
+                        method,
+                        attempt,
+                        error = %error,
+                        delay_ms = delay.as_millis() as u64,
+                        "Slack call failed, retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(Failure::Retryable { error, .. }) | Err(Failure::Fatal(error)) => {
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// The message `posted` describes, if it's there. Replies are looked for
+    /// in their thread, as the channel history leaves them out.
+    async fn find_posted(&self, posted: &Posted<'_>) -> Result<Option<Response>, SlackError> {
+        let Posted {
+            channel,
+            thread_ts,
+            metadata,
+            since,
+        } = *posted;
+        let oldest = since.as_secs().to_string();
+        let (method, thread) = match thread_ts {
+            Some(ts) => ("conversations.replies", Some(("ts", ts))),
+            None => ("conversations.history", None),
+        };
+        let history = self
+            .client
+            .get(format!("{}{method}", self.config.api_base_url))
+            .header("Authorization", format!("Bearer {}", self.config.token))
+            .query(&[
+                ("channel", channel),
+                ("oldest", &oldest),
+                ("include_all_metadata", "true"),
+                ("limit", "100"),
+            ])
+            .query(&thread.as_slice())
+            .timeout(self.config.timeout)
+            .send()
+            .await
+            .map_err(|e| SlackError::Http {
+                status: e.status(),
+                message: e.to_string(),
+            })?
+            .json::<History>()
+            .await
+            .map_err(|e| SlackError::Decode(e.to_string()))?;
+        if !history.ok {
+            return Err(SlackError::Api {
+                code: history.error,
+            });
+        }
+
+        Ok(history
+            .messages
+            .into_iter()
+            .find(|message| message.metadata.as_ref() == Some(metadata))
+            .map(|message| Response {
+                ok: true,
+                ts: message.ts,
+                channel: channel.to_string(),
+                error: String::new(),
+            }))
     }
 
-    async fn send(&self, method: &str, payload: &Message) -> Result<Response, Error> {
+    async fn send_once(
+        &self,
+        method: &str,
+        payload: &(impl Serialize + Sync),
+    ) -> Result<Response, Failure> {
         let slack_token = &self.config.token;
+        self.rate_limiter.acquire().await;
 
         let response = self
             .client
-            .post(format!("https://slack.com/api/{method}"))
+            .post(format!("{}{method}", self.config.api_base_url))
             .header("Authorization", format!("Bearer {slack_token}"))
             .header("Content-Type", "application/json; charset=utf-8")
             .json(payload)
+            .timeout(self.config.timeout)
             .send()
             .await
-            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?
+            .map_err(|e| {
+                let error = if e.is_timeout() {
+                    SlackError::Timeout(self.config.timeout)
+                } else {
+                    SlackError::Http {
+                        status: e.status(),
+                        message: e.to_string(),
+                    }
+                };
+                if e.is_connect() || e.is_timeout() {
+                    Failure::Retryable {
+                        error,
+                        retry_after: None,
+                    }
+                } else {
+                    Failure::Fatal(error)
+                }
+            })?;
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+            return Err(Failure::Retryable {
+                error: SlackError::Http {
+                    status: Some(status),
+                    message: format!("Slack answered with {status}"),
+                },
+                retry_after,
+            });
+        }
+
+        let response = response
             .json::<Response>()
             .await
-            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+            .map_err(|e| Failure::Fatal(SlackError::Decode(e.to_string())))?;
 
         if response.ok {
-            Ok(response)
+            return Ok(response);
+        }
+        let error = SlackError::Api {
+            code: response.error,
+        };
+        if error.is_rate_limited() {
+            Err(Failure::Retryable {
+                error,
+                retry_after: None,
+            })
         } else {
-            Err(Error::new(ErrorKind::Other, response.error))
+            Err(Failure::Fatal(error))
         }
     }
 }
 
-#[async_trait]
-impl SlackClient for HttpSlackClient {
-    async fn post_message(&self, post: &Post) -> Result<Response, Error> {
-        let content = format_slack_post(&post.content);
-        let payload = Message {
-            channel: self.config.channel_id.clone(),
-            ts: String::new(),
-            text: format!("<{}|{}>\n{}", post.link, post.title, content),
+fn message_text(post: &Post, format: ContentFormat) -> String {
+    format!(
+        "<{}|{}>\n{}",
+        post.link,
+        post.title,
+        format_content(&post.content, format)
+    )
+}
+
+/// Splits `text` into chunks of at most `limit` characters, breaking between
+/// lines where possible and inside a line only when it is too long on its own.
+/// No chunk ends inside a link or an open code fence unless there is no other
+/// way to fit it.
+pub(crate) fn split_text(text: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while rest.chars().count() > limit {
+        let cut = mrkdwn_cut(rest, limit);
+        let line_break = rest
+            .char_indices()
+            .take(cut + 1)
+            .filter(|(_, c)| *c == '\n')
+            .last();
+        let (chunk, next) = match line_break {
+            Some((at, _)) => (&rest[..at], &rest[at + 1..]),
+            None => rest.split_at(byte_offset(rest, cut)),
         };
+        if !chunk.is_empty() {
+            chunks.push(chunk.to_string());
+        }
+        rest = next;
+    }
 
-        self.send("chat.postMessage", &payload).await
+    if !rest.is_empty() {
+        chunks.push(rest.to_string());
     }
+    chunks
+}
 
-    async fn update_message(&self, post: &Post, timestamp: &str) -> Result<Response, Error> {
-        let content = format_slack_post(&post.content);
-        let payload = Message {
-            channel: self.config.channel_id.clone(),
-            ts: timestamp.to_string(),
-            text: format!("<{}|{}>\n{}", post.link, post.title, content),
+/// How many of the first `limit` characters of `text` to keep so the cut
+/// doesn't land inside a `<link|label>` or leave a ``` code fence open,
+/// either of which Slack would render the rest of the message wrong for.
+/// Falls back to `limit` when everything before it is one link or fence.
+fn mrkdwn_cut(text: &str, limit: usize) -> usize {
+    let head: Vec<char> = text.chars().take(limit).collect();
+    let mut cut = head.len();
+    loop {
+        let kept = &head[..cut];
+        let open_link = kept
+            .iter()
+            .rposition(|c| *c == '<')
+            .filter(|at| !kept[*at..].contains(&'>'));
+        let fences: Vec<usize> = kept
+            .windows(3)
+            .enumerate()
+            .filter(|(_, window)| *window == ['`'; 3])
+            .map(|(at, _)| at)
+            .collect();
+        let open_fence = fences.last().copied().filter(|_| fences.len() % 2 == 1);
+        match open_link.or(open_fence) {
+            Some(0) => return head.len(),
+            Some(at) => cut = at,
+            None => return cut,
+        }
+    }
+}
+
+/// The byte offset of the character `chars` characters into `text`.
+fn byte_offset(text: &str, chars: usize) -> usize {
+    text.char_indices()
+        .nth(chars)
+        .map_or(text.len(), |(at, _)| at)
+}
+
+/// Renders a post as a header block with the title followed by section blocks
+/// holding the content.
+fn message_blocks(post: &Post, format: ContentFormat) -> Vec<Block> {
+    let title: String = post.title.chars().take(HEADER_TEXT_LIMIT).collect();
+    let mut blocks = vec![Block::Header {
+        text: TextObject {
+            kind: "plain_text",
+            text: title,
+        },
+    }];
+
+    let body = format!("<{}>\n{}", post.link, format_content(&post.content, format));
+    blocks.extend(
+        split_text(&body, SECTION_TEXT_LIMIT)
+            .into_iter()
+            .map(|text| Block::Section {
+                text: TextObject {
+                    kind: "mrkdwn",
+                    text,
+                },
+            }),
+    );
+    blocks
+}
+
+/// Cuts `text` down to `limit` characters, ending it with a link to the full post.
+fn truncate_text(text: &str, link: &str, limit: usize) -> String {
+    let suffix = format!("… <{link}|read more>");
+    let keep = mrkdwn_cut(text, limit.saturating_sub(suffix.chars().count()));
+    let mut truncated: String = text.chars().take(keep).collect();
+    truncated.push_str(&suffix);
+    truncated
+}
+
+/// The texts a post is sent as: a single message when it fits in `limit`
+/// characters, otherwise either a truncated message or the chunks of a thread.
+fn message_texts(
+    post: &Post,
+    format: ContentFormat,
+    mode: LongPostMode,
+    limit: usize,
+) -> Vec<String> {
+    let text = message_text(post, format);
+    if text.chars().count() <= limit {
+        return vec![text];
+    }
+
+    match mode {
+        LongPostMode::Truncate => vec![truncate_text(&text, &post.link, limit)],
+        LongPostMode::Thread => split_text(&text, limit),
+    }
+}
+
+/// Room a prefix or footer takes up in a message, including the line break
+/// separating it from the post.
+fn branding_len(text: &str) -> usize {
+    match text.chars().count() {
+        0 => 0,
+        len => len + 1,
+    }
+}
+
+/// Puts `prefix` above the first of `texts` and `footer` below the last.
+/// Neither goes through the markdown conversion, as they're written in
+/// Slack mrkdwn already.
+fn brand_texts(mut texts: Vec<String>, prefix: &str, footer: &str) -> Vec<String> {
+    if let Some(first) = texts.first_mut().filter(|_| !prefix.is_empty()) {
+        *first = format!("{prefix}\n{first}");
+    }
+    if let Some(last) = texts.last_mut().filter(|_| !footer.is_empty()) {
+        last.push('\n');
+        last.push_str(footer);
+    }
+    texts
+}
+
+/// The line linking the title of a post, all there is of it in the compact
+/// layout.
+fn compact_line(post: &Post) -> String {
+    format!("• <{}|{}>", post.link, post.title)
+}
+
+/// Renders a post as the single text of a message that can't be threaded,
+/// cutting long posts short and wrapping it in `prefix` and `footer`.
+pub(crate) fn single_text(
+    post: &Post,
+    format: ContentFormat,
+    layout: SlackLayout,
+    prefix: &str,
+    footer: &str,
+) -> String {
+    let limit = MESSAGE_TEXT_LIMIT.saturating_sub(branding_len(prefix) + branding_len(footer));
+    let texts = match layout {
+        SlackLayout::Full => message_texts(post, format, LongPostMode::Truncate, limit),
+        SlackLayout::Compact => vec![compact_line(post)],
+    };
+    brand_texts(texts, prefix, footer).concat()
+}
+
+/// Renders several new posts as one message linking each of them, leaving
+/// out the ones that don't fit. The compact layout leaves out the heading
+/// counting them too.
+pub(crate) fn digest_text(
+    posts: &[&Post],
+    layout: SlackLayout,
+    prefix: &str,
+    footer: &str,
+) -> String {
+    let limit = MESSAGE_TEXT_LIMIT.saturating_sub(branding_len(prefix) + branding_len(footer));
+    let mut text = match layout {
+        SlackLayout::Full => format!("*{} new posts*", posts.len()),
+        SlackLayout::Compact => String::new(),
+    };
+    for (i, post) in posts.iter().enumerate() {
+        let line = if text.is_empty() {
+            compact_line(post)
+        } else {
+            format!("\n{}", compact_line(post))
+        };
+        let rest = format!("\n…and {} more", posts.len() - i);
+        // Keep room to say how many were left out, unless this is the last one.
+        let reserved = if i + 1 < posts.len() {
+            rest.chars().count()
+        } else {
+            0
+        };
+        if text.chars().count() + line.chars().count() + reserved > limit {
+            text.push_str(&rest);
+            break;
+        }
+        text.push_str(&line);
+    }
+    brand_texts(vec![text], prefix, footer).concat()
+}
+
+/// Adds `prefix` and `footer` as sections around the blocks of a post.
+fn brand_blocks(mut blocks: Vec<Block>, prefix: &str, footer: &str) -> Vec<Block> {
+    let section = |text: &str| Block::Section {
+        text: TextObject {
+            kind: "mrkdwn",
+            text: text.chars().take(SECTION_TEXT_LIMIT).collect(),
+        },
+    };
+    if !prefix.is_empty() {
+        blocks.insert(0, section(prefix));
+    }
+    if !footer.is_empty() {
+        blocks.push(section(footer));
+    }
+    blocks
+}
+
+impl SlackNotifier {
+    /// Renders a post into the main message and any thread replies carrying
+    /// the rest of a long post.
+    fn messages(&self, post: &Post, channel: &str, ts: &str) -> (Message, Vec<Message>) {
+        // Blocks already spread long content over sections, so the text is
+        // only a notification fallback and never needs a thread.
+        let prefix = &self.config.message_prefix;
+        let footer = &self.config.message_footer;
+        if self.config.layout == SlackLayout::Compact {
+            let message = Message {
+                channel: channel.to_string(),
+                ts: ts.to_string(),
+                text: brand_texts(vec![compact_line(post)], prefix, footer).concat(),
+                blocks: Vec::new(),
+                thread_ts: None,
+                unfurl_links: self.config.unfurl,
+                unfurl_media: self.config.unfurl,
+                metadata: None,
+            };
+            return (message, Vec::new());
+        }
+        let (blocks, mode) = if self.config.use_blocks {
+            (
+                brand_blocks(
+                    message_blocks(post, self.config.content_format),
+                    prefix,
+                    footer,
+                ),
+                LongPostMode::Truncate,
+            )
+        } else {
+            (Vec::new(), self.config.long_posts)
+        };
+
+        let limit = MESSAGE_TEXT_LIMIT.saturating_sub(branding_len(prefix) + branding_len(footer));
+        let mut texts = brand_texts(
+            message_texts(post, self.config.content_format, mode, limit),
+            prefix,
+            footer,
+        )
+        .into_iter();
+        let main = Message {
+            channel: channel.to_string(),
+            ts: ts.to_string(),
+            text: texts.next().unwrap_or_default(),
+            blocks,
+            thread_ts: None,
+            unfurl_links: self.config.unfurl,
+            unfurl_media: self.config.unfurl,
+            metadata: None,
+        };
+        let replies = texts
+            .map(|text| Message {
+                channel: channel.to_string(),
+                ts: String::new(),
+                text,
+                blocks: Vec::new(),
+                thread_ts: None,
+                unfurl_links: self.config.unfurl,
+                unfurl_media: self.config.unfurl,
+                metadata: None,
+            })
+            .collect();
+
+        (main, replies)
+    }
+
+    /// The message posts in `channel` are threaded under with
+    /// `SLACK_THREADED`, posting and pinning one first if there's none yet.
+    /// `None` when posts go in the channel itself.
+    async fn thread_root(&self, channel: &str) -> Result<Option<String>, SlackError> {
+        if !self.config.threaded {
+            return Ok(None);
+        }
+        let mut roots = self.thread_roots.lock().await;
+        if let Some(root) = roots.get(channel) {
+            return Ok(Some(root.clone()));
+        }
+
+        let root = Message {
+            channel: channel.to_string(),
+            ts: String::new(),
+            text: THREAD_ROOT_TEXT.to_string(),
+            blocks: Vec::new(),
+            thread_ts: None,
+            unfurl_links: false,
+            unfurl_media: false,
+            metadata: None,
         };
+        let response = self.post_message(&root).await?;
+        info!(channel, ts = %response.ts, "Posted the message announcements are threaded under");
+        let pinned_in = if response.channel.is_empty() {
+            channel
+        } else {
+            &response.channel
+        };
+        let pin = Pin {
+            channel: pinned_in,
+            timestamp: &response.ts,
+        };
+        // The thread works unpinned too, it's just harder to find.
+        if let Err(err) = self.send("pins.add", &pin).await {
+            warn!(channel, error = %err, "Failed pinning the message announcements are threaded under");
+        }
+        roots.insert(channel.to_string(), response.ts.clone());
+        Ok(Some(response.ts))
+    }
+
+    async fn post_to(&self, channel: &str, post: &Post) -> Result<Response, SlackError> {
+        let (mut payload, replies) = self.messages(post, channel, "");
+        payload.metadata = Some(Metadata::for_post(post));
+        payload.thread_ts = self.thread_root(channel).await?;
+
+        let response = self.post_message(&payload).await?;
+        for mut reply in replies {
+            // Threads don't nest, so under a thread root the rest of a long
+            // post follows it in the same thread.
+            reply.thread_ts = Some(payload.thread_ts.clone().unwrap_or(response.ts.clone()));
+            // The post itself is out, so a missing reply shouldn't get it announced twice.
+            if let Err(err) = self.send("chat.postMessage", &reply).await {
+                warn!(channel, link = %post.link, error = %err, "Failed posting thread reply for long post");
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Posts `post` as an "Updated:" reply in the thread under `root`. Long
+    /// posts continue in further replies in the same thread.
+    async fn reply_in(
+        &self,
+        channel: &str,
+        post: &Post,
+        root: &str,
+    ) -> Result<Response, SlackError> {
+        let (mut payload, replies) = self.messages(post, channel, "");
+        payload.text = format!("Updated: {}", payload.text);
+        if !payload.blocks.is_empty() {
+            payload.blocks.insert(
+                0,
+                Block::Section {
+                    text: TextObject {
+                        kind: "mrkdwn",
+                        text: "*Updated:*".to_string(),
+                    },
+                },
+            );
+        }
+
+        let mut response = None;
+        for mut message in std::iter::once(payload).chain(replies) {
+            message.thread_ts = Some(root.to_string());
+            match self.send("chat.postMessage", &message).await {
+                Ok(sent) => {
+                    response.get_or_insert(sent);
+                }
+                Err(err) if response.is_none() => return Err(err),
+                Err(err) => {
+                    warn!(channel, link = %post.link, error = %err, "Failed posting thread reply for long post");
+                }
+            }
+        }
+
+        Ok(response.unwrap_or_default())
+    }
+
+    async fn update_in(
+        &self,
+        channel: &str,
+        post: &Post,
+        timestamp: &str,
+    ) -> Result<Response, SlackError> {
+        let (payload, replies) = self.messages(post, channel, timestamp);
+        if !replies.is_empty() {
+            warn!(channel, link = %post.link, "Updating only the first part of a threaded long post");
+        }
 
         self.send("chat.update", &payload).await
     }
+
+    /// Reacts with `name` to the message `ts` in `channel`. Reacting twice
+    /// with the same emoji is no error, as the message already shows it.
+    pub async fn add_reaction(
+        &self,
+        channel: &str,
+        ts: &str,
+        name: &str,
+    ) -> Result<(), SlackError> {
+        let reaction = Reaction {
+            channel,
+            timestamp: ts,
+            name,
+        };
+        match self.send("reactions.add", &reaction).await {
+            Ok(_) => Ok(()),
+            Err(SlackError::Api { code }) if code == "already_reacted" => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The channel to edit or reply to messages from `channel` in: the id
+    /// Slack posted them under if it differs from the configured one.
+    fn resolved<'a>(ids: &'a MessageIds, channel: &'a str) -> &'a str {
+        ids.get(&resolved_channel_key(channel))
+            .map_or(channel, String::as_str)
+    }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct StdoutSlackClient;
+/// What Slack made of the token, as reported by `/healthz`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlackAuth {
+    Ok,
+    /// Slack turned the token down, e.g. with `invalid_auth` or `token_revoked`.
+    BadAuth,
+    /// Slack couldn't be asked.
+    Down,
+}
+
+/// How long an answer from `auth.test` is reused before asking again.
+const AUTH_CHECK_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Checks the Slack token with `auth.test`, reusing the answer for
+/// [`AUTH_CHECK_TTL`] so health probes don't call Slack every time.
+pub struct SlackAuthCheck {
+    notifier: SlackNotifier,
+    checked: tokio::sync::Mutex<Option<(Instant, SlackAuth)>>,
+}
+
+impl SlackAuthCheck {
+    pub fn new(notifier: SlackNotifier) -> Self {
+        Self {
+            notifier,
+            checked: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    pub async fn status(&self) -> SlackAuth {
+        let mut checked = self.checked.lock().await;
+        if let Some((at, auth)) = *checked
+            && at.elapsed() < AUTH_CHECK_TTL
+        {
+            return auth;
+        }
+
+        let auth = match self
+            .notifier
+            .send("auth.test", &serde_json::json!({}))
+            .await
+        {
+            Ok(_) => SlackAuth::Ok,
+            Err(SlackError::Api { code }) => {
+                error!(code, "Health check: Slack turned down the token");
+                SlackAuth::BadAuth
+            }
+            Err(err) => {
+                error!(error = %err, "Health check: unable to reach Slack");
+                SlackAuth::Down
+            }
+        };
+        *checked = Some((Instant::now(), auth));
+        auth
+    }
+}
+
+/// Tallies the channels a post or update went out to, failing only when it
+/// reached none of them.
+struct FanOut {
+    ids: MessageIds,
+    delivered: usize,
+    failed: Vec<String>,
+    last_error: Option<SlackError>,
+}
+
+impl FanOut {
+    fn new() -> Self {
+        Self {
+            ids: MessageIds::new(),
+            delivered: 0,
+            failed: Vec::new(),
+            last_error: None,
+        }
+    }
+
+    fn record(&mut self, channel: &str, result: Result<Response, SlackError>, link: &str) {
+        match result {
+            Ok(response) => {
+                self.ids.insert(channel.to_string(), response.ts);
+                if !response.channel.is_empty() && response.channel != channel {
+                    self.ids
+                        .insert(resolved_channel_key(channel), response.channel);
+                }
+                self.delivered += 1;
+            }
+            Err(err) => {
+                if err.is_missing_channel() {
+                    warn!(channel, link, error = %err, "Bot can't post in channel; invite the bot to {channel}");
+                } else {
+                    warn!(channel, link, error = %err, "Failed sending post to channel");
+                }
+                self.failed.push(channel.to_string());
+                self.last_error = Some(err);
+            }
+        }
+    }
+
+    fn finish(self, link: &str) -> Result<MessageIds, Error> {
+        match self.last_error {
+            Some(err) if self.delivered == 0 => Err(err.into()),
+            _ => {
+                if !self.failed.is_empty() {
+                    warn!(
+                        link,
+                        delivered = self.delivered,
+                        failed = %self.failed.join(","),
+                        "Post only reached some of the channels"
+                    );
+                }
+                Ok(self.ids)
+            }
+        }
+    }
+}
 
 #[async_trait]
-impl SlackClient for StdoutSlackClient {
-    async fn post_message(&self, post: &Post) -> Result<Response, Error> {
-        let content = format_slack_post(&post.content);
-        let text = format!("<{}|{}>\n{}", post.link, post.title, content);
-        info!(
-            title = %post.title,
-            link = %post.link,
-            "DRY_RUN Slack post"
+impl Notifier for SlackNotifier {
+    async fn post(&self, post: &Post) -> Result<MessageIds, Error> {
+        let mut fan_out = FanOut::new();
+        for channel in &self.config.channel_ids {
+            let result = self.post_to(channel, post).await;
+            fan_out.record(channel, result, &post.link);
+        }
+        fan_out.finish(&post.link)
+    }
+
+    async fn post_digest(&self, posts: &[&Post]) -> Result<MessageIds, Error> {
+        let text = digest_text(
+            posts,
+            self.config.layout,
+            &self.config.message_prefix,
+            &self.config.message_footer,
         );
-        debug!(%text, "DRY_RUN Slack post body");
+        let link = posts
+            .first()
+            .map(|post| post.link.as_str())
+            .unwrap_or_default();
+        let mut fan_out = FanOut::new();
+        for channel in &self.config.channel_ids {
+            let thread_ts = match self.thread_root(channel).await {
+                Ok(root) => root,
+                Err(err) => {
+                    fan_out.record(channel, Err(err), link);
+                    continue;
+                }
+            };
+            let payload = Message {
+                channel: channel.clone(),
+                ts: String::new(),
+                text: text.clone(),
+                blocks: Vec::new(),
+                thread_ts,
+                unfurl_links: self.config.unfurl,
+                unfurl_media: self.config.unfurl,
+                metadata: None,
+            };
+            let result = self.post_message(&payload).await;
+            fan_out.record(channel, result, link);
+        }
+        fan_out.finish(link)
+    }
+
+    async fn update(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        let mut fan_out = FanOut::new();
+        for (i, channel) in self.config.channel_ids.iter().enumerate() {
+            // Entries from before fan-out only know the message in the first channel.
+            let existing = ids
+                .get(channel)
+                .or_else(|| ids.get(DEFAULT_CHANNEL).filter(|_| i == 0));
+            let target = Self::resolved(ids, channel);
+            let result = match existing {
+                Some(ts) => match self.update_in(target, post, ts).await {
+                    // Someone deleted the announcement, so edits would never land again.
+                    Err(SlackError::Api { code }) if code == "message_not_found" => {
+                        warn!(channel, link = %post.link, "Announcement was deleted in Slack, posting it again");
+                        self.post_to(channel, post).await
+                    }
+                    Ok(response) if self.config.react_on_update => {
+                        // The edit is what matters, so a missing reaction is only logged.
+                        if let Err(err) = self.add_reaction(target, ts, UPDATED_REACTION).await {
+                            warn!(channel, link = %post.link, error = %err, "Failed reacting to updated announcement");
+                        }
+                        Ok(response)
+                    }
+                    result => result,
+                },
+                // A channel added since the post went out gets it now.
+                None => self.post_to(channel, post).await,
+            };
+            if let (Err(_), Some(ts)) = (&result, existing) {
+                fan_out.ids.insert(channel.clone(), ts.clone());
+                if target != channel {
+                    fan_out
+                        .ids
+                        .insert(resolved_channel_key(channel), target.to_string());
+                }
+            }
+            fan_out.record(channel, result, &post.link);
+        }
+        fan_out.finish(&post.link)
+    }
+
+    async fn reply(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        let mut fan_out = FanOut::new();
+        for (i, channel) in self.config.channel_ids.iter().enumerate() {
+            // The announcement is itself a reply under a thread root, so the
+            // update goes in that thread.
+            let thread_root = match self.thread_root(channel).await {
+                Ok(root) => root,
+                Err(err) => {
+                    fan_out.record(channel, Err(err), &post.link);
+                    continue;
+                }
+            };
+            let root = thread_root.as_ref().or_else(|| {
+                ids.get(channel)
+                    .or_else(|| ids.get(DEFAULT_CHANNEL).filter(|_| i == 0))
+            });
+            let Some(root) = root else {
+                // There is no thread to reply in for a channel added since the post went out.
+                warn!(channel, link = %post.link, "No announcement to reply to in channel, skipping");
+                continue;
+            };
+            let result = self
+                .reply_in(Self::resolved(ids, channel), post, root)
+                .await;
+            fan_out.record(channel, result, &post.link);
+        }
+        fan_out.finish(&post.link)
+    }
+
+    async fn thread_roots(&self) -> MessageIds {
+        self.thread_roots.lock().await.clone()
+    }
 
-        Ok(Response {
-            ok: true,
-            ts: "dry-run".to_string(),
-            error: String::new(),
-        })
+    async fn restore_thread_roots(&self, roots: &MessageIds) {
+        let mut kept = self.thread_roots.lock().await;
+        for (channel, root) in roots {
+            kept.entry(channel.clone()).or_insert_with(|| root.clone());
+        }
     }
+}
+
+/// Channel reported in DRY_RUN payloads, since there is no Slack config to take it from.
+const DRY_RUN_CHANNEL: &str = "dry-run";
 
-    async fn update_message(&self, post: &Post, timestamp: &str) -> Result<Response, Error> {
-        let content = format_slack_post(&post.content);
-        let text = format!("<{}|{}>\n{}", post.link, post.title, content);
+/// Logs the rendered Slack payloads instead of sending them. Used in DRY_RUN mode.
+#[derive(Debug, Clone, Default)]
+pub struct StdoutNotifier;
+
+impl StdoutNotifier {
+    fn log(&self, method: &str, payload: &Message) {
         info!(
-            title = %post.title,
-            link = %post.link,
-            ts = %timestamp,
-            "DRY_RUN Slack update"
+            method,
+            channel = %payload.channel,
+            ts = %payload.ts,
+            text = %payload.text,
+            "DRY_RUN Slack payload"
         );
-        debug!(%text, "DRY_RUN Slack update body");
+    }
+}
+
+#[async_trait]
+impl Notifier for StdoutNotifier {
+    async fn post(&self, post: &Post) -> Result<MessageIds, Error> {
+        let payload = Message {
+            channel: DRY_RUN_CHANNEL.to_string(),
+            ts: String::new(),
+            text: message_text(post, ContentFormat::default()),
+            blocks: Vec::new(),
+            thread_ts: None,
+            unfurl_links: false,
+            unfurl_media: false,
+            metadata: None,
+        };
+        self.log("chat.postMessage", &payload);
+
+        Ok(single_message("dry-run".to_string()))
+    }
+
+    async fn update(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        let timestamp = ids.get(DEFAULT_CHANNEL).cloned().unwrap_or_default();
+        let payload = Message {
+            channel: DRY_RUN_CHANNEL.to_string(),
+            ts: timestamp.clone(),
+            text: message_text(post, ContentFormat::default()),
+            blocks: Vec::new(),
+            thread_ts: None,
+            unfurl_links: false,
+            unfurl_media: false,
+            metadata: None,
+        };
+        self.log("chat.update", &payload);
+
+        Ok(single_message(timestamp))
+    }
+
+    async fn reply(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        let payload = Message {
+            channel: DRY_RUN_CHANNEL.to_string(),
+            ts: String::new(),
+            text: format!("Updated: {}", message_text(post, ContentFormat::default())),
+            blocks: Vec::new(),
+            thread_ts: ids.get(DEFAULT_CHANNEL).cloned(),
+            unfurl_links: false,
+            unfurl_media: false,
+            metadata: None,
+        };
+        self.log("chat.postMessage", &payload);
 
-        Ok(Response {
-            ok: true,
-            ts: timestamp.to_string(),
-            error: String::new(),
-        })
+        Ok(single_message("dry-run".to_string()))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::format_slack_post;
+    use super::{
+        Block, MESSAGE_TEXT_LIMIT, Response, SECTION_TEXT_LIMIT, SlackError, SlackNotifier,
+        StdoutNotifier, THREAD_ROOT_TEXT, digest_text, format_content, format_slack_post,
+        message_blocks, message_texts, split_text, truncate_text,
+    };
+    use crate::{
+        config::{
+            AppConfig, AppState, ContentFormat, DEFAULT_SLACK_API_BASE_URL, LongPostMode,
+            SlackConfig, SlackLayout, parse_slack_api_base_url,
+        },
+        notifier::{MessageIds, Notifier, single_message},
+        rate_limit::RateLimiter,
+        redis_client::{InMemoryValkey, ValkeyClient},
+        rss::{self, Post},
+    };
+    use axum::{Json, Router, http::StatusCode, response::IntoResponse, routing::post};
+    use chrono::{TimeZone, Utc};
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::{Duration, Instant},
+    };
+    use tracing_test::traced_test;
 
-    #[test]
-    fn formats_single_markdown_link() {
-        let input = "See [NAIS](https://nais.io) for more info";
-        let expected = "See <https://nais.io|NAIS> for more info";
-        assert_eq!(format_slack_post(input), expected);
+    fn slack_config(max_attempts: u32) -> SlackConfig {
+        SlackConfig {
+            token: "xoxb-test".to_string(),
+            channel_ids: vec!["C0000000000".to_string()],
+            max_attempts,
+            use_blocks: false,
+            long_posts: LongPostMode::default(),
+            rate_limit: Duration::ZERO,
+            message_prefix: String::new(),
+            message_footer: String::new(),
+            api_base_url: DEFAULT_SLACK_API_BASE_URL.to_string(),
+            timeout: Duration::from_secs(10),
+            unfurl: false,
+            content_format: ContentFormat::Markdown,
+            layout: SlackLayout::Full,
+            react_on_update: false,
+            auto_join: false,
+            breaker_threshold: 0,
+            breaker_cooldown: Duration::ZERO,
+            threaded: false,
+            thread_root_ts: None,
+        }
     }
 
-    #[test]
-    fn formats_multiple_markdown_links() {
+    fn no_rate_limit() -> Arc<RateLimiter> {
+        Arc::new(RateLimiter::new(Duration::ZERO, 1))
+    }
+
+    fn sample_post() -> Post {
+        Post {
+            title: "Test Post".to_string(),
+            link: "https://nais.io/log#test-post".to_string(),
+            pub_date: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            content: "Content".to_string(),
+            categories: Vec::new(),
+            guid: None,
+        }
+    }
+
+    /// Serves `chat.postMessage` locally, rate limiting the first `limited` calls.
+    async fn rate_limited_slack(limited: usize) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = Router::new().route(
+            "/chat.postMessage",
+            post(move || {
+                let counter = counter.clone();
+                async move {
+                    if counter.fetch_add(1, Ordering::SeqCst) < limited {
+                        (StatusCode::TOO_MANY_REQUESTS, [("Retry-After", "0")]).into_response()
+                    } else {
+                        Json(serde_json::json!({ "ok": true, "ts": "1700000000.000100" }))
+                            .into_response()
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), calls)
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_posts() {
+        let (base_url, calls) = rate_limited_slack(2).await;
+        let client = SlackNotifier::new(slack_config(3), reqwest::Client::new(), no_rate_limit())
+            .with_base_url(&base_url);
+
+        let ids = client.post(&sample_post()).await.unwrap();
+
+        assert_eq!(ids["C0000000000"], "1700000000.000100");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn rapid_posts_are_spaced_by_rate_limit() {
+        let (base_url, calls) = rate_limited_slack(0).await;
+        let interval = Duration::from_millis(50);
+        let client = SlackNotifier::new(
+            slack_config(1),
+            reqwest::Client::new(),
+            Arc::new(RateLimiter::new(interval, 1)),
+        )
+        .with_base_url(&base_url);
+
+        let start = Instant::now();
+        for _ in 0..4 {
+            client.post(&sample_post()).await.unwrap();
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert!(start.elapsed() >= interval * 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let (base_url, calls) = rate_limited_slack(usize::MAX).await;
+        let client = SlackNotifier::new(slack_config(2), reqwest::Client::new(), no_rate_limit())
+            .with_base_url(&base_url);
+
+        let err = client.post(&sample_post()).await.unwrap_err();
+
+        assert!(err.to_string().contains("429"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    /// Serves `chat.postMessage` locally, failing every post to `broken`.
+    async fn slack_with_broken_channel(broken: &'static str) -> String {
+        let app = Router::new().route(
+            "/chat.postMessage",
+            post(move |Json(body): Json<serde_json::Value>| async move {
+                if body["channel"] == broken {
+                    Json(serde_json::json!({ "ok": false, "error": "channel_not_found" }))
+                } else {
+                    Json(serde_json::json!({ "ok": true, "ts": "1700000000.000100" }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    /// Serves a Slack that posts the first message it gets but never
+    /// answers, and lists it in the channel history only if `listed`.
+    async fn slack_losing_first_response(listed: bool) -> (String, Arc<AtomicUsize>) {
+        let posts = Arc::new(AtomicUsize::new(0));
+        let counter = posts.clone();
+        let first = Arc::new(std::sync::Mutex::new(None));
+        let stored = first.clone();
+        let app = Router::new()
+            .route(
+                "/chat.postMessage",
+                post(move |Json(body): Json<serde_json::Value>| {
+                    let attempt = counter.fetch_add(1, Ordering::SeqCst);
+                    if attempt == 0 {
+                        *stored.lock().unwrap() = Some(body["metadata"].clone());
+                    }
+                    async move {
+                        if attempt == 0 {
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                        }
+                        Json(serde_json::json!({ "ok": true, "ts": "1700000000.000200" }))
+                    }
+                }),
+            )
+            .route(
+                "/conversations.history",
+                axum::routing::get(move || {
+                    let metadata = first.lock().unwrap().clone();
+                    async move {
+                        let messages = match metadata {
+                            Some(metadata) if listed => vec![
+                                serde_json::json!({ "ts": "1700000000.000050" }),
+                                serde_json::json!({
+                                    "ts": "1700000000.000100",
+                                    "metadata": metadata
+                                }),
+                            ],
+                            _ => Vec::new(),
+                        };
+                        Json(serde_json::json!({ "ok": true, "messages": messages }))
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), posts)
+    }
+
+    fn impatient_client(base_url: &str) -> SlackNotifier {
+        let config = SlackConfig {
+            timeout: Duration::from_millis(100),
+            ..slack_config(2)
+        };
+        SlackNotifier::new(config, reqwest::Client::new(), no_rate_limit()).with_base_url(base_url)
+    }
+
+    #[tokio::test]
+    async fn retry_after_a_lost_response_finds_the_post_instead_of_posting_again() {
+        let (base_url, posts) = slack_losing_first_response(true).await;
+
+        let ids = impatient_client(&base_url)
+            .post(&sample_post())
+            .await
+            .unwrap();
+
+        assert_eq!(posts.load(Ordering::SeqCst), 1);
+        assert_eq!(ids["C0000000000"], "1700000000.000100");
+    }
+
+    #[tokio::test]
+    async fn retry_posts_again_when_the_lost_attempt_is_nowhere_to_be_found() {
+        let (base_url, posts) = slack_losing_first_response(false).await;
+
+        let ids = impatient_client(&base_url)
+            .post(&sample_post())
+            .await
+            .unwrap();
+
+        assert_eq!(posts.load(Ordering::SeqCst), 2);
+        assert_eq!(ids["C0000000000"], "1700000000.000200");
+    }
+
+    #[tokio::test]
+    async fn calls_the_configured_api_base_url() {
+        let (base_url, calls) = rate_limited_slack(0).await;
+        let config = SlackConfig {
+            api_base_url: parse_slack_api_base_url(&base_url).unwrap(),
+            ..slack_config(1)
+        };
+        let client = SlackNotifier::new(config, reqwest::Client::new(), no_rate_limit());
+
+        let ids = client.post(&sample_post()).await.unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(ids["C0000000000"], "1700000000.000100");
+    }
+
+    #[tokio::test]
+    async fn slow_slack_times_out() {
+        let app = Router::new().route(
+            "/chat.postMessage",
+            post(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Json(serde_json::json!({ "ok": true, "ts": "1700000000.000100" }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let config = SlackConfig {
+            timeout: Duration::from_millis(100),
+            ..slack_config(1)
+        };
+        let client = SlackNotifier::new(config, reqwest::Client::new(), no_rate_limit())
+            .with_base_url(&format!("http://{addr}"));
+
+        let started = Instant::now();
+        let err = client.post(&sample_post()).await.unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let source = err.into_inner().unwrap().downcast::<SlackError>().unwrap();
+        assert!(
+            matches!(*source, SlackError::Timeout(after) if after == Duration::from_millis(100))
+        );
+        assert_eq!(source.to_string(), "Slack didn't answer within 0.1s");
+    }
+
+    #[tokio::test]
+    async fn reply_posts_in_the_announcement_thread() {
+        let payloads = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = payloads.clone();
+        let app = Router::new().route(
+            "/chat.postMessage",
+            post(move |Json(payload): Json<serde_json::Value>| {
+                let recorded = recorded.clone();
+                async move {
+                    recorded.lock().unwrap().push(payload);
+                    Json(serde_json::json!({ "ok": true, "ts": "1700000000.000200" }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = SlackNotifier::new(slack_config(1), reqwest::Client::new(), no_rate_limit())
+            .with_base_url(&format!("http://{addr}"));
+
+        let ids = client
+            .reply(
+                &sample_post(),
+                &single_message("1700000000.000100".to_string()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(ids["C0000000000"], "1700000000.000200");
+        let payloads = payloads.lock().unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["thread_ts"], "1700000000.000100");
+        assert!(
+            payloads[0]["text"]
+                .as_str()
+                .unwrap()
+                .starts_with("Updated: ")
+        );
+    }
+
+    #[tokio::test]
+    async fn posts_to_every_channel_that_accepts() {
+        let base_url = slack_with_broken_channel("C0BROKEN00").await;
+        let config = SlackConfig {
+            channel_ids: vec!["C0000000000".to_string(), "C0BROKEN00".to_string()],
+            ..slack_config(1)
+        };
+        let client = SlackNotifier::new(config, reqwest::Client::new(), no_rate_limit())
+            .with_base_url(&base_url);
+
+        let ids = client.post(&sample_post()).await.unwrap();
+
+        assert_eq!(ids.len(), 1);
+        assert_eq!(ids["C0000000000"], "1700000000.000100");
+    }
+
+    #[tokio::test]
+    async fn fails_when_no_channel_accepts() {
+        let base_url = slack_with_broken_channel("C0BROKEN00").await;
+        let config = SlackConfig {
+            channel_ids: vec!["C0BROKEN00".to_string()],
+            ..slack_config(1)
+        };
+        let client = SlackNotifier::new(config, reqwest::Client::new(), no_rate_limit())
+            .with_base_url(&base_url);
+
+        let err = client.post(&sample_post()).await.unwrap_err();
+
+        assert!(err.to_string().contains("channel_not_found"));
+    }
+
+    /// Posts through a local `chat.postMessage` answering with `status` and
+    /// `body`, returning the `SlackError` behind the failure.
+    async fn slack_error(status: StatusCode, body: &'static str) -> SlackError {
+        let app = Router::new().route(
+            "/chat.postMessage",
+            post(move || async move { (status, body) }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = SlackNotifier::new(slack_config(1), reqwest::Client::new(), no_rate_limit())
+            .with_base_url(&format!("http://{addr}"));
+
+        let err = client.post(&sample_post()).await.unwrap_err();
+        *err.into_inner().unwrap().downcast::<SlackError>().unwrap()
+    }
+
+    #[tokio::test]
+    async fn error_status_is_http_error() {
+        let err = slack_error(StatusCode::SERVICE_UNAVAILABLE, "").await;
+
+        assert!(matches!(
+            err,
+            SlackError::Http {
+                status: Some(StatusCode::SERVICE_UNAVAILABLE),
+                ..
+            }
+        ));
+        assert!(!err.is_rate_limited());
+    }
+
+    #[tokio::test]
+    async fn unreadable_response_is_decode_error() {
+        let err = slack_error(StatusCode::OK, "<html>Slack is down</html>").await;
+
+        assert!(matches!(err, SlackError::Decode(_)));
+    }
+
+    #[tokio::test]
+    async fn refused_call_is_api_error() {
+        let err = slack_error(
+            StatusCode::OK,
+            r#"{ "ok": false, "error": "channel_not_found" }"#,
+        )
+        .await;
+
+        assert!(matches!(&err, SlackError::Api { code } if code == "channel_not_found"));
+        assert!(!err.is_rate_limited());
+
+        let err = slack_error(StatusCode::OK, r#"{ "ok": false, "error": "ratelimited" }"#).await;
+        assert!(err.is_rate_limited());
+    }
+
+    #[tokio::test]
+    async fn deleted_message_is_posted_again_and_archived() {
+        let posted = Arc::new(AtomicUsize::new(0));
+        let counter = posted.clone();
+        let app = Router::new()
+            .route(
+                "/chat.update",
+                post(|| async {
+                    Json(serde_json::json!({ "ok": false, "error": "message_not_found" }))
+                }),
+            )
+            .route(
+                "/chat.postMessage",
+                post(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    async { Json(serde_json::json!({ "ok": true, "ts": "1700000001.000200" })) }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut state = AppState::new(AppConfig::DryRun);
+        state.notifier = Arc::new(
+            SlackNotifier::new(slack_config(1), reqwest::Client::new(), no_rate_limit())
+                .with_base_url(&format!("http://{addr}")),
+        );
+        let mut store = InMemoryValkey::new();
+        store
+            .set(
+                "test-post",
+                r#"{"content_hash":"stale","timestamp":"1700000000.000100"}"#,
+            )
+            .await
+            .unwrap();
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>NAIS Log</title>
+    <item>
+      <title>Test Post</title>
+      <link>https://nais.io/log#test-post</link>
+      <pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+      <encoded><![CDATA[Edited content]]></encoded>
+    </item>
+  </channel>
+</rss>"#;
+
+        let summary = rss::handle_feed(feed, &mut store, &state).await.unwrap();
+
+        assert_eq!(summary.updated, 1);
+        assert_eq!(posted.load(Ordering::SeqCst), 1);
+        let archive: serde_json::Value =
+            serde_json::from_str(&store.get("test-post").await.unwrap().unwrap()).unwrap();
+        assert_eq!(
+            archive["timestamps"],
+            serde_json::json!({ "C0000000000": "1700000001.000200" })
+        );
+    }
+
+    #[test]
+    fn messages_ask_slack_not_to_unfurl_unless_configured() {
+        let payload = |unfurl| {
+            let client = SlackNotifier::new(
+                SlackConfig {
+                    unfurl,
+                    ..slack_config(1)
+                },
+                reqwest::Client::new(),
+                no_rate_limit(),
+            );
+            let (message, _) = client.messages(&sample_post(), "C0000000000", "");
+            serde_json::to_value(&message).unwrap()
+        };
+
+        let quiet = payload(false);
+        assert_eq!(quiet["unfurl_links"], serde_json::json!(false));
+        assert_eq!(quiet["unfurl_media"], serde_json::json!(false));
+        let unfurled = payload(true);
+        assert_eq!(unfurled["unfurl_links"], serde_json::json!(true));
+        assert_eq!(unfurled["unfurl_media"], serde_json::json!(true));
+    }
+
+    #[test]
+    fn reads_the_channel_from_a_response() {
+        let response: Response = serde_json::from_str(
+            r#"{
+                "ok": true,
+                "channel": "C0123456789",
+                "ts": "1700000000.000100",
+                "message": {
+                    "type": "message",
+                    "subtype": "bot_message",
+                    "text": "<https://nais.io/log#test-post|Test Post>",
+                    "ts": "1700000000.000100",
+                    "username": "NAIS Log",
+                    "bot_id": "B0123456789"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(response.ok);
+        assert_eq!(response.channel, "C0123456789");
+        assert_eq!(response.ts, "1700000000.000100");
+    }
+
+    #[tokio::test]
+    async fn edits_go_to_the_channel_slack_posted_in() {
+        let updated_in = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = updated_in.clone();
+        let app = Router::new()
+            .route(
+                "/chat.postMessage",
+                post(|| async {
+                    Json(serde_json::json!({
+                        "ok": true,
+                        "channel": "C0123456789",
+                        "ts": "1700000000.000100"
+                    }))
+                }),
+            )
+            .route(
+                "/chat.update",
+                post(move |Json(body): Json<serde_json::Value>| {
+                    recorder
+                        .lock()
+                        .unwrap()
+                        .push(body["channel"].as_str().unwrap().to_string());
+                    async move {
+                        Json(serde_json::json!({
+                            "ok": true,
+                            "channel": body["channel"],
+                            "ts": body["ts"]
+                        }))
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = SlackNotifier::new(
+            SlackConfig {
+                channel_ids: vec!["#announcements".to_string()],
+                ..slack_config(1)
+            },
+            reqwest::Client::new(),
+            no_rate_limit(),
+        )
+        .with_base_url(&format!("http://{addr}"));
+
+        let ids = client.post(&sample_post()).await.unwrap();
+        let updated = client.update(&sample_post(), &ids).await.unwrap();
+
+        assert_eq!(*updated_in.lock().unwrap(), vec!["C0123456789"]);
+        assert_eq!(updated, ids);
+    }
+
+    /// Serves `chat.update` and `reactions.add`, recording the reactions
+    /// and answering them with `reaction_error` if set.
+    async fn reacting_slack(
+        reaction_error: Option<&'static str>,
+    ) -> (String, Arc<std::sync::Mutex<Vec<serde_json::Value>>>) {
+        let reactions = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = reactions.clone();
+        let app = Router::new()
+            .route(
+                "/chat.update",
+                post(|Json(body): Json<serde_json::Value>| async move {
+                    Json(serde_json::json!({ "ok": true, "ts": body["ts"] }))
+                }),
+            )
+            .route(
+                "/reactions.add",
+                post(move |Json(body): Json<serde_json::Value>| {
+                    recorded.lock().unwrap().push(body);
+                    async move {
+                        Json(match reaction_error {
+                            Some(error) => serde_json::json!({ "ok": false, "error": error }),
+                            None => serde_json::json!({ "ok": true }),
+                        })
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), reactions)
+    }
+
+    /// Serves a Slack where the bot isn't in any channel until it joins one,
+    /// recording the channels joined.
+    async fn uninvited_slack() -> (String, Arc<std::sync::Mutex<Vec<serde_json::Value>>>) {
+        let joined = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (recorded, checked) = (joined.clone(), joined.clone());
+        let app = Router::new()
+            .route(
+                "/chat.postMessage",
+                post(move || {
+                    let member = !checked.lock().unwrap().is_empty();
+                    async move {
+                        Json(if member {
+                            serde_json::json!({ "ok": true, "ts": "1700000000.000100" })
+                        } else {
+                            serde_json::json!({ "ok": false, "error": "not_in_channel" })
+                        })
+                    }
+                }),
+            )
+            .route(
+                "/conversations.join",
+                post(move |Json(body): Json<serde_json::Value>| {
+                    recorded.lock().unwrap().push(body["channel"].clone());
+                    async { Json(serde_json::json!({ "ok": true })) }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), joined)
+    }
+
+    fn joining_client(base_url: &str, auto_join: bool) -> SlackNotifier {
+        SlackNotifier::new(
+            SlackConfig {
+                auto_join,
+                ..slack_config(1)
+            },
+            reqwest::Client::new(),
+            no_rate_limit(),
+        )
+        .with_base_url(base_url)
+    }
+
+    /// Posts like the ones on nais.io, covering links, lists, code blocks
+    /// and text outside ASCII.
+    fn nais_posts() -> [Post; 3] {
+        let post = |title: &str, fragment: &str, content: &str| Post {
+            title: title.to_string(),
+            link: format!("https://nais.io/log#{fragment}"),
+            content: content.to_string(),
+            ..sample_post()
+        };
+        [
+            post(
+                "Nytt i Nais: bedre logging",
+                "bedre-logging",
+                "## Hva er nytt\nLes **mer** i [dokumentasjonen](https://doc.nais.io/observability/logging) og på <https://nais.io>.\n\n- Strukturerte logger\n- _Raskere_ søk",
+            ),
+            post(
+                "Slik logger du inn med nais-cli",
+                "nais-cli",
+                "Kjør dette:\n\n```shell\nnais login\nkubectl get pods -n **team**\n```\n\nBruk `nais device connect` først.",
+            ),
+            post(
+                "Blåbær og ærfugl: støtte for ÆØÅ 🚀",
+                "unicode",
+                "Navn som «Åse» og “Øyvind” — vises nå riktig… 🎉\n\n### Takk til 日本 teamet",
+            ),
+        ]
+    }
+
+    fn rendered(post: &Post, use_blocks: bool) -> serde_json::Value {
+        let client = SlackNotifier::new(
+            SlackConfig {
+                use_blocks,
+                ..slack_config(1)
+            },
+            reqwest::Client::new(),
+            no_rate_limit(),
+        );
+        let (message, replies) = client.messages(post, "C0000000000", "");
+        assert!(replies.is_empty());
+        serde_json::to_value(message).unwrap()
+    }
+
+    #[test]
+    fn nais_posts_render_to_mrkdwn() {
+        let [links, code, unicode] = nais_posts();
+
+        assert_eq!(
+            format_slack_post(&links.content),
+            "*Hva er nytt*\nLes *mer* i <https://doc.nais.io/observability/logging|dokumentasjonen> og på <https://nais.io>.\n\n• Strukturerte logger\n• _Raskere_ søk"
+        );
+        assert_eq!(
+            format_slack_post(&code.content),
+            "Kjør dette:\n\n```shell\nnais login\nkubectl get pods -n **team**\n```\n\nBruk `nais device connect` først."
+        );
+        assert_eq!(
+            format_slack_post(&unicode.content),
+            "Navn som «Åse» og “Øyvind” — vises nå riktig… 🎉\n\n*Takk til 日本 teamet*"
+        );
+    }
+
+    #[test]
+    fn nais_posts_are_sent_as_text_exactly_like_this() {
+        let [links, code, unicode] = nais_posts();
+        let text = |text: &str| {
+            serde_json::json!({
+                "channel": "C0000000000",
+                "ts": "",
+                "text": text,
+                "unfurl_links": false,
+                "unfurl_media": false
+            })
+        };
+
+        assert_eq!(
+            rendered(&links, false),
+            text(
+                "<https://nais.io/log#bedre-logging|Nytt i Nais: bedre logging>\n*Hva er nytt*\nLes *mer* i <https://doc.nais.io/observability/logging|dokumentasjonen> og på <https://nais.io>.\n\n• Strukturerte logger\n• _Raskere_ søk"
+            )
+        );
+        assert_eq!(
+            rendered(&code, false),
+            text(
+                "<https://nais.io/log#nais-cli|Slik logger du inn med nais-cli>\nKjør dette:\n\n```shell\nnais login\nkubectl get pods -n **team**\n```\n\nBruk `nais device connect` først."
+            )
+        );
+        assert_eq!(
+            rendered(&unicode, false),
+            text(
+                "<https://nais.io/log#unicode|Blåbær og ærfugl: støtte for ÆØÅ 🚀>\nNavn som «Åse» og “Øyvind” — vises nå riktig… 🎉\n\n*Takk til 日本 teamet*"
+            )
+        );
+    }
+
+    #[test]
+    fn nais_posts_are_sent_as_blocks_exactly_like_this() {
+        let [_, _, unicode] = nais_posts();
+
+        assert_eq!(
+            rendered(&unicode, true),
+            serde_json::json!({
+                "channel": "C0000000000",
+                "ts": "",
+                "text": "<https://nais.io/log#unicode|Blåbær og ærfugl: støtte for ÆØÅ 🚀>\nNavn som «Åse» og “Øyvind” — vises nå riktig… 🎉\n\n*Takk til 日本 teamet*",
+                "blocks": [
+                    {
+                        "type": "header",
+                        "text": { "type": "plain_text", "text": "Blåbær og ærfugl: støtte for ÆØÅ 🚀" }
+                    },
+                    {
+                        "type": "section",
+                        "text": {
+                            "type": "mrkdwn",
+                            "text": "<https://nais.io/log#unicode>\nNavn som «Åse» og “Øyvind” — vises nå riktig… 🎉\n\n*Takk til 日本 teamet*"
+                        }
+                    }
+                ],
+                "unfurl_links": false,
+                "unfurl_media": false
+            })
+        );
+    }
+
+    #[test]
+    fn missing_channel_errors_are_told_apart() {
+        let api = |code: &str| SlackError::Api {
+            code: code.to_string(),
+        };
+        assert!(api("not_in_channel").is_missing_channel());
+        assert!(api("channel_not_found").is_missing_channel());
+        assert!(!api("invalid_auth").is_missing_channel());
+        assert!(!SlackError::Timeout(Duration::from_secs(1)).is_missing_channel());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn posting_where_the_bot_isnt_invited_says_to_invite_it() {
+        let (base_url, joined) = uninvited_slack().await;
+        let client = joining_client(&base_url, false);
+
+        let err = client.post(&sample_post()).await.unwrap_err();
+
+        assert!(err.to_string().contains("not_in_channel"));
+        assert!(logs_contain("invite the bot to C0000000000"));
+        assert!(joined.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn auto_join_joins_the_channel_and_posts_again() {
+        let (base_url, joined) = uninvited_slack().await;
+        let client = joining_client(&base_url, true);
+
+        let ids = client.post(&sample_post()).await.unwrap();
+
+        assert_eq!(
+            *joined.lock().unwrap(),
+            vec![serde_json::json!("C0000000000")]
+        );
+        assert_eq!(ids.get("C0000000000").unwrap(), "1700000000.000100");
+    }
+
+    /// Serves `chat.postMessage`, `chat.update` and `pins.add`, recording
+    /// every call by method. Posted messages get ts `1700000000.00000N`,
+    /// counting from 1.
+    async fn threading_slack() -> (
+        String,
+        Arc<std::sync::Mutex<Vec<(String, serde_json::Value)>>>,
+    ) {
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = |method: &'static str| {
+            let calls = calls.clone();
+            post(move |Json(body): Json<serde_json::Value>| {
+                let mut calls = calls.lock().unwrap();
+                calls.push((method.to_string(), body.clone()));
+                let posted = calls
+                    .iter()
+                    .filter(|(method, _)| method == "chat.postMessage")
+                    .count();
+                async move {
+                    Json(match method {
+                        "chat.postMessage" => serde_json::json!({
+                            "ok": true,
+                            "channel": body["channel"],
+                            "ts": format!("1700000000.{posted:06}"),
+                        }),
+                        "chat.update" => serde_json::json!({ "ok": true, "ts": body["ts"] }),
+                        _ => serde_json::json!({ "ok": true }),
+                    })
+                }
+            })
+        };
+        let app = Router::new()
+            .route("/chat.postMessage", record("chat.postMessage"))
+            .route("/chat.update", record("chat.update"))
+            .route("/pins.add", record("pins.add"));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), calls)
+    }
+
+    fn threading_client(base_url: &str, thread_root_ts: Option<&str>) -> SlackNotifier {
+        SlackNotifier::new(
+            SlackConfig {
+                threaded: true,
+                thread_root_ts: thread_root_ts.map(str::to_string),
+                ..slack_config(1)
+            },
+            reqwest::Client::new(),
+            no_rate_limit(),
+        )
+        .with_base_url(base_url)
+    }
+
+    #[tokio::test]
+    async fn threaded_posts_go_under_a_root_posted_and_pinned_once() {
+        let (base_url, calls) = threading_slack().await;
+        let client = threading_client(&base_url, None);
+
+        let first = client.post(&sample_post()).await.unwrap();
+        let second = client.post(&sample_post()).await.unwrap();
+        client.update(&sample_post(), &second).await.unwrap();
+
+        assert_eq!(
+            client.thread_roots().await,
+            MessageIds::from([("C0000000000".to_string(), "1700000000.000001".to_string())])
+        );
+        let calls = calls.lock().unwrap();
+        let summary: Vec<_> = calls
+            .iter()
+            .map(|(method, body)| {
+                (
+                    method.as_str(),
+                    body["ts"].clone(),
+                    body["thread_ts"].clone(),
+                )
+            })
+            .collect();
+        let root = serde_json::json!("1700000000.000001");
+        assert_eq!(
+            summary,
+            [
+                ("chat.postMessage", "".into(), serde_json::Value::Null),
+                ("pins.add", serde_json::Value::Null, serde_json::Value::Null),
+                ("chat.postMessage", "".into(), root.clone()),
+                ("chat.postMessage", "".into(), root.clone()),
+                (
+                    "chat.update",
+                    "1700000000.000003".into(),
+                    serde_json::Value::Null
+                ),
+            ]
+        );
+        assert_eq!(calls[0].1["text"], THREAD_ROOT_TEXT);
+        assert_eq!(calls[1].1["timestamp"], root);
+        assert_eq!(first.get("C0000000000").unwrap(), "1700000000.000002");
+        assert_eq!(second.get("C0000000000").unwrap(), "1700000000.000003");
+    }
+
+    #[tokio::test]
+    async fn known_thread_roots_are_posted_under_as_they_are() {
+        let (base_url, calls) = threading_slack().await;
+        let configured = threading_client(&base_url, Some("1600000000.000100"));
+        let restored = threading_client(&base_url, None);
+        restored
+            .restore_thread_roots(&MessageIds::from([(
+                "C0000000000".to_string(),
+                "1650000000.000100".to_string(),
+            )]))
+            .await;
+
+        configured.post(&sample_post()).await.unwrap();
+        restored.post(&sample_post()).await.unwrap();
+        restored
+            .reply(
+                &sample_post(),
+                &single_message("1700000000.000002".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let thread_ts: Vec<_> = calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(method, body)| (method.clone(), body["thread_ts"].clone()))
+            .collect();
+        assert_eq!(
+            thread_ts,
+            [
+                ("chat.postMessage".to_string(), "1600000000.000100".into()),
+                ("chat.postMessage".to_string(), "1650000000.000100".into()),
+                ("chat.postMessage".to_string(), "1650000000.000100".into()),
+            ]
+        );
+    }
+
+    fn reacting_client(base_url: &str, react_on_update: bool) -> SlackNotifier {
+        SlackNotifier::new(
+            SlackConfig {
+                react_on_update,
+                ..slack_config(1)
+            },
+            reqwest::Client::new(),
+            no_rate_limit(),
+        )
+        .with_base_url(base_url)
+    }
+
+    #[tokio::test]
+    async fn updates_react_to_the_stored_message() {
+        let (base_url, reactions) = reacting_slack(None).await;
+        let client = reacting_client(&base_url, true);
+        let ids = single_message("1700000000.000100".to_string());
+
+        client.update(&sample_post(), &ids).await.unwrap();
+
+        assert_eq!(
+            *reactions.lock().unwrap(),
+            vec![serde_json::json!({
+                "channel": "C0000000000",
+                "timestamp": "1700000000.000100",
+                "name": "pencil2"
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn updates_only_react_when_configured() {
+        let (base_url, reactions) = reacting_slack(None).await;
+        let client = reacting_client(&base_url, false);
+        let ids = single_message("1700000000.000100".to_string());
+
+        client.update(&sample_post(), &ids).await.unwrap();
+
+        assert!(reactions.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reacting_again_is_no_error() {
+        let (base_url, _) = reacting_slack(Some("already_reacted")).await;
+        let client = reacting_client(&base_url, true);
+
+        let result = client
+            .add_reaction("C0000000000", "1700000000.000100", "pencil2")
+            .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn dry_run_client_accepts_posts() {
+        let client = StdoutNotifier;
+
+        let posted = client.post(&sample_post()).await.unwrap();
+        assert_eq!(posted, single_message("dry-run".to_string()));
+
+        let updated = client.update(&sample_post(), &posted).await.unwrap();
+        assert_eq!(updated, posted);
+    }
+
+    #[test]
+    fn formats_single_markdown_link() {
+        let input = "See [NAIS](https://nais.io) for more info";
+        let expected = "See <https://nais.io|NAIS> for more info";
+        assert_eq!(format_slack_post(input), expected);
+    }
+
+    #[test]
+    fn formats_multiple_markdown_links() {
         let input = "[One](https://one.example) and [Two](https://two.example)";
         let expected = "<https://one.example|One> and <https://two.example|Two>";
         assert_eq!(format_slack_post(input), expected);
@@ -165,4 +2309,381 @@ mod tests {
         let input = "No links here, just text.";
         assert_eq!(format_slack_post(input), input);
     }
+
+    #[test]
+    fn converts_common_markdown() {
+        let cases = [
+            ("**bold** text", "*bold* text"),
+            ("__bold__ text", "*bold* text"),
+            ("_italic_ stays", "_italic_ stays"),
+            ("# Heading", "*Heading*"),
+            ("### Sub heading ###", "*Sub heading*"),
+            ("## **Bold** heading", "*Bold heading*"),
+            ("- first\n- second", "• first\n• second"),
+            ("* star bullet", "• star bullet"),
+            ("  + nested", "  • nested"),
+            ("Use `**not bold**` here", "Use `**not bold**` here"),
+            (
+                "```\n# not a heading\n- [x](y)\n```",
+                "```\n# not a heading\n- [x](y)\n```",
+            ),
+            (
+                "[**bold link**](https://nais.io)",
+                "<https://nais.io|*bold link*>",
+            ),
+            (
+                "**[link](https://nais.io) in bold**",
+                "*<https://nais.io|link> in bold*",
+            ),
+            ("- [item](https://nais.io)", "• <https://nais.io|item>"),
+            ("a lone ` backtick **x**", "a lone ` backtick *x*"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(format_slack_post(input), expected, "input: {input:?}");
+        }
+    }
+
+    const HTML_CONTENT: &str = "<p>We've moved the <strong>deploy</strong> docs to\n  <a href=\"https://doc.nais.io/deploy\">doc.nais.io</a>.</p>\n<p><img src=\"https://nais.io/deploy.png\" alt=\"Deploy\"></p>\n<ul>\n  <li>Run <code>nais deploy</code></li>\n  <li>Check &amp; wait</li>\n</ul>";
+
+    #[test]
+    fn html_content_is_converted_to_mrkdwn() {
+        assert_eq!(
+            format_content(HTML_CONTENT, ContentFormat::Html),
+            "We've moved the *deploy* docs to <https://doc.nais.io/deploy|doc.nais.io>.\n\n• Run `nais deploy`\n• Check & wait"
+        );
+    }
+
+    #[test]
+    fn html_headings_and_code_blocks_keep_their_shape() {
+        let html = "<h2>Breaking <em>change</em></h2><pre><code>nais  deploy\n  --wait</code></pre><script>track()</script><p>Done<br>Thanks</p>";
+
+        assert_eq!(
+            format_content(html, ContentFormat::Html),
+            "*Breaking _change_*\n\n```\nnais  deploy\n  --wait\n```\n\nDone\nThanks"
+        );
+    }
+
+    #[test]
+    fn markdown_content_leaves_html_alone() {
+        let formatted = format_content(HTML_CONTENT, ContentFormat::Markdown);
+
+        assert!(formatted.contains("<strong>deploy</strong>"));
+        assert!(formatted.contains("<img src="));
+    }
+
+    #[test]
+    fn raw_content_is_sent_as_it_is() {
+        assert_eq!(
+            format_content(HTML_CONTENT, ContentFormat::Raw),
+            HTML_CONTENT
+        );
+        assert_eq!(
+            format_content("**bold** [link](https://nais.io)", ContentFormat::Raw),
+            "**bold** [link](https://nais.io)"
+        );
+    }
+
+    #[test]
+    fn split_text_keeps_short_text_whole() {
+        assert_eq!(split_text("one\ntwo", 100), vec!["one\ntwo"]);
+    }
+
+    #[test]
+    fn split_text_breaks_between_lines() {
+        assert_eq!(
+            split_text("aaaa\nbbbb\ncccc", 9),
+            vec!["aaaa\nbbbb", "cccc"]
+        );
+    }
+
+    #[test]
+    fn split_text_breaks_overlong_lines() {
+        assert_eq!(
+            split_text("abcdefghij\nk", 4),
+            vec!["abcd", "efgh", "ij\nk"]
+        );
+    }
+
+    #[test]
+    fn split_text_does_not_cut_through_links_or_code_fences() {
+        assert_eq!(
+            split_text("see <https://x.io|docs> and more", 20),
+            vec!["see ", "<https://x.io|docs> ", "and more"]
+        );
+        assert_eq!(
+            split_text("intro ```code here``` done", 16),
+            vec!["intro ", "```code here``` ", "done"]
+        );
+    }
+
+    #[test]
+    fn truncating_backs_off_a_link_on_the_boundary() {
+        let text = format!("{} <https://nais.io/log|the log> and more", "x".repeat(20));
+        let truncated = truncate_text(&text, "https://nais.io/post", 60);
+
+        assert_eq!(
+            truncated,
+            format!("{} … <https://nais.io/post|read more>", "x".repeat(20))
+        );
+    }
+
+    #[test]
+    fn long_post_is_split_into_sections() {
+        let paragraph = "x".repeat(999);
+        let content = vec![paragraph; 5].join("\n");
+        let post = Post {
+            content,
+            ..sample_post()
+        };
+
+        let blocks = message_blocks(&post, ContentFormat::Markdown);
+
+        assert!(matches!(blocks[0], Block::Header { ref text } if text.text == "Test Post"));
+        let sections: Vec<&str> = blocks[1..]
+            .iter()
+            .map(|block| match block {
+                Block::Section { text } => text.text.as_str(),
+                Block::Header { .. } => panic!("only the first block is a header"),
+            })
+            .collect();
+        assert_eq!(sections.len(), 2);
+        assert!(
+            sections
+                .iter()
+                .all(|s| s.chars().count() <= SECTION_TEXT_LIMIT)
+        );
+        assert_eq!(
+            sections.join("\n"),
+            format!("<{}>\n{}", post.link, post.content)
+        );
+    }
+
+    fn long_post() -> Post {
+        let line = format!("{}\n", "y".repeat(99));
+        Post {
+            content: line.repeat(50),
+            ..sample_post()
+        }
+    }
+
+    #[test]
+    fn short_post_is_sent_whole() {
+        assert_eq!(
+            message_texts(
+                &sample_post(),
+                ContentFormat::Markdown,
+                LongPostMode::Thread,
+                MESSAGE_TEXT_LIMIT
+            )
+            .len(),
+            1
+        );
+        assert_eq!(
+            message_texts(
+                &sample_post(),
+                ContentFormat::Markdown,
+                LongPostMode::Truncate,
+                MESSAGE_TEXT_LIMIT
+            )
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn long_post_is_truncated_with_link() {
+        let post = long_post();
+        let texts = message_texts(
+            &post,
+            ContentFormat::Markdown,
+            LongPostMode::Truncate,
+            MESSAGE_TEXT_LIMIT,
+        );
+
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].chars().count(), MESSAGE_TEXT_LIMIT);
+        assert!(texts[0].ends_with(&format!("… <{}|read more>", post.link)));
+    }
+
+    #[test]
+    fn long_post_is_chunked_for_thread() {
+        let post = long_post();
+        let texts = message_texts(
+            &post,
+            ContentFormat::Markdown,
+            LongPostMode::Thread,
+            MESSAGE_TEXT_LIMIT,
+        );
+
+        // The header line and 29 content lines of 100 characters fill the
+        // first message; the other 21 lines and the trailing newline follow.
+        assert_eq!(texts.len(), 2);
+        assert!(texts[0].starts_with("<https://nais.io/log#test-post|Test Post>\n"));
+        assert_eq!(texts[0].lines().count(), 30);
+        assert_eq!(texts[1].lines().count(), 21);
+        assert!(
+            texts
+                .iter()
+                .all(|t| t.chars().count() <= MESSAGE_TEXT_LIMIT)
+        );
+        assert_eq!(
+            texts.join("\n"),
+            super::message_text(&post, ContentFormat::Markdown)
+        );
+    }
+
+    fn branded(config: SlackConfig) -> SlackNotifier {
+        SlackNotifier::new(
+            SlackConfig {
+                message_prefix: ":nais: *NAIS Log*".to_string(),
+                message_footer: "<https://nais.io/settings|Manage subscriptions>".to_string(),
+                ..config
+            },
+            reqwest::Client::new(),
+            no_rate_limit(),
+        )
+    }
+
+    #[test]
+    fn prefix_and_footer_wrap_posts_and_updates_once() {
+        let client = branded(slack_config(1));
+        let post = Post {
+            content: "Read **this** and [that](https://nais.io)".to_string(),
+            ..sample_post()
+        };
+
+        for ts in ["", "1700000000.000100"] {
+            let (message, replies) = client.messages(&post, "C0000000000", ts);
+
+            assert!(replies.is_empty());
+            assert_eq!(
+                message.text,
+                ":nais: *NAIS Log*\n<https://nais.io/log#test-post|Test Post>\nRead *this* and <https://nais.io|that>\n<https://nais.io/settings|Manage subscriptions>"
+            );
+            assert_eq!(message.text.matches("*NAIS Log*").count(), 1);
+            assert_eq!(message.text.matches("Manage subscriptions").count(), 1);
+        }
+    }
+
+    #[test]
+    fn threaded_post_has_prefix_first_and_footer_last() {
+        let client = branded(SlackConfig {
+            long_posts: LongPostMode::Thread,
+            ..slack_config(1)
+        });
+
+        let (message, replies) = client.messages(&long_post(), "C0000000000", "");
+
+        assert!(message.text.starts_with(":nais: *NAIS Log*\n"));
+        assert!(!message.text.contains("Manage subscriptions"));
+        let last = replies.last().unwrap();
+        assert!(
+            last.text
+                .ends_with("\n<https://nais.io/settings|Manage subscriptions>")
+        );
+        assert!(!last.text.contains("NAIS Log"));
+        assert!(
+            std::iter::once(&message)
+                .chain(&replies)
+                .all(|m| m.text.chars().count() <= MESSAGE_TEXT_LIMIT)
+        );
+    }
+
+    #[test]
+    fn digest_links_every_post() {
+        let first = sample_post();
+        let second = Post {
+            title: "Second".to_string(),
+            link: "https://nais.io/log#second".to_string(),
+            ..sample_post()
+        };
+
+        let text = digest_text(&[&first, &second], SlackLayout::Full, ":nais:", "");
+
+        assert_eq!(
+            text,
+            format!(
+                ":nais:\n*2 new posts*\n• <{}|{}>\n• <https://nais.io/log#second|Second>",
+                first.link, first.title
+            )
+        );
+    }
+
+    #[test]
+    fn long_digest_says_how_many_are_left_out() {
+        let post = long_post();
+        let posts: Vec<&Post> = std::iter::repeat_n(&post, 200).collect();
+
+        let text = digest_text(&posts, SlackLayout::Full, "", "");
+
+        assert!(text.chars().count() <= MESSAGE_TEXT_LIMIT);
+        let listed = text.matches("\n• ").count();
+        assert!(listed < 200);
+        assert!(text.ends_with(&format!("\n…and {} more", 200 - listed)));
+    }
+
+    #[test]
+    fn compact_layout_only_links_the_title() {
+        let client = branded(SlackConfig {
+            layout: SlackLayout::Compact,
+            use_blocks: true,
+            ..slack_config(1)
+        });
+        let post = sample_post();
+
+        let (message, replies) = client.messages(&post, "C0000000000", "");
+
+        assert_eq!(
+            message.text,
+            format!(
+                ":nais: *NAIS Log*\n• <{}|{}>\n<https://nais.io/settings|Manage subscriptions>",
+                post.link, post.title
+            )
+        );
+        assert!(!message.text.contains(&post.content));
+        assert!(message.blocks.is_empty());
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn compact_digest_leaves_out_the_heading() {
+        let first = sample_post();
+        let second = Post {
+            title: "Second".to_string(),
+            link: "https://nais.io/log#second".to_string(),
+            ..sample_post()
+        };
+
+        let text = digest_text(&[&first, &second], SlackLayout::Compact, "", "");
+
+        assert_eq!(
+            text,
+            format!(
+                "• <{}|{}>\n• <https://nais.io/log#second|Second>",
+                first.link, first.title
+            )
+        );
+    }
+
+    #[test]
+    fn blocks_get_prefix_and_footer_sections() {
+        let client = branded(SlackConfig {
+            use_blocks: true,
+            ..slack_config(1)
+        });
+
+        let (message, _) = client.messages(&sample_post(), "C0000000000", "");
+
+        let section = |block: &Block| match block {
+            Block::Section { text } => text.text.clone(),
+            Block::Header { .. } => panic!("expected a section"),
+        };
+        assert_eq!(section(&message.blocks[0]), ":nais: *NAIS Log*");
+        assert!(matches!(message.blocks[1], Block::Header { .. }));
+        assert_eq!(
+            section(message.blocks.last().unwrap()),
+            "<https://nais.io/settings|Manage subscriptions>"
+        );
+    }
 }
@@ -0,0 +1,258 @@
+use crate::{config::AppState, error::ApiError};
+use axum::{
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+const SIGNATURE: &str = "x-slack-signature";
+const TIMESTAMP: &str = "x-slack-request-timestamp";
+
+/// Oldest request accepted, so a captured one can't be replayed later.
+const MAX_AGE_SECONDS: i64 = 5 * 60;
+
+/// Largest body read to check the signature; slash commands send far less.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// SHA-256 works on blocks of this many bytes, which HMAC pads its key to.
+const BLOCK_SIZE: usize = 64;
+
+/// Lets through only requests signed by Slack with `SLACK_SIGNING_SECRET`,
+/// answering `401` to unsigned, tampered or stale ones. Every request is
+/// turned away when the secret isn't set.
+pub async fn verify(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(secret) = state.slack_signing_secret.as_deref() else {
+        return ApiError::new(
+            StatusCode::FORBIDDEN,
+            "slack_commands_disabled",
+            "Slack commands need SLACK_SIGNING_SECRET",
+        )
+        .into_response();
+    };
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, MAX_BODY_BYTES).await else {
+        return ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "slack_request_too_large",
+            "Slack request body is too large",
+        )
+        .into_response();
+    };
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+    if let Err(reason) = check(
+        secret,
+        header(TIMESTAMP),
+        header(SIGNATURE),
+        &body,
+        state.clock.now().timestamp(),
+    ) {
+        warn!(reason, "Rejecting unverified Slack request");
+        return ApiError::new(StatusCode::UNAUTHORIZED, "slack_signature_invalid", reason)
+            .into_response();
+    }
+
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// Checks `signature` against the one Slack makes of `timestamp` and `body`,
+/// as described in https://api.slack.com/authentication/verifying-requests-from-slack.
+fn check(
+    secret: &str,
+    timestamp: &str,
+    signature: &str,
+    body: &[u8],
+    now: i64,
+) -> Result<(), &'static str> {
+    let sent: i64 = timestamp
+        .parse()
+        .map_err(|_| "Missing or invalid X-Slack-Request-Timestamp")?;
+    if (now - sent).abs() > MAX_AGE_SECONDS {
+        return Err("Slack request is too old");
+    }
+
+    let mut base = format!("v0:{timestamp}:").into_bytes();
+    base.extend_from_slice(body);
+    let expected: String = hmac_sha256(secret.as_bytes(), &base)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    if crate::tokens_match(signature, &format!("v0={expected}")) {
+        Ok(())
+    } else {
+        Err("Slack signature doesn't match")
+    }
+}
+
+/// HMAC-SHA256 as in RFC 2104.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner = Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, hmac_sha256, verify};
+    use crate::{
+        clock::FixedClock,
+        config::{AppConfig, AppState},
+    };
+    use axum::{Router, http::StatusCode, middleware, routing::post};
+    use chrono::DateTime;
+
+    const SECRET: &str = "8f742231b10e8888abcd99yyyzzz85a5";
+
+    /// The example request from Slack's documentation.
+    const BODY: &str = "token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+    const TIMESTAMP: i64 = 1531420618;
+    const SIGNATURE: &str = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn computes_hmac_sha256() {
+        // Test case 2 from RFC 4231.
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // Test case 6, with a key longer than a block.
+        assert_eq!(
+            hex(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn accepts_slacks_example_request() {
+        let timestamp = TIMESTAMP.to_string();
+
+        assert_eq!(
+            check(SECRET, &timestamp, SIGNATURE, BODY.as_bytes(), TIMESTAMP),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_and_stale_requests() {
+        let timestamp = TIMESTAMP.to_string();
+        let tampered = BODY.replace("text=", "text=reset");
+
+        assert_eq!(
+            check(
+                SECRET,
+                &timestamp,
+                SIGNATURE,
+                tampered.as_bytes(),
+                TIMESTAMP
+            ),
+            Err("Slack signature doesn't match")
+        );
+        assert_eq!(
+            check(
+                SECRET,
+                &timestamp,
+                SIGNATURE,
+                BODY.as_bytes(),
+                TIMESTAMP + 6 * 60
+            ),
+            Err("Slack request is too old")
+        );
+        assert_eq!(
+            check(SECRET, "", SIGNATURE, BODY.as_bytes(), TIMESTAMP),
+            Err("Missing or invalid X-Slack-Request-Timestamp")
+        );
+    }
+
+    /// Serves the check at `now` seconds since the epoch.
+    async fn serve(secret: Option<&str>, now: i64) -> String {
+        let state = AppState::new(AppConfig::DryRun)
+            .with_slack_signing_secret(secret.map(str::to_string))
+            .with_clock(FixedClock(DateTime::from_timestamp(now, 0).unwrap()));
+        let app = Router::new().route(
+            "/slack/command",
+            post(|body: String| async move { body })
+                .layer(middleware::from_fn_with_state(state, verify)),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}/slack/command")
+    }
+
+    async fn send(url: String, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(url)
+            .header("X-Slack-Request-Timestamp", TIMESTAMP.to_string())
+            .header("X-Slack-Signature", SIGNATURE)
+            .body(body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn passes_signed_requests_on_with_their_body() {
+        let url = serve(Some(SECRET), TIMESTAMP + 60).await;
+
+        let response = send(url, BODY.to_string()).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), BODY);
+    }
+
+    #[tokio::test]
+    async fn turns_away_tampered_requests() {
+        let url = serve(Some(SECRET), TIMESTAMP).await;
+
+        let response = send(url, BODY.replace("roadrunner", "coyote")).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn turns_away_replayed_requests() {
+        let url = serve(Some(SECRET), TIMESTAMP + 5 * 60 + 1).await;
+
+        let response = send(url, BODY.to_string()).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn is_off_without_a_signing_secret() {
+        let url = serve(None, TIMESTAMP).await;
+
+        let response = reqwest::Client::new().post(url).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{
+    config::SlackWebhookConfig,
+    notifier::{MessageIds, Notifier, single_message},
+    rss::Post,
+    slack::single_text,
+};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::io::Error;
+
+#[derive(Debug, Serialize)]
+struct WebhookMessage {
+    text: String,
+}
+
+/// Posts to a Slack channel through an incoming webhook, for workspaces that
+/// don't hand out a bot token with `chat:write`.
+///
+/// Incoming webhooks never hand back a message id, so posts can't be edited
+/// afterwards; an update is announced as a new "Updated:" message instead.
+/// Long posts are cut short, as there is no thread to continue them in.
+#[derive(Debug, Clone)]
+pub struct SlackWebhookNotifier {
+    config: SlackWebhookConfig,
+    client: reqwest::Client,
+}
+
+impl SlackWebhookNotifier {
+    pub fn new(config: SlackWebhookConfig, client: reqwest::Client) -> Self {
+        Self { config, client }
+    }
+
+    fn message(&self, post: &Post) -> WebhookMessage {
+        WebhookMessage {
+            text: single_text(
+                post,
+                self.config.content_format,
+                self.config.layout,
+                &self.config.message_prefix,
+                &self.config.message_footer,
+            ),
+        }
+    }
+
+    async fn send(&self, payload: &WebhookMessage) -> Result<(), Error> {
+        let response = self
+            .client
+            .post(&self.config.webhook_url)
+            .json(payload)
+            .send()
+            .await
+            .map_err(|e| Error::other(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        // Webhooks explain a rejection in a plain text body, like `invalid_token`.
+        let reason = response.text().await.unwrap_or_default();
+        Err(Error::other(format!(
+            "Slack webhook answered with {status}: {reason}"
+        )))
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackWebhookNotifier {
+    async fn post(&self, post: &Post) -> Result<MessageIds, Error> {
+        self.send(&self.message(post)).await?;
+        Ok(single_message(String::new()))
+    }
+
+    async fn update(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        let mut message = self.message(post);
+        message.text = format!("Updated: {}", message.text);
+        self.send(&message).await?;
+        Ok(ids.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SlackWebhookNotifier;
+    use crate::{
+        config::{ContentFormat, SlackLayout, SlackWebhookConfig},
+        notifier::{Notifier, single_message},
+        rss::Post,
+    };
+    use axum::{Json, Router, http::StatusCode, routing::post};
+    use chrono::{TimeZone, Utc};
+    use std::sync::{Arc, Mutex};
+
+    fn sample_post() -> Post {
+        Post {
+            title: "Test Post".to_string(),
+            link: "https://nais.io/log#test-post".to_string(),
+            pub_date: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            content: "This is **content**".to_string(),
+            categories: Vec::new(),
+            guid: None,
+        }
+    }
+
+    /// Serves an incoming webhook locally, recording every payload sent to it.
+    async fn webhook() -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let payloads = Arc::new(Mutex::new(Vec::new()));
+        let recorded = payloads.clone();
+        let app = Router::new().route(
+            "/services/T000/B000/XXX",
+            post(move |Json(payload): Json<serde_json::Value>| {
+                let recorded = recorded.clone();
+                async move {
+                    recorded.lock().unwrap().push(payload);
+                    (StatusCode::OK, "ok")
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}/services/T000/B000/XXX"), payloads)
+    }
+
+    fn notifier(webhook_url: String) -> SlackWebhookNotifier {
+        SlackWebhookNotifier::new(
+            SlackWebhookConfig {
+                webhook_url,
+                message_prefix: String::new(),
+                message_footer: String::new(),
+                content_format: ContentFormat::Markdown,
+                layout: SlackLayout::Full,
+            },
+            reqwest::Client::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn posts_the_rendered_message() {
+        let (url, payloads) = webhook().await;
+
+        notifier(url).post(&sample_post()).await.unwrap();
+
+        assert_eq!(
+            *payloads.lock().unwrap(),
+            vec![serde_json::json!({
+                "text": "<https://nais.io/log#test-post|Test Post>\nThis is *content*",
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_posts_a_new_message() {
+        let (url, payloads) = webhook().await;
+        let ids = single_message(String::new());
+
+        let updated = notifier(url).update(&sample_post(), &ids).await.unwrap();
+
+        assert_eq!(updated, ids);
+        let payloads = payloads.lock().unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(
+            payloads[0]["text"],
+            "Updated: <https://nais.io/log#test-post|Test Post>\nThis is *content*"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_payload_is_an_error() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/hook",
+            post(|| async { (StatusCode::FORBIDDEN, "invalid_token") }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let err = notifier(format!("http://{addr}/hook"))
+            .post(&sample_post())
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("invalid_token"));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{
+    config::TeamsConfig,
+    markdown::{map_lines_outside_fences, regex},
+    notifier::{MessageIds, Notifier, single_message},
+    rss::Post,
+};
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Serialize;
+use std::{io::Error, sync::OnceLock};
+
+#[derive(Debug, Serialize)]
+struct WebhookMessage {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Attachment {
+    content_type: &'static str,
+    content: AdaptiveCard,
+}
+
+#[derive(Debug, Serialize)]
+struct AdaptiveCard {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    version: &'static str,
+    body: Vec<CardElement>,
+    actions: Vec<CardAction>,
+}
+
+/// The subset of Adaptive Card elements we render posts into.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+enum CardElement {
+    TextBlock {
+        text: String,
+        wrap: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        weight: Option<&'static str>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+enum CardAction {
+    #[serde(rename = "Action.OpenUrl")]
+    OpenUrl { title: &'static str, url: String },
+}
+
+static RE_HEADING: OnceLock<Regex> = OnceLock::new();
+
+/// Adapts the CommonMark used in nais.io posts to the markdown subset a
+/// `TextBlock` renders, which has no headings.
+pub(crate) fn format_teams_post(org: &str) -> String {
+    map_lines_outside_fences(org, |line| {
+        regex(&RE_HEADING, r"^#{1,6}\s+(.*?)\s*#*$")
+            .replace(line, "**$1**")
+            .to_string()
+    })
+}
+
+fn card(title: String, post: &Post) -> WebhookMessage {
+    WebhookMessage {
+        kind: "message",
+        attachments: vec![Attachment {
+            content_type: "application/vnd.microsoft.card.adaptive",
+            content: AdaptiveCard {
+                schema: "http://adaptivecards.io/schemas/adaptive-card.json",
+                kind: "AdaptiveCard",
+                version: "1.4",
+                body: vec![
+                    CardElement::TextBlock {
+                        text: title,
+                        wrap: true,
+                        size: Some("Large"),
+                        weight: Some("Bolder"),
+                    },
+                    CardElement::TextBlock {
+                        text: format_teams_post(&post.content),
+                        wrap: true,
+                        size: None,
+                        weight: None,
+                    },
+                ],
+                actions: vec![CardAction::OpenUrl {
+                    title: "Read on nais.io",
+                    url: post.link.clone(),
+                }],
+            },
+        }],
+    }
+}
+
+/// Posts Adaptive Cards to a Teams channel through an incoming webhook.
+///
+/// Incoming webhooks never hand back a message id, so posts can't be edited
+/// afterwards; an update is announced as a new card instead.
+#[derive(Debug, Clone)]
+pub struct TeamsNotifier {
+    config: TeamsConfig,
+    client: reqwest::Client,
+}
+
+impl TeamsNotifier {
+    pub fn new(config: TeamsConfig, client: reqwest::Client) -> Self {
+        Self { config, client }
+    }
+
+    async fn send(&self, payload: &WebhookMessage) -> Result<(), Error> {
+        let response = self
+            .client
+            .post(&self.config.webhook_url)
+            .json(payload)
+            .send()
+            .await
+            .map_err(|e| Error::other(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(Error::other(format!("Teams answered with {status}")))
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for TeamsNotifier {
+    async fn post(&self, post: &Post) -> Result<MessageIds, Error> {
+        self.send(&card(post.title.clone(), post)).await?;
+        Ok(single_message(String::new()))
+    }
+
+    async fn update(&self, post: &Post, ids: &MessageIds) -> Result<MessageIds, Error> {
+        self.send(&card(format!("Updated: {}", post.title), post))
+            .await?;
+        Ok(ids.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{card, format_teams_post};
+    use crate::rss::Post;
+    use chrono::{TimeZone, Utc};
+
+    fn sample_post() -> Post {
+        Post {
+            title: "Test Post".to_string(),
+            link: "https://nais.io/log#test-post".to_string(),
+            pub_date: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            content: "## Intro\nThis is **content** with a [link](https://example.com)."
+                .to_string(),
+            categories: Vec::new(),
+            guid: None,
+        }
+    }
+
+    #[test]
+    fn renders_adaptive_card() {
+        let post = sample_post();
+        let payload = serde_json::to_value(card(post.title.clone(), &post)).unwrap();
+
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "type": "message",
+                "attachments": [{
+                    "contentType": "application/vnd.microsoft.card.adaptive",
+                    "content": {
+                        "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                        "type": "AdaptiveCard",
+                        "version": "1.4",
+                        "body": [
+                            {
+                                "type": "TextBlock",
+                                "text": "Test Post",
+                                "wrap": true,
+                                "size": "Large",
+                                "weight": "Bolder",
+                            },
+                            {
+                                "type": "TextBlock",
+                                "text": "**Intro**\nThis is **content** with a [link](https://example.com).",
+                                "wrap": true,
+                            },
+                        ],
+                        "actions": [{
+                            "type": "Action.OpenUrl",
+                            "title": "Read on nais.io",
+                            "url": "https://nais.io/log#test-post",
+                        }],
+                    },
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn leaves_fenced_headings_alone() {
+        let input = "```\n# comment\n```\n# Heading";
+        assert_eq!(format_teams_post(input), "```\n# comment\n```\n**Heading**");
+    }
+}